@@ -1,42 +1,149 @@
-use crate::token::Token;
+use crate::token::{Symbol, Token};
+use std::fmt;
+use std::rc::Rc;
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Program {
     pub statements: Vec<Statement>,
+    // The included file each statement was spliced in from, by index, or
+    // None for the main source. Empty when nothing was included.
+    pub files: Vec<Option<Symbol>>,
 }
 
+// Every statement but a block records the source line it starts on
 #[derive(Debug, PartialEq, Clone)]
 pub enum Statement {
     Let {
-        name: String,
+        ty: Type,
+        constant: bool, // Declared `const`, so it can't be assigned again
+        name: Symbol,
         value: Expression,
+        slot: Option<usize>, // Set by the resolver for locals
+        line: usize,
+    },
+    // int a = 1, b = 2;
+    MultiLet {
+        ty: Type,
+        constant: bool,
+        declarators: Vec<(Symbol, Expression)>,
+        slots: Option<Vec<usize>>, // One per declarator, set by the resolver for locals
+        line: usize,
+    },
+    Return {
+        value: Expression,
+        line: usize,
+    },
+    Expression {
+        expr: Expression,
+        line: usize,
     },
-    Return(Expression),
-    Expression(Expression),
     Block(Vec<Statement>),
     If {
         condition: Expression,
-        consequence: Box<Statement>,         // Should be a Block
-        alternative: Option<Box<Statement>>, // Should be a Block
+        consequence: Box<Statement>,         // Block or single statement
+        alternative: Option<Box<Statement>>, // Block, single statement or else-if
+        line: usize,
     },
     While {
         condition: Expression,
         body: Box<Statement>,
+        line: usize,
+    },
+    DoWhile {
+        body: Box<Statement>,
+        condition: Expression,
+        line: usize,
+    },
+    Switch {
+        subject: Expression,
+        cases: Vec<(Expression, Vec<Statement>)>,
+        default: Option<Vec<Statement>>,
+        line: usize,
+    },
+    For {
+        init: Option<Box<Statement>>, // Let or Expression
+        condition: Option<Expression>,
+        post: Option<Expression>,
+        body: Box<Statement>, // Block or single statement
+        line: usize,
     },
     Function {
-        name: String,
-        params: Vec<String>,
-        body: Box<Statement>, // Should be a Block
+        return_type: Type,
+        name: Symbol,
+        // Shared with the function values the definition creates, so
+        // defining a function never copies its AST
+        params: Rc<Vec<(Type, Symbol)>>,
+        body: Rc<Statement>, // Should be a Block
+        slot: Option<usize>, // Set by the resolver for functions defined in a local scope
+        line: usize,
+    },
+    // int f(int x); declares a function defined elsewhere and does nothing
+    Prototype {
+        return_type: Type,
+        name: Symbol,
+        params: Vec<Param>,
+        line: usize,
+    },
+    // #include "path", replaced by the file's statements before evaluation
+    Include {
+        path: String,
+        line: usize,
     },
 }
 
+// A prototype's parameter, whose name may be left out as in `int f(int);`
+pub type Param = (Type, Option<Symbol>);
+
+// Declared types are advisory: values are still dynamically typed at runtime
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Type {
+    Int,
+    Char,
+    Void, // Only valid as a return type
+}
+
+impl Type {
+    pub fn from_token(token: &Token) -> Option<Type> {
+        match token {
+            Token::Int => Some(Type::Int),
+            Token::Char => Some(Type::Char),
+            Token::Void => Some(Type::Void),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Char => write!(f, "char"),
+            Type::Void => write!(f, "void"),
+        }
+    }
+}
+
+// Where the resolver placed a local variable: `index` among the variables of
+// the scope `depth` scopes out from the one the code runs in
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Slot {
+    pub depth: usize,
+    pub index: usize,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Expression {
-    Identifier(String),
+    Identifier(Symbol),
+    // An identifier the resolver found declared in a local scope
+    Local {
+        name: Symbol,
+        slot: Slot,
+    },
     Integer(i64),
+    Float(f64),
     String(String),
-    #[allow(dead_code)]
-    Boolean(bool), // For true/false usually, strictly speaking lexer didn't have bool literals yet, maybe will add later
+    Character(char),
+    Boolean(bool),
     Prefix {
         operator: Token,
         right: Box<Expression>,
@@ -47,7 +154,334 @@ pub enum Expression {
         right: Box<Expression>,
     },
     Call {
-        function: Box<Expression>, // Identifier
+        function: Box<Expression>, // Any expression evaluating to a function
         arguments: Vec<Expression>,
     },
+    Assign {
+        name: Symbol,
+        value: Box<Expression>,
+        slot: Option<Slot>, // Set by the resolver for locals
+    },
+    Ternary {
+        condition: Box<Expression>,
+        then: Box<Expression>,
+        otherwise: Box<Expression>,
+    },
+    // `++x`, `x++`, `--x` or `x--`
+    Update {
+        name: Symbol,
+        operator: Token, // Increment or Decrement
+        prefix: bool,
+        slot: Option<Slot>, // Set by the resolver for locals
+    },
+    ArrayLiteral(Vec<Expression>),
+    Index {
+        left: Box<Expression>,
+        index: Box<Expression>,
+    },
+    // `a[i] = v`, or `a[i] += v` and the like with `operator` set to `+`
+    IndexAssign {
+        left: Box<Expression>,
+        index: Box<Expression>,
+        operator: Option<Token>,
+        value: Box<Expression>,
+    },
+}
+
+const INDENT: &str = "    ";
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for stmt in &self.statements {
+            writeln!(f, "{}", stmt.pretty(0))?;
+        }
+        Ok(())
+    }
+}
+
+impl Statement {
+    // Renders the statement as indented, source-like text. Nested lines are
+    // indented relative to `indent`; the first line is not.
+    pub fn pretty(&self, indent: usize) -> String {
+        match self {
+            Statement::Block(stmts) => {
+                let mut out = "{\n".to_string();
+                for stmt in stmts {
+                    out.push_str(&INDENT.repeat(indent + 1));
+                    out.push_str(&stmt.pretty(indent + 1));
+                    out.push('\n');
+                }
+                out.push_str(&INDENT.repeat(indent));
+                out.push('}');
+                out
+            }
+            Statement::If {
+                consequence,
+                alternative,
+                ..
+            } => {
+                let mut out = format!("{} {}", self.summary(), consequence.pretty(indent));
+                if let Some(alt) = alternative {
+                    out.push_str(&format!(" Else {}", alt.pretty(indent)));
+                }
+                out
+            }
+            Statement::While { body, .. } => format!("{} {}", self.summary(), body.pretty(indent)),
+            Statement::DoWhile {
+                body, condition, ..
+            } => {
+                format!("Do {} While ({});", body.pretty(indent), condition)
+            }
+            Statement::Switch { cases, default, .. } => {
+                let labels = cases
+                    .iter()
+                    .map(|(label, body)| (format!("Case {}:", label), body))
+                    .chain(default.iter().map(|body| ("Default:".to_string(), body)));
+
+                let mut out = format!("{} {{\n", self.summary());
+                for (label, body) in labels {
+                    out.push_str(&INDENT.repeat(indent + 1));
+                    out.push_str(&label);
+                    out.push('\n');
+                    for stmt in body {
+                        out.push_str(&INDENT.repeat(indent + 2));
+                        out.push_str(&stmt.pretty(indent + 2));
+                        out.push('\n');
+                    }
+                }
+                out.push_str(&INDENT.repeat(indent));
+                out.push('}');
+                out
+            }
+            Statement::For { body, .. } => format!("{} {}", self.summary(), body.pretty(indent)),
+            Statement::Function { body, .. } => {
+                format!("{} {}", self.summary(), body.pretty(indent))
+            }
+            _ => self.summary(),
+        }
+    }
+
+    // The statement on one line: compound statements without their bodies
+    pub fn summary(&self) -> String {
+        match self {
+            Statement::Block(_) => "{ ... }".to_string(),
+            Statement::If { condition, .. } => format!("If ({})", condition),
+            Statement::While { condition, .. } => format!("While ({})", condition),
+            Statement::DoWhile { condition, .. } => format!("Do ... While ({});", condition),
+            Statement::Switch { subject, .. } => format!("Switch ({})", subject),
+            Statement::For {
+                init,
+                condition,
+                post,
+                ..
+            } => {
+                let init = init.as_ref().map(|s| s.header()).unwrap_or_default();
+                let condition = condition
+                    .as_ref()
+                    .map(|c| c.to_string())
+                    .unwrap_or_default();
+                let post = post.as_ref().map(|p| p.to_string()).unwrap_or_default();
+                format!("For ({}; {}; {})", init, condition, post)
+            }
+            Statement::Function {
+                return_type,
+                name,
+                params,
+                ..
+            } => {
+                let params: Vec<String> = params
+                    .iter()
+                    .map(|(ty, name)| format!("{} {}", ty, name))
+                    .collect();
+                format!("Function {} {}({})", return_type, name, params.join(", "))
+            }
+            Statement::Prototype {
+                return_type,
+                name,
+                params,
+                ..
+            } => {
+                let params: Vec<String> = params
+                    .iter()
+                    .map(|(ty, name)| match name {
+                        Some(name) => format!("{} {}", ty, name),
+                        None => ty.to_string(),
+                    })
+                    .collect();
+                format!("Prototype {} {}({});", return_type, name, params.join(", "))
+            }
+            Statement::Include { path, .. } => format!("#include {:?}", path),
+            _ => format!("{};", self.header()),
+        }
+    }
+
+    // The line the statement starts on; blocks have none of their own
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Statement::Block(_) => None,
+            Statement::Let { line, .. }
+            | Statement::MultiLet { line, .. }
+            | Statement::Return { line, .. }
+            | Statement::Expression { line, .. }
+            | Statement::If { line, .. }
+            | Statement::While { line, .. }
+            | Statement::DoWhile { line, .. }
+            | Statement::Switch { line, .. }
+            | Statement::For { line, .. }
+            | Statement::Function { line, .. }
+            | Statement::Prototype { line, .. }
+            | Statement::Include { line, .. } => Some(*line),
+        }
+    }
+
+    // Single-line statements without their trailing semicolon
+    fn header(&self) -> String {
+        match self {
+            Statement::Let {
+                ty,
+                constant,
+                name,
+                value,
+                ..
+            } => format!("Let {}{} {} = {}", const_prefix(*constant), ty, name, value),
+            Statement::MultiLet {
+                ty,
+                constant,
+                declarators,
+                ..
+            } => {
+                let parts: Vec<String> = declarators
+                    .iter()
+                    .map(|(name, value)| format!("{} = {}", name, value))
+                    .collect();
+                format!("Let {}{} {}", const_prefix(*constant), ty, parts.join(", "))
+            }
+            Statement::Return { value, .. } => format!("Return {}", value),
+            Statement::Expression { expr, .. } => expr.to_string(),
+            other => other.pretty(0),
+        }
+    }
+}
+
+fn const_prefix(constant: bool) -> &'static str {
+    if constant { "const " } else { "" }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.pretty(0))
+    }
+}
+
+// Operators are always parenthesized so precedence is unambiguous in dumps
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expression::Identifier(name) | Expression::Local { name, .. } => write!(f, "{}", name),
+            Expression::Integer(val) => write!(f, "{}", val),
+            Expression::Float(val) => write!(f, "{:?}", val),
+            Expression::String(val) => write!(f, "{:?}", val),
+            Expression::Character(val) => write!(f, "{:?}", val),
+            Expression::Boolean(val) => write!(f, "{}", val),
+            Expression::Prefix { operator, right } => {
+                write!(f, "({}{})", operator.literal(), right)
+            }
+            Expression::Infix {
+                left,
+                operator,
+                right,
+            } => write!(f, "({} {} {})", left, operator.literal(), right),
+            Expression::Call {
+                function,
+                arguments,
+            } => {
+                let args: Vec<String> = arguments.iter().map(|a| a.to_string()).collect();
+                write!(f, "{}({})", function, args.join(", "))
+            }
+            Expression::Assign { name, value, .. } => write!(f, "({} = {})", name, value),
+            Expression::Ternary {
+                condition,
+                then,
+                otherwise,
+            } => write!(f, "({} ? {} : {})", condition, then, otherwise),
+            Expression::Update {
+                name,
+                operator,
+                prefix: true,
+                ..
+            } => write!(f, "({}{})", operator.literal(), name),
+            Expression::Update { name, operator, .. } => {
+                write!(f, "({}{})", name, operator.literal())
+            }
+            Expression::ArrayLiteral(elements) => {
+                let elements: Vec<String> = elements.iter().map(|e| e.to_string()).collect();
+                write!(f, "[{}]", elements.join(", "))
+            }
+            Expression::Index { left, index } => write!(f, "({}[{}])", left, index),
+            Expression::IndexAssign {
+                left,
+                index,
+                operator,
+                value,
+            } => {
+                let operator = operator.as_ref().map(Token::literal).unwrap_or_default();
+                write!(f, "({}[{}] {}= {})", left, index, operator, value)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::Parser;
+    use crate::token::Lexer;
+
+    fn dump(input: &str) -> String {
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+        assert!(
+            parser.errors.is_empty(),
+            "parser errors: {:?}",
+            parser.errors
+        );
+        program.to_string()
+    }
+
+    #[test]
+    fn test_pretty_expressions() {
+        assert_eq!(dump("1 + 2 * 3;"), "(1 + (2 * 3));\n");
+        assert_eq!(dump("-a == !b;"), "((-a) == (!b));\n");
+        assert_eq!(
+            dump("x = f(1, \"a\\n\") && y;"),
+            "(x = (f(1, \"a\\n\") && y));\n"
+        );
+    }
+
+    #[test]
+    fn test_pretty_statements() {
+        let input =
+            "int main() { int x = 1 + 2; if (x > 1) { return x; } else { x = 0; } return x; }";
+        let expected = "\
+Function int main() {
+    Let int x = (1 + 2);
+    If ((x > 1)) {
+        Return x;
+    } Else {
+        (x = 0);
+    }
+    Return x;
+}
+";
+        assert_eq!(dump(input), expected);
+
+        assert_eq!(
+            dump("for (int i = 0; i < 3; i = i + 1) { }"),
+            "For (Let int i = 0; (i < 3); (i = (i + 1))) {\n}\n"
+        );
+        assert_eq!(dump("int a = 1, b;"), "Let int a = 1, b = 0;\n");
+        assert_eq!(
+            dump("int f(char c, int n) { char d = c; }"),
+            "Function int f(char c, int n) {\n    Let char d = c;\n}\n"
+        );
+    }
 }
@@ -0,0 +1,212 @@
+use crate::ast::Statement;
+use crate::env::{Environment, Object};
+use crate::stdlib::Output;
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::io::BufRead;
+use std::rc::Rc;
+
+const HELP: &str = "commands: step (s), next (n), continue (c), break <line> (b), \
+print <name> (p), vars, quit (q)";
+
+// When to pause next
+enum Mode {
+    Step,                  // Before the next statement, wherever it is
+    Next { depth: usize }, // Before the next statement not inside a deeper call
+    Continue,              // Only at breakpoints
+}
+
+// A line-based debugger driven by commands read from `input`. The interpreter
+// calls `pause` before each statement; the debugger decides whether to stop
+// there and prompt.
+pub struct Debugger {
+    input: Box<dyn BufRead>,
+    output: Output,
+    breakpoints: BTreeSet<usize>,
+    mode: Mode,
+}
+
+// What the program should do once the debugger lets it go
+#[derive(Debug, PartialEq)]
+pub enum Resume {
+    Run,
+    Quit,
+}
+
+impl Debugger {
+    // Stops before the first statement
+    pub fn new(input: Box<dyn BufRead>, output: Output) -> Self {
+        Debugger {
+            input,
+            output,
+            breakpoints: BTreeSet::new(),
+            mode: Mode::Step,
+        }
+    }
+
+    // Called before `stmt` runs at call depth `depth`, with `env` the scope it
+    // runs in, so `print` sees exactly the names the statement would
+    pub fn pause(
+        &mut self,
+        stmt: &Statement,
+        depth: usize,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Resume {
+        let Some(line) = stmt.line() else {
+            return Resume::Run;
+        };
+        let stop = match self.mode {
+            Mode::Step => true,
+            Mode::Next { depth: limit } => depth <= limit,
+            Mode::Continue => false,
+        };
+        if !stop && !self.breakpoints.contains(&line) {
+            return Resume::Run;
+        }
+
+        self.say(&format!("line {}: {}", line, stmt.summary()));
+        loop {
+            self.write("(debug) ");
+            let mut command = String::new();
+            match self.input.read_line(&mut command) {
+                Ok(0) | Err(_) => return Resume::Quit,
+                Ok(_) => {}
+            }
+            let mut words = command.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some("step" | "s"), None) => self.mode = Mode::Step,
+                (Some("next" | "n"), None) => self.mode = Mode::Next { depth },
+                (Some("continue" | "c"), None) => self.mode = Mode::Continue,
+                (Some("quit" | "q"), None) => return Resume::Quit,
+                (Some("break" | "b"), Some(line)) => {
+                    match line.parse() {
+                        Ok(line) => {
+                            self.breakpoints.insert(line);
+                            self.say(&format!("breakpoint at line {}", line));
+                        }
+                        Err(_) => self.say(&format!("not a line number: {}", line)),
+                    }
+                    continue;
+                }
+                (Some("print" | "p"), Some(name)) => {
+                    match env.borrow().get(name) {
+                        Some(value) => self.say(&format!("{} = {}", name, value.repr())),
+                        None => self.say(&format!("{} is not defined", name)),
+                    }
+                    continue;
+                }
+                (Some("vars"), None) => {
+                    for (name, value) in env.borrow().bindings() {
+                        // The stdlib would drown out the program's own globals
+                        if !matches!(value, Object::Builtin(_)) {
+                            self.say(&format!("{} = {}", name, value.repr()));
+                        }
+                    }
+                    continue;
+                }
+                _ => {
+                    self.say(HELP);
+                    continue;
+                }
+            }
+            return Resume::Run;
+        }
+    }
+
+    fn say(&self, line: &str) {
+        self.write(&format!("{}\n", line));
+    }
+
+    // Best-effort, like --trace: the debugger shouldn't stop the program
+    fn write(&self, text: &str) {
+        let mut output = self.output.borrow_mut();
+        let _ = output.write_all(text.as_bytes());
+        let _ = output.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::Parser;
+    use crate::token::Lexer;
+    use crate::{Interpreter, RuntimeError};
+
+    use super::*;
+
+    // Runs `input` under the debugger, feeding it `commands`, and returns
+    // everything printed: the program's output and the debugger's
+    fn debug(input: &str, commands: &str) -> (Result<Object, RuntimeError>, String) {
+        let program = Parser::new(Lexer::new(input)).parse_program();
+        let buffer = Rc::new(RefCell::new(Vec::new()));
+        let mut interpreter = Interpreter::with_output(buffer.clone());
+        let commands = std::io::Cursor::new(commands.to_string());
+        interpreter.set_debugger(Debugger::new(Box::new(commands), buffer.clone()));
+        let env = interpreter.global_environment();
+        let result = interpreter.eval_program(&program, env);
+        let printed = String::from_utf8(buffer.borrow().clone()).unwrap();
+        (result, printed)
+    }
+
+    const PROGRAM: &str = "int sq(int x) {
+    int y = x * x;
+    return y;
+}
+int a = 3;
+int b = sq(a);
+puts(b);";
+
+    #[test]
+    fn test_step_and_next() {
+        // `next` on line 6 runs the call to sq without stopping inside it
+        let (result, printed) = debug(PROGRAM, "s\ns\nnext\nstep\n");
+        assert_eq!(result, Ok(Object::Null));
+        assert_eq!(
+            printed,
+            "\
+line 1: Function int sq(int x)
+(debug) line 5: Let int a = 3;
+(debug) line 6: Let int b = sq(a);
+(debug) line 7: puts(b);
+(debug) 9
+"
+        );
+
+        // `step` goes into it
+        let (_, printed) = debug(PROGRAM, "s\ns\ns\nc\n");
+        assert!(
+            printed.contains("(debug) line 2: Let int y = (x * x);\n"),
+            "{}",
+            printed
+        );
+    }
+
+    #[test]
+    fn test_breakpoints_and_print() {
+        let (result, printed) = debug(PROGRAM, "break 3\nc\nprint y\np a\np missing\nvars\nc\n");
+        assert_eq!(result, Ok(Object::Null));
+        assert_eq!(
+            printed,
+            "\
+line 1: Function int sq(int x)
+(debug) breakpoint at line 3
+(debug) line 3: Return y;
+(debug) y = 9
+(debug) a = 3
+(debug) missing is not defined
+(debug) y = 9
+(debug) 9
+"
+        );
+    }
+
+    #[test]
+    fn test_quit_stops_the_program() {
+        let (result, printed) = debug(PROGRAM, "q\n");
+        assert_eq!(result, Err(RuntimeError::Exit(0)));
+        assert_eq!(printed, "line 1: Function int sq(int x)\n(debug) ");
+
+        // Running out of commands also quits
+        let (result, _) = debug(PROGRAM, "s\n");
+        assert_eq!(result, Err(RuntimeError::Exit(0)));
+    }
+}
@@ -1,75 +1,540 @@
+use crate::ast::{Slot, Statement, Type};
+use crate::token::Symbol;
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
-use std::rc::Rc;
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::rc::{Rc, Weak};
 
+// How much of a file is read at once to serve fgetc and fgets
+const READ_BUFFER_SIZE: usize = 8 * 1024;
+
+// What a FileHandle reads from or writes to
+pub enum Stream {
+    File(File),
+    // Read a byte at a time, so nothing is taken ahead of getchar and gets.
+    // Holds the interpreter's output, which is flushed before each read.
+    Stdin(Rc<RefCell<dyn Write>>),
+    Stdout(Rc<RefCell<dyn Write>>), // The interpreter's output, shared with printf
+    Stderr,
+}
+
+impl fmt::Debug for Stream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stream::File(file) => write!(f, "File({:?})", file),
+            Stream::Stdin(_) => write!(f, "Stdin"),
+            Stream::Stdout(_) => write!(f, "Stdout"),
+            Stream::Stderr => write!(f, "Stderr"),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::File(file) => file.read(buf),
+            Stream::Stdin(output) => {
+                output.borrow_mut().flush()?;
+                io::stdin().read(buf)
+            }
+            Stream::Stdout(_) | Stream::Stderr => {
+                Err(io::Error::other("stream is not open for reading"))
+            }
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::File(file) => file.write(buf),
+            Stream::Stdout(output) => output.borrow_mut().write(buf),
+            Stream::Stderr => io::stderr().write(buf),
+            Stream::Stdin(_) => Err(io::Error::other("stream is not open for writing")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::File(file) => file.flush(),
+            Stream::Stdout(output) => output.borrow_mut().flush(),
+            Stream::Stderr => io::stderr().flush(),
+            Stream::Stdin(_) => Ok(()),
+        }
+    }
+}
+
+// Like C, the standard streams can't seek or report a position
+impl Seek for Stream {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Stream::File(file) => file.seek(pos),
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "standard streams are not seekable",
+            )),
+        }
+    }
+}
+
+// An open file or standard stream with a read buffer. Bytes read ahead but not
+// yet returned are given back before writing or seeking, so positions are the
+// ones the script sees rather than the file descriptor's.
 #[derive(Debug)]
 pub struct FileHandle {
-    pub file: File,
+    pub stream: Option<Stream>, // None once closed
     pub eof: bool,
     pub error: bool,
+    buffer: Vec<u8>,
+    pos: usize,                 // Next unread byte of `buffer`
+    temp_path: Option<PathBuf>, // A tmpfile() file, removed when the handle is closed or dropped
+}
+
+impl FileHandle {
+    pub fn new(file: File) -> Self {
+        Self::from_stream(Stream::File(file))
+    }
+
+    pub fn from_stream(stream: Stream) -> Self {
+        FileHandle {
+            stream: Some(stream),
+            eof: false,
+            error: false,
+            buffer: Vec::new(),
+            pos: 0,
+            temp_path: None,
+        }
+    }
+
+    // A handle for `file`, which is deleted from `path` once the handle is
+    // closed or dropped
+    pub fn temporary(file: File, path: PathBuf) -> Self {
+        let mut handle = Self::new(file);
+        handle.temp_path = Some(path);
+        handle
+    }
+
+    pub fn temp_path(&self) -> Option<&Path> {
+        self.temp_path.as_deref()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.stream.is_none()
+    }
+
+    // Flushes and drops the underlying stream; closing twice is a no-op
+    pub fn close(&mut self) -> io::Result<()> {
+        self.discard_buffer();
+        let flushed = match self.stream.take() {
+            Some(mut stream) => stream.flush(),
+            None => Ok(()),
+        };
+        if let Some(path) = self.temp_path.take() {
+            // Nothing useful can be done if a temporary file is already gone
+            let _ = std::fs::remove_file(path);
+        }
+        flushed
+    }
+
+    fn open_stream(&mut self) -> io::Result<&mut Stream> {
+        self.stream
+            .as_mut()
+            .ok_or_else(|| io::Error::other("operation on closed file"))
+    }
+
+    fn unread(&self) -> usize {
+        self.buffer.len() - self.pos
+    }
+
+    fn discard_buffer(&mut self) {
+        self.buffer.clear();
+        self.pos = 0;
+    }
+
+    // Moves the file back over bytes read ahead, so it's where the script thinks
+    fn rewind_read_ahead(&mut self) -> io::Result<()> {
+        let unread = self.unread() as i64;
+        if unread > 0 {
+            self.open_stream()?.seek(SeekFrom::Current(-unread))?;
+        }
+        self.discard_buffer();
+        Ok(())
+    }
+}
+
+// Temporary files are cleaned up even if the script never closes them
+impl Drop for FileHandle {
+    fn drop(&mut self) {
+        if self.temp_path.is_some() {
+            let _ = self.close();
+        }
+    }
+}
+
+impl Read for FileHandle {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Large reads skip the buffer once it's drained
+        if self.unread() == 0 && buf.len() >= READ_BUFFER_SIZE {
+            return self.open_stream()?.read(buf);
+        }
+        let n = self.fill_buf()?.read(buf)?;
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for FileHandle {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.unread() == 0 {
+            let Some(stream) = self.stream.as_mut() else {
+                return Err(io::Error::other("operation on closed file"));
+            };
+            let size = match stream {
+                Stream::Stdin(_) => 1,
+                _ => READ_BUFFER_SIZE,
+            };
+            self.buffer.resize(size, 0);
+            self.pos = 0;
+            match stream.read(&mut self.buffer) {
+                Ok(n) => self.buffer.truncate(n),
+                Err(e) => {
+                    self.buffer.clear();
+                    return Err(e);
+                }
+            }
+        }
+        Ok(&self.buffer[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.buffer.len());
+    }
+}
+
+impl Write for FileHandle {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.rewind_read_ahead()?;
+        self.open_stream()?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.open_stream()?.flush()
+    }
+}
+
+impl Seek for FileHandle {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        // A relative seek starts from the script's position, behind the read-ahead
+        let pos = match pos {
+            SeekFrom::Current(offset) => SeekFrom::Current(offset - self.unread() as i64),
+            pos => pos,
+        };
+        self.discard_buffer();
+        self.open_stream()?.seek(pos)
+    }
+
+    fn stream_position(&mut self) -> io::Result<u64> {
+        let unread = self.unread() as u64;
+        Ok(self.open_stream()?.stream_position()? - unread)
+    }
+}
+
+// A host function callable from scripts. Closures let embedders capture state.
+#[derive(Clone)]
+pub struct BuiltinFunction {
+    name: Rc<str>, // What it was registered as, for --profile
+    func: Rc<dyn Fn(Vec<Object>) -> Object>,
+}
+
+impl BuiltinFunction {
+    pub fn new(name: &str, f: impl Fn(Vec<Object>) -> Object + 'static) -> Self {
+        BuiltinFunction {
+            name: name.into(),
+            func: Rc::new(f),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn call(&self, args: Vec<Object>) -> Object {
+        (self.func)(args)
+    }
+}
+
+impl fmt::Debug for BuiltinFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BuiltinFunction")
+    }
+}
+
+// A user-defined function closed over the environment it was defined in. That
+// environment usually holds the function too, so the function only points
+// back weakly; the `Closure` values it's passed around in keep it alive.
+pub struct Function {
+    pub name: Symbol,
+    pub line: usize,          // Where it was defined, for error traces
+    pub file: Option<Symbol>, // The included file it was defined in, if any
+    pub return_type: Type,
+    pub params: Rc<Vec<(Type, Symbol)>>,
+    pub body: Rc<Statement>,
+    pub env: Weak<RefCell<Environment>>,
+}
+
+// The environment usually contains the function itself, so it's left out
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Function({}({}))",
+            self.name,
+            self.param_names().join(", ")
+        )
+    }
+}
+
+impl Function {
+    pub fn param_names(&self) -> Vec<&str> {
+        self.params.iter().map(|(_, name)| &**name).collect()
+    }
+}
+
+// A function value. Every copy holds the function's environment strongly
+// except the one bound in that environment itself, where it would be a cycle
+// keeping the scope and everything in it alive forever. Reading the binding
+// back out makes a strong copy again.
+#[derive(Clone)]
+pub struct Closure {
+    func: Rc<Function>,
+    env: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Closure {
+    pub fn new(func: Function) -> Self {
+        Closure {
+            env: func.env.upgrade(),
+            func: Rc::new(func),
+        }
+    }
+
+    // The environment to run the function in, alive for as long as the
+    // function can be reached
+    pub fn env(&self) -> Option<Rc<RefCell<Environment>>> {
+        self.func.env.upgrade()
+    }
+
+    pub fn ptr_eq(&self, other: &Closure) -> bool {
+        Rc::ptr_eq(&self.func, &other.func)
+    }
+}
+
+impl std::ops::Deref for Closure {
+    type Target = Function;
+
+    fn deref(&self) -> &Function {
+        &self.func
+    }
+}
+
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.func.fmt(f)
+    }
+}
+
+// `val` as stored in the environment at `scope`: without its hold on that
+// environment if it's a function closed over it
+fn held_in(scope: *const Environment, val: Object) -> Object {
+    match val {
+        Object::Function(Closure {
+            func,
+            env: Some(env),
+        }) if std::ptr::eq(env.as_ptr(), scope) => Object::Function(Closure { func, env: None }),
+        val => val,
+    }
+}
+
+// A stored value as read back out, holding its function's environment again
+fn read(val: &Object) -> Object {
+    match val {
+        Object::Function(Closure { func, env: None }) => Object::Function(Closure {
+            func: Rc::clone(func),
+            env: func.env.upgrade(),
+        }),
+        val => val.clone(),
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum Object {
     Integer(i64),
+    Float(f64),
     String(String),
     Boolean(bool),
-    Function(
-        Vec<String>,
-        Box<crate::ast::Statement>,
-        Rc<RefCell<Environment>>,
-    ), // params, body, env
-    Builtin(fn(Vec<Object>) -> Object),
+    // Shared so reading a function out of the environment doesn't copy its AST
+    Function(Closure),
+    Builtin(BuiltinFunction),
     File(Rc<RefCell<FileHandle>>),
+    // Shared, so every copy of an array sees pushes and element assignments
+    Array(Rc<RefCell<Vec<Object>>>),
     Null,
     ReturnValue(Box<Object>),
     Error(String),
+    Exit(i64), // exit(code) unwinding to the top level
 }
 
+// An array being printed or compared further up, to spot one that contains itself
+type ArrayPtr = *const RefCell<Vec<Object>>;
+
 impl PartialEq for Object {
     fn eq(&self, other: &Self) -> bool {
+        self.equals(other, &mut vec![])
+    }
+}
+
+impl Object {
+    // Pairs of arrays already being compared further up count as equal, so
+    // comparing arrays that contain themselves ends
+    fn equals(&self, other: &Self, open: &mut Vec<(ArrayPtr, ArrayPtr)>) -> bool {
         match (self, other) {
             (Object::Integer(l), Object::Integer(r)) => l == r,
+            (Object::Float(l), Object::Float(r)) => l == r,
             (Object::String(l), Object::String(r)) => l == r,
             (Object::Boolean(l), Object::Boolean(r)) => l == r,
-            (Object::Function(p1, b1, _), Object::Function(p2, b2, _)) => p1 == p2 && b1 == b2, // ignoring env
+            (Object::Function(l), Object::Function(r)) => {
+                l.ptr_eq(r)
+                    || (l.return_type == r.return_type && l.params == r.params && l.body == r.body)
+            } // ignoring env
             (Object::Builtin(_), Object::Builtin(_)) => false, // Functions are not comparable easily
             (Object::File(_), Object::File(_)) => false,       // Files are not comparable easily
+            (Object::Array(l), Object::Array(r)) => {
+                let pair = (Rc::as_ptr(l), Rc::as_ptr(r));
+                if Rc::ptr_eq(l, r) || open.contains(&pair) {
+                    return true;
+                }
+                let (l, r) = (l.borrow(), r.borrow());
+                if l.len() != r.len() {
+                    return false;
+                }
+                open.push(pair);
+                let equal = l.iter().zip(r.iter()).all(|(l, r)| l.equals(r, open));
+                open.pop();
+                equal
+            }
             (Object::Null, Object::Null) => true,
-            (Object::ReturnValue(l), Object::ReturnValue(r)) => l == r,
+            (Object::ReturnValue(l), Object::ReturnValue(r)) => l.equals(r, open),
             (Object::Error(l), Object::Error(r)) => l == r,
+            (Object::Exit(l), Object::Exit(r)) => l == r,
             _ => false,
         }
     }
-}
 
-impl Object {
     pub fn inspect(&self) -> String {
+        self.show(false, &mut vec![])
+    }
+
+    // Like inspect, but with strings quoted and escaped the way the --ast printer
+    // shows literals, so "5" and 5 or a trailing newline can be told apart
+    pub fn repr(&self) -> String {
+        self.show(true, &mut vec![])
+    }
+
+    // An array inside itself prints as [...] rather than recursing forever
+    fn show(&self, quoted: bool, open: &mut Vec<ArrayPtr>) -> String {
         match self {
+            Object::String(val) if quoted => format!("{:?}", val),
             Object::Integer(val) => format!("{}", val),
-            Object::String(val) => format!("{}", val),
+            // Debug formatting keeps the point, so 2.0 doesn't read as an integer
+            Object::Float(val) => format!("{:?}", val),
+            Object::String(val) => val.clone(),
             Object::Boolean(val) => format!("{}", val),
-            Object::Function(params, _, _) => format!("fn({}) {{ ... }}", params.join(", ")),
+            Object::Function(func) => format!("fn({}) {{ ... }}", func.param_names().join(", ")),
             Object::Builtin(_) => "builtin function".to_string(),
             Object::File(_) => "file".to_string(),
+            Object::Array(elements) => {
+                let ptr = Rc::as_ptr(elements);
+                if open.contains(&ptr) {
+                    return "[...]".to_string();
+                }
+                open.push(ptr);
+                let shown: Vec<String> = elements
+                    .borrow()
+                    .iter()
+                    .map(|e| e.show(quoted, open))
+                    .collect();
+                open.pop();
+                format!("[{}]", shown.join(", "))
+            }
             Object::Null => "null".to_string(),
-            Object::ReturnValue(val) => val.inspect(),
+            Object::ReturnValue(val) => val.show(quoted, open),
             Object::Error(msg) => format!("ERROR: {}", msg),
+            Object::Exit(code) => format!("exit({})", code),
+        }
+    }
+
+    // The type as named in runtime error messages, such as "type mismatch: INTEGER + STRING"
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Object::Integer(_) => "INTEGER",
+            Object::Float(_) => "FLOAT",
+            Object::String(_) => "STRING",
+            Object::Boolean(_) => "BOOLEAN",
+            Object::Function(_) => "FUNCTION",
+            Object::Builtin(_) => "BUILTIN",
+            Object::File(_) => "FILE",
+            Object::Array(_) => "ARRAY",
+            Object::Null => "NULL",
+            Object::ReturnValue(val) => val.type_name(),
+            Object::Error(_) => "ERROR",
+            Object::Exit(_) => "EXIT",
+        }
+    }
+
+    // Whether the value counts as true in a condition, as in C: zero and null don't
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Object::Null => false,
+            Object::Boolean(val) => *val,
+            Object::Integer(val) => *val != 0,
+            Object::Float(val) => *val != 0.0,
+            _ => true,
         }
     }
 }
 
+// Why a binding can't be assigned or declared again in its scope
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Constant {
+    Declared, // const int MAX = 100;
+    Builtin,  // Builtins and preset globals like null, which only inner scopes can shadow
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Environment {
-    store: HashMap<String, Object>,
+    store: HashMap<Symbol, Object>,
+    // Parameters, and locals the resolver placed, by index. Empty until defined.
+    slots: Vec<Option<(Symbol, Object)>>,
+    // Names bound in this scope, by name or slot, that can't be assigned again
+    constants: HashMap<Symbol, Constant>,
     outer: Option<Rc<RefCell<Environment>>>,
 }
 
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Environment {
     pub fn new() -> Self {
         Environment {
             store: HashMap::new(),
+            slots: vec![],
+            constants: HashMap::new(),
             outer: None,
         }
     }
@@ -77,13 +542,15 @@ impl Environment {
     pub fn new_enclosed(outer: Rc<RefCell<Environment>>) -> Self {
         Environment {
             store: HashMap::new(),
+            slots: vec![],
+            constants: HashMap::new(),
             outer: Some(outer),
         }
     }
 
     pub fn get(&self, name: &str) -> Option<Object> {
-        match self.store.get(name) {
-            Some(obj) => Some(obj.clone()),
+        match self.store.get(name).or_else(|| self.slot_named(name)) {
+            Some(obj) => Some(read(obj)),
             None => match &self.outer {
                 Some(outer) => outer.borrow().get(name),
                 None => None,
@@ -91,8 +558,164 @@ impl Environment {
         }
     }
 
-    pub fn set(&mut self, name: String, val: Object) -> Object {
-        self.store.insert(name, val.clone());
+    // Looks up a resolved local. A slot not yet defined, like a variable used
+    // before its declaration in the same block, falls back to looking the
+    // name up outside that scope, just as an unresolved lookup would.
+    pub fn get_slot(&self, slot: Slot, name: &str) -> Option<Object> {
+        if slot.depth > 0 {
+            let depth = slot.depth - 1;
+            return self
+                .outer
+                .as_ref()?
+                .borrow()
+                .get_slot(Slot { depth, ..slot }, name);
+        }
+        match self.slots.get(slot.index) {
+            Some(Some((_, obj))) => Some(read(obj)),
+            _ => self.outer.as_ref()?.borrow().get(name),
+        }
+    }
+
+    // The value of a slot by name; the last wins, as with repeated parameters
+    fn slot_named(&self, name: &str) -> Option<&Object> {
+        self.slots
+            .iter()
+            .rev()
+            .flatten()
+            .find(|(slot_name, _)| &**slot_name == name)
+            .map(|(_, obj)| obj)
+    }
+
+    // The names bound in this scope itself, not its enclosing ones, sorted
+    pub fn bindings(&self) -> Vec<(String, Object)> {
+        let mut bindings: Vec<(String, Object)> = self
+            .store
+            .iter()
+            .chain(self.slots.iter().flatten().map(|(name, val)| (name, val)))
+            .map(|(name, val)| (name.to_string(), read(val)))
+            .collect();
+        bindings.sort_by(|a, b| a.0.cmp(&b.0));
+        bindings
+    }
+
+    // Binds `name` in this scope, unless it's already a constant here
+    pub fn set(&mut self, name: impl Into<Symbol>, val: Object) -> Object {
+        let name = name.into();
+        if let Some(kind) = self.constants.get(&name) {
+            return constant_error(&name, *kind);
+        }
+        let stored = held_in(self, val.clone());
+        self.store.insert(name, stored);
         val
     }
+
+    // Binds `name` to slot `index` of this scope, unless it's already a constant here
+    pub fn define(&mut self, index: usize, name: &Symbol, val: Object) -> Object {
+        if let Some(kind) = self.constants.get(name) {
+            return constant_error(name, *kind);
+        }
+        if index >= self.slots.len() {
+            self.slots.resize(index + 1, None);
+        }
+        self.slots[index] = Some((Rc::clone(name), held_in(self, val.clone())));
+        val
+    }
+
+    // Makes a name already bound in this scope constant, so later assignments
+    // and declarations of it in this scope fail
+    pub fn make_constant(&mut self, name: impl Into<Symbol>) {
+        self.constants.insert(name.into(), Constant::Declared);
+    }
+
+    // Whether the nearest binding of `name` is a constant, and of which kind
+    pub fn constant(&self, name: &str) -> Option<Constant> {
+        if self.store.contains_key(name) || self.slot_named(name).is_some() {
+            return self.constants.get(name).copied();
+        }
+        self.outer.as_ref()?.borrow().constant(name)
+    }
+
+    // Binds a preset global that scripts can't assign or declare again in
+    // this scope. The host can still replace it, as sandbox mode does.
+    pub fn set_builtin(&mut self, name: &str, val: Object) -> Object {
+        self.store.insert(name.into(), val.clone());
+        self.constants.insert(name.into(), Constant::Builtin);
+        val
+    }
+
+    pub fn register_builtin(
+        &mut self,
+        name: &str,
+        f: impl Fn(Vec<Object>) -> Object + 'static,
+    ) -> Object {
+        self.set_builtin(name, Object::Builtin(BuiltinFunction::new(name, f)))
+    }
+
+    // Updates an existing binding, searching outward through enclosing scopes
+    pub fn assign(&mut self, name: &str, val: Object) -> Object {
+        let stored = held_in(self, val.clone());
+        let existing = match self.store.get_mut(name) {
+            Some(obj) => Some(obj),
+            None => self
+                .slots
+                .iter_mut()
+                .rev()
+                .flatten()
+                .find(|(slot_name, _)| &**slot_name == name)
+                .map(|(_, obj)| obj),
+        };
+        if let Some(obj) = existing {
+            if let Some(kind) = self.constants.get(name) {
+                return constant_error(name, *kind);
+            }
+            *obj = stored;
+            return val;
+        }
+        match &self.outer {
+            Some(outer) => outer.borrow_mut().assign(name, val),
+            None => Object::Error(format!("identifier not found: {}", name)),
+        }
+    }
+
+    // Updates a resolved local, falling back like `get_slot` when it's undefined
+    pub fn assign_slot(&mut self, slot: Slot, name: &str, val: Object) -> Object {
+        if slot.depth > 0 {
+            let depth = slot.depth - 1;
+            return match &self.outer {
+                Some(outer) => outer
+                    .borrow_mut()
+                    .assign_slot(Slot { depth, ..slot }, name, val),
+                None => Object::Error(format!("identifier not found: {}", name)),
+            };
+        }
+        let stored = held_in(self, val.clone());
+        match self.slots.get_mut(slot.index) {
+            Some(Some(_)) if self.constants.contains_key(name) => {
+                constant_error(name, self.constants[name])
+            }
+            Some(Some((_, obj))) => {
+                *obj = stored;
+                val
+            }
+            _ => match &self.outer {
+                Some(outer) => outer.borrow_mut().assign(name, val),
+                None => Object::Error(format!("identifier not found: {}", name)),
+            },
+        }
+    }
+}
+
+fn constant_error(name: &str, kind: Constant) -> Object {
+    Object::Error(match kind {
+        Constant::Declared => format!("cannot assign to constant {}", name),
+        Constant::Builtin => builtin_error(name),
+    })
+}
+
+// Names the rule, since a script only meets it by reusing a builtin's name
+pub fn builtin_error(name: &str) -> String {
+    format!(
+        "cannot assign to builtin {}; only a parameter or local variable can shadow it",
+        name
+    )
 }
@@ -1,129 +1,711 @@
-use crate::ast::{Expression, Program, Statement};
-use crate::env::{Environment, Object};
-use crate::token::Token;
+use crate::ast::{Expression, Program, Statement, Type};
+use crate::debugger::{Debugger, Resume};
+use crate::env::{self, Closure, Constant, Environment, Function, Object};
+use crate::profiler::Profiler;
+use crate::stdlib::{self, Output, StdlibConfig, TempFiles, stdout_output};
+use crate::token::{Symbol, Token};
 use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
 use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_MAX_DEPTH: usize = 1000;
+
+// The wall-clock deadline is only checked this often, since reading the clock
+// on every step would dominate tight loops
+const TIMEOUT_CHECK_INTERVAL: u64 = 1024;
+
+// Error traces list at most this many calls, so runaway recursion stays readable
+const MAX_TRACE_FRAMES: usize = 10;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum RuntimeError {
+    UndefinedIdentifier(String),
+    TypeMismatch { op: Token, lhs: Object, rhs: Object },
+    // An infix operator both operands support in general, but not this one
+    UnknownOperator { op: Token, lhs: Object, rhs: Object },
+    UnknownPrefixOperator { op: Token, operand: Object },
+    NotAnInteger { op: Token, value: Object }, // `++`/`--` on a non-integer
+    DivisionByZero,
+    ShiftOutOfRange(i64),
+    IntegerOverflow(Token), // Results outside i64 are errors in every build profile
+    WrongArity { want: usize, got: usize },
+    RecursionLimit,
+    // A second top-level definition of a function, first defined at `first`
+    FunctionRedefined { name: String, first: Position },
+    ConstantAssignment(String), // Assigning or redeclaring a `const`
+    BuiltinAssignment(String),  // The same for a builtin or preset global at the top level
+    StepLimit,
+    Timeout(Duration),
+    NotAFunction(Object),
+    NotIndexable(Object),
+    InvalidIndex(Object), // An index that isn't an integer
+    IndexOutOfBounds { index: i64, len: usize },
+    ImmutableString, // `s[i] = v` on a string
+    Custom(String),  // Raised by builtins
+    // Not a failure: exit(code) unwinding to the top level
+    Exit(i64),
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuntimeError::UndefinedIdentifier(name) => write!(f, "identifier not found: {}", name),
+            RuntimeError::TypeMismatch { op, lhs, rhs } => {
+                write!(
+                    f,
+                    "type mismatch: {} {} {}",
+                    lhs.type_name(),
+                    op.literal(),
+                    rhs.type_name()
+                )
+            }
+            RuntimeError::UnknownOperator { op, lhs, rhs } => write!(
+                f,
+                "unknown operator: {} {} {}",
+                lhs.type_name(),
+                op.literal(),
+                rhs.type_name()
+            ),
+            RuntimeError::UnknownPrefixOperator { op, operand } => {
+                write!(
+                    f,
+                    "unknown operator: {}{}",
+                    op.literal(),
+                    operand.type_name()
+                )
+            }
+            RuntimeError::NotAnInteger { op, value } => {
+                write!(
+                    f,
+                    "{} requires an integer, got {}",
+                    op.literal(),
+                    value.type_name()
+                )
+            }
+            RuntimeError::DivisionByZero => write!(f, "division by zero"),
+            RuntimeError::ShiftOutOfRange(count) => {
+                write!(f, "shift count {} out of range 0..=63", count)
+            }
+            RuntimeError::IntegerOverflow(op) => {
+                write!(f, "integer overflow in {}", op.literal())
+            }
+            RuntimeError::WrongArity { want, got } => {
+                write!(f, "wrong number of arguments: want={}, got={}", want, got)
+            }
+            RuntimeError::RecursionLimit => write!(f, "maximum recursion depth exceeded"),
+            RuntimeError::FunctionRedefined { name, first } => write!(
+                f,
+                "function {} is already defined {} {}",
+                name,
+                if first.file.is_some() { "at" } else { "on" },
+                first
+            ),
+            RuntimeError::ConstantAssignment(name) => {
+                write!(f, "cannot assign to constant {}", name)
+            }
+            RuntimeError::BuiltinAssignment(name) => write!(f, "{}", env::builtin_error(name)),
+            RuntimeError::StepLimit => write!(f, "execution step limit exceeded"),
+            RuntimeError::Timeout(limit) => {
+                write!(f, "execution timed out after {} ms", limit.as_millis())
+            }
+            RuntimeError::NotAFunction(obj) => write!(f, "not a function: {}", obj.type_name()),
+            RuntimeError::NotIndexable(obj) => {
+                write!(f, "index operator not supported: {}", obj.type_name())
+            }
+            RuntimeError::InvalidIndex(obj) => {
+                write!(f, "index must be an integer, got {}", obj.type_name())
+            }
+            RuntimeError::IndexOutOfBounds { index, len } => {
+                write!(f, "index {} out of bounds for length {}", index, len)
+            }
+            RuntimeError::ImmutableString => {
+                write!(f, "strings are immutable; build a new string instead")
+            }
+            RuntimeError::Custom(msg) => write!(f, "{}", msg),
+            RuntimeError::Exit(code) => write!(f, "exit({})", code),
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+// An active call to a user function, for error traces
+#[derive(Debug, Clone)]
+struct Frame {
+    name: Symbol,
+    file: Option<Symbol>, // The included file the function was defined in, if any
+    line: usize,          // Where the function was defined
+}
+
+// A line of the main source, or of the included `file`
+#[derive(Debug, PartialEq, Clone)]
+pub struct Position {
+    pub file: Option<Symbol>,
+    pub line: usize,
+}
+
+// Where `line` is, as error reports name it: `line 3` in the main source,
+// `lib.c:3` in an included file
+fn position(file: &Option<Symbol>, line: usize) -> String {
+    match file {
+        Some(file) => format!("{}:{}", file, line),
+        None => format!("line {}", line),
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", position(&self.file, self.line))
+    }
+}
 
 pub struct Interpreter {
-    // env: Rc<RefCell<Environment>>,
+    output: Output,
+    stdlib_config: StdlibConfig, // Which builtins `global_environment` enables
+    args: Vec<String>,           // The program's command line, bound as ARGV
+    max_depth: usize,            // Deeper calls fail instead of overflowing the Rust stack
+    // Statements and loop iterations run so far, and the most allowed
+    steps: u64,
+    max_steps: Option<u64>,
+    timeout: Option<(Duration, Instant)>, // The limit and when it runs out
+    line: usize,                          // Line of the statement being evaluated
+    file: Option<Symbol>,                 // Its included file, None for the main source
+    trace: Option<Output>,                // Where --trace reports each statement
+    temp_files: TempFiles,                // Created by tmpfile(), removed by remove_temp_files
+    debugger: Option<Debugger>,
+    profiler: Option<Profiler>,
+    call_stack: Vec<Frame>,
+    returned: bool, // Whether the last eval_program ended with a top-level return
+    // File, line and call stack where the last runtime error was raised,
+    // captured before the stack unwinds
+    error_trace: Option<(Option<Symbol>, usize, Vec<Frame>)>,
+}
+
+impl Drop for Interpreter {
+    fn drop(&mut self) {
+        self.remove_temp_files();
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Interpreter {
     pub fn new() -> Self {
-        Interpreter {}
+        Self::with_output(stdout_output())
+    }
+
+    // Printing builtins registered by `global_environment` write to `output`
+    pub fn with_output(output: Output) -> Self {
+        Interpreter {
+            output,
+            stdlib_config: StdlibConfig::default(),
+            args: vec![],
+            max_depth: DEFAULT_MAX_DEPTH,
+            steps: 0,
+            max_steps: None,
+            timeout: None,
+            line: 0,
+            file: None,
+            trace: None,
+            temp_files: TempFiles::default(),
+            debugger: None,
+            profiler: None,
+            call_stack: vec![],
+            returned: false,
+            error_trace: None,
+        }
+    }
+
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
     }
 
-    pub fn eval_program(&mut self, program: &Program, env: Rc<RefCell<Environment>>) -> Object {
+    // Stops evaluation with an error once this many statements and loop
+    // iterations have run, counted over the interpreter's lifetime
+    pub fn set_max_steps(&mut self, max_steps: u64) {
+        self.max_steps = Some(max_steps);
+    }
+
+    // Stops evaluation with an error once `limit` has passed, starting now
+    pub fn set_timeout(&mut self, limit: Duration) {
+        self.timeout = Some((limit, Instant::now() + limit));
+    }
+
+    // Writes a line to `sink` before each statement and each loop condition,
+    // indented by call depth
+    pub fn set_trace(&mut self, sink: Output) {
+        self.trace = Some(sink);
+    }
+
+    // Hands control to `debugger` before each statement. Quitting from it
+    // unwinds like exit(0).
+    pub fn set_debugger(&mut self, debugger: Debugger) {
+        self.debugger = Some(debugger);
+    }
+
+    // Counts calls and time per function, user-defined or builtin, from now on
+    pub fn enable_profiling(&mut self) {
+        self.profiler = Some(Profiler::new());
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    pub fn set_stdlib_config(&mut self, config: StdlibConfig) {
+        self.stdlib_config = config;
+    }
+
+    // The program's name and arguments, like C's argv
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+    }
+
+    // A root environment with the stdlib, the null constant and the
+    // ARGC/ARGV command line
+    pub fn global_environment(&self) -> Rc<RefCell<Environment>> {
+        let env = Rc::new(RefCell::new(Environment::new()));
+
+        // Register stdlib
+        stdlib::register_stdlib(
+            Rc::clone(&env),
+            Rc::clone(&self.output),
+            self.stdlib_config,
+            Rc::clone(&self.temp_files),
+        );
+
+        // Add constants
+        let argv = self.args.iter().cloned().map(Object::String).collect();
+        for (name, val) in [
+            ("null", Object::Null),
+            ("ARGC", Object::Integer(self.args.len() as i64)),
+            ("ARGV", Object::Array(Rc::new(RefCell::new(argv)))),
+        ] {
+            env.borrow_mut().set_builtin(name, val);
+        }
+
+        env
+    }
+
+    // Deletes the files tmpfile() made that are still there, even ones a
+    // reference cycle keeps open. The interpreter does this when dropped;
+    // call it before exiting the process without dropping it.
+    pub fn remove_temp_files(&self) {
+        stdlib::remove_temp_files(&self.temp_files);
+    }
+
+    // Like C, a `main` taking no arguments or (argc, argv) is the entry point
+    // once the top level has run. None if there is no such function.
+    pub fn call_main(
+        &mut self,
+        env: Rc<RefCell<Environment>>,
+    ) -> Option<Result<Object, RuntimeError>> {
+        let main_fn = env.borrow().get("main")?;
+        let args = match &main_fn {
+            Object::Function(func) if func.params.is_empty() => vec![],
+            Object::Function(func) if func.params.len() == 2 => {
+                let env = env.borrow();
+                vec![
+                    env.get("ARGC").unwrap_or(Object::Integer(0)),
+                    env.get("ARGV")
+                        .unwrap_or_else(|| Object::Array(Rc::new(RefCell::new(vec![])))),
+                ]
+            }
+            _ => return None,
+        };
+        Some(self.call_function(main_fn, args))
+    }
+
+    // Runs the program, returning the value of its last statement. A call to
+    // exit() comes back as `Err(RuntimeError::Exit(code))`.
+    pub fn eval_program(
+        &mut self,
+        program: &Program,
+        env: Rc<RefCell<Environment>>,
+    ) -> Result<Object, RuntimeError> {
         let mut result = Object::Null;
+        self.error_trace = None;
+        self.returned = false;
 
-        for stmt in &program.statements {
-            result = self.eval_statement(stmt, Rc::clone(&env));
+        // Top-level functions are defined before anything else runs, so code
+        // can call a function defined further down the file
+        let mut defined: HashMap<&str, Position> = HashMap::new();
+        let mut hoisted = vec![];
+        for (i, stmt) in program.statements.iter().enumerate() {
+            if let Statement::Function { name, line, .. } = stmt {
+                self.file = source_file(program, i);
+                let here = Position {
+                    file: self.file.clone(),
+                    line: *line,
+                };
+                if let Some(first) = defined.insert(name, here) {
+                    self.line = *line;
+                    let err = RuntimeError::FunctionRedefined {
+                        name: name.to_string(),
+                        first,
+                    };
+                    self.capture_error_trace(&err);
+                    return Err(err);
+                }
+                let value = self
+                    .eval_statement(stmt, Rc::clone(&env))
+                    .inspect_err(|err| self.capture_error_trace(err))?;
+                hoisted.push(value);
+            }
+        }
 
-            if let Object::ReturnValue(val) = result {
-                return *val;
+        // A definition still counts as the last statement's value, as the REPL shows
+        let mut hoisted = hoisted.into_iter();
+        for (i, stmt) in program.statements.iter().enumerate() {
+            if let Statement::Function { .. } = stmt {
+                result = hoisted.next().unwrap_or(Object::Null);
+                continue;
             }
-            if let Object::Error(_) = result {
-                return result;
+            self.file = source_file(program, i);
+            result = self
+                .eval_statement(stmt, Rc::clone(&env))
+                .inspect_err(|err| self.capture_error_trace(err))?;
+
+            if let Object::ReturnValue(val) = result {
+                self.returned = true;
+                return Ok(*val);
             }
         }
 
-        result
+        Ok(result)
     }
 
-    fn eval_block(&mut self, statements: &Vec<Statement>, env: Rc<RefCell<Environment>>) -> Object {
+    // Whether the last eval_program stopped at a top-level `return`, rather
+    // than running to the end, so hosts can treat its value as an exit status
+    pub fn program_returned(&self) -> bool {
+        self.returned
+    }
+
+    // Describes where the last runtime error happened, innermost call first:
+    //
+    //     runtime error at line 42: identifier not found: cnt
+    //       in count_words (lib.c:37)
+    //       in main (line 12)
+    //
+    // Each function is listed with the line it was defined on. Lines in an
+    // included file are given with the file's name.
+    pub fn error_report(&self, err: &RuntimeError) -> String {
+        let (file, line, frames) = match &self.error_trace {
+            Some((file, line, frames)) => (file, *line, frames.as_slice()),
+            None => (&self.file, self.line, &[][..]),
+        };
+
+        let mut report = format!("runtime error at {}: {}", position(file, line), err);
+        for frame in frames.iter().rev().take(MAX_TRACE_FRAMES) {
+            report.push_str(&format!(
+                "\n  in {} ({})",
+                frame.name,
+                position(&frame.file, frame.line)
+            ));
+        }
+        if frames.len() > MAX_TRACE_FRAMES {
+            report.push_str(&format!(
+                "\n  ... {} more calls",
+                frames.len() - MAX_TRACE_FRAMES
+            ));
+        }
+        report
+    }
+
+    // Records the current line and call stack for the error being returned,
+    // unless an inner call already recorded where it was raised
+    fn capture_error_trace(&mut self, err: &RuntimeError) {
+        if self.error_trace.is_none() && !matches!(err, RuntimeError::Exit(_)) {
+            self.error_trace = Some((self.file.clone(), self.line, self.call_stack.clone()));
+        }
+    }
+
+    fn trace(&self, stmt: &Statement) {
+        if let (Some(sink), Some(line)) = (&self.trace, stmt.line()) {
+            let indent = "  ".repeat(self.call_stack.len());
+            // Tracing is best-effort; a failed write shouldn't stop the program
+            let _ = writeln!(
+                sink.borrow_mut(),
+                "TRACE line {}: {}{}",
+                line,
+                indent,
+                stmt.summary()
+            );
+        }
+    }
+
+    // Spends one step of the execution budget
+    fn tick(&mut self) -> Result<(), RuntimeError> {
+        self.steps += 1;
+        if let Some(max_steps) = self.max_steps
+            && self.steps > max_steps
+        {
+            return Err(RuntimeError::StepLimit);
+        }
+        if let Some((limit, deadline)) = self.timeout
+            && self.steps.is_multiple_of(TIMEOUT_CHECK_INTERVAL)
+            && Instant::now() >= deadline
+        {
+            return Err(RuntimeError::Timeout(limit));
+        }
+        Ok(())
+    }
+
+    fn eval_block(
+        &mut self,
+        statements: &Vec<Statement>,
+        env: Rc<RefCell<Environment>>,
+    ) -> Result<Object, RuntimeError> {
         let mut result = Object::Null;
 
         for stmt in statements {
-            result = self.eval_statement(stmt, Rc::clone(&env));
+            result = self.eval_statement(stmt, Rc::clone(&env))?;
 
             if let Object::ReturnValue(_) = result {
-                return result;
-            }
-            if let Object::Error(_) = result {
-                return result;
+                return Ok(result);
             }
         }
 
-        result
+        Ok(result)
     }
 
-    fn eval_statement(&mut self, stmt: &Statement, env: Rc<RefCell<Environment>>) -> Object {
+    fn eval_statement(
+        &mut self,
+        stmt: &Statement,
+        env: Rc<RefCell<Environment>>,
+    ) -> Result<Object, RuntimeError> {
+        if let Some(line) = stmt.line() {
+            self.line = line;
+        }
+        self.trace(stmt);
+        if let Some(debugger) = &mut self.debugger
+            && debugger.pause(stmt, self.call_stack.len(), &env) == Resume::Quit
+        {
+            return Err(RuntimeError::Exit(0));
+        }
+        self.tick()?;
+
         match stmt {
-            Statement::Expression(expr) => self.eval_expression(expr, env),
-            Statement::Return(expr) => {
-                let val = self.eval_expression(expr, env);
-                if self.is_error(&val) {
-                    return val;
-                }
-                Object::ReturnValue(Box::new(val))
+            Statement::Expression { expr, .. } => self.eval_expression(expr, env),
+            Statement::Return { value, .. } => {
+                let val = self.eval_expression(value, env)?;
+                Ok(Object::ReturnValue(Box::new(val)))
             }
-            Statement::Let { name, value } => {
-                let val = self.eval_expression(value, Rc::clone(&env));
-                if self.is_error(&val) {
-                    return val;
+            Statement::Let {
+                constant,
+                name,
+                value,
+                slot,
+                ..
+            } => {
+                let val = self.eval_expression(value, Rc::clone(&env))?;
+                declare(&env, *slot, name, val, *constant)
+            }
+            Statement::MultiLet {
+                constant,
+                declarators,
+                slots,
+                ..
+            } => {
+                let mut result = Object::Null;
+                for (i, (name, value)) in declarators.iter().enumerate() {
+                    let val = self.eval_expression(value, Rc::clone(&env))?;
+                    let slot = slots.as_ref().map(|slots| slots[i]);
+                    result = declare(&env, slot, name, val, *constant)?;
                 }
-                env.borrow_mut().set(name.clone(), val)
+                Ok(result)
+            }
+            Statement::Block(stmts) => {
+                // Each block gets its own scope so declarations don't leak out
+                let block_env = Rc::new(RefCell::new(Environment::new_enclosed(env)));
+                self.eval_block(stmts, block_env)
             }
-            Statement::Block(stmts) => self.eval_block(stmts, env),
             Statement::If {
                 condition,
                 consequence,
                 alternative,
+                ..
             } => {
-                let cond = self.eval_expression(condition, Rc::clone(&env));
-                if self.is_error(&cond) {
-                    return cond;
-                }
+                let cond = self.eval_expression(condition, Rc::clone(&env))?;
 
-                if self.is_truthy(&cond) {
+                if cond.is_truthy() {
                     self.eval_statement(consequence, env)
                 } else if let Some(alt) = alternative {
                     self.eval_statement(alt, env)
                 } else {
-                    Object::Null
+                    Ok(Object::Null)
                 }
             }
-            Statement::While { condition, body } => {
+            Statement::While {
+                condition, body, ..
+            } => {
+                // The statement itself was traced before the first check
+                let mut first = true;
                 loop {
-                    let cond = self.eval_expression(condition, Rc::clone(&env));
-                    if self.is_error(&cond) {
-                        return cond;
+                    if !std::mem::take(&mut first) {
+                        self.trace(stmt);
+                    }
+                    self.tick()?;
+                    let cond = self.eval_expression(condition, Rc::clone(&env))?;
+                    if !cond.is_truthy() {
+                        break;
                     }
 
-                    if !self.is_truthy(&cond) {
+                    let result = self.eval_statement(body, Rc::clone(&env))?;
+                    if let Object::ReturnValue(_) = result {
+                        return Ok(result);
+                    }
+                }
+                Ok(Object::Null)
+            }
+            Statement::DoWhile {
+                body, condition, ..
+            } => {
+                // The body always runs once before the condition is checked
+                loop {
+                    self.tick()?;
+                    let result = self.eval_statement(body, Rc::clone(&env))?;
+                    if let Object::ReturnValue(_) = result {
+                        return Ok(result);
+                    }
+
+                    self.trace(stmt);
+                    let cond = self.eval_expression(condition, Rc::clone(&env))?;
+                    if !cond.is_truthy() {
                         break;
                     }
+                }
+                Ok(Object::Null)
+            }
+            Statement::Switch {
+                subject,
+                cases,
+                default,
+                ..
+            } => {
+                let subject = self.eval_expression(subject, Rc::clone(&env))?;
 
-                    let result = self.eval_statement(body, Rc::clone(&env));
-                    // Handle return inside while?
-                    match result {
-                        Object::ReturnValue(_) | Object::Error(_) => return result,
-                        _ => {}
+                // The first matching case runs; cases never fall through
+                let mut body = default.as_ref();
+                for (label, case_body) in cases {
+                    let label = self.eval_expression(label, Rc::clone(&env))?;
+                    let matched =
+                        self.eval_infix_expression(&Token::Equal, subject.clone(), label)?;
+                    if matched.is_truthy() {
+                        body = Some(case_body);
+                        break;
                     }
                 }
-                Object::Null
+
+                let Some(body) = body else {
+                    return Ok(Object::Null);
+                };
+                let case_env = Rc::new(RefCell::new(Environment::new_enclosed(env)));
+                match self.eval_block(body, case_env)? {
+                    result @ Object::ReturnValue(_) => Ok(result),
+                    _ => Ok(Object::Null),
+                }
             }
-            Statement::Function { name, params, body } => {
-                let func = Object::Function(params.clone(), body.clone(), Rc::clone(&env));
-                env.borrow_mut().set(name.clone(), func)
+            Statement::For {
+                init,
+                condition,
+                post,
+                body,
+                ..
+            } => {
+                // Variables declared in the initializer are scoped to the loop
+                let loop_env = Rc::new(RefCell::new(Environment::new_enclosed(env)));
+
+                if let Some(init) = init {
+                    self.eval_statement(init, Rc::clone(&loop_env))?;
+                }
+
+                let mut first = true;
+                loop {
+                    if !std::mem::take(&mut first) {
+                        self.trace(stmt);
+                    }
+                    self.tick()?;
+                    if let Some(condition) = condition {
+                        let cond = self.eval_expression(condition, Rc::clone(&loop_env))?;
+                        if !cond.is_truthy() {
+                            break;
+                        }
+                    }
+
+                    let result = self.eval_statement(body, Rc::clone(&loop_env))?;
+                    if let Object::ReturnValue(_) = result {
+                        return Ok(result);
+                    }
+
+                    if let Some(post) = post {
+                        self.eval_expression(post, Rc::clone(&loop_env))?;
+                    }
+                }
+                Ok(Object::Null)
             }
+            Statement::Function {
+                return_type,
+                name,
+                params,
+                body,
+                slot,
+                line,
+            } => {
+                let func = Function {
+                    name: name.clone(),
+                    line: *line,
+                    file: self.file.clone(),
+                    return_type: *return_type,
+                    params: Rc::clone(params),
+                    body: Rc::clone(body),
+                    env: Rc::downgrade(&env),
+                };
+                declare(
+                    &env,
+                    *slot,
+                    name,
+                    Object::Function(Closure::new(func)),
+                    false,
+                )
+            }
+            Statement::Prototype { .. } => Ok(Object::Null),
+            // Only reachable when the program wasn't loaded with load_program
+            Statement::Include { path, .. } => Err(RuntimeError::Custom(format!(
+                "#include \"{}\" was not expanded before running",
+                path
+            ))),
         }
     }
 
-    fn eval_expression(&mut self, expr: &Expression, env: Rc<RefCell<Environment>>) -> Object {
+    fn eval_expression(
+        &mut self,
+        expr: &Expression,
+        env: Rc<RefCell<Environment>>,
+    ) -> Result<Object, RuntimeError> {
         match expr {
-            Expression::Integer(val) => Object::Integer(*val),
-            Expression::String(val) => Object::String(val.clone()),
-            Expression::Boolean(val) => Object::Boolean(*val),
-            Expression::Identifier(name) => match env.borrow().get(name) {
-                Some(val) => val,
-                None => Object::Error(format!("identifier not found: {}", name)),
-            },
+            Expression::Integer(val) => Ok(Object::Integer(*val)),
+            Expression::Float(val) => Ok(Object::Float(*val)),
+            Expression::String(val) => Ok(Object::String(val.clone())),
+            // Characters are one-character strings, the same values fgetc and getchar return
+            Expression::Character(val) => Ok(Object::String(val.to_string())),
+            Expression::Boolean(val) => Ok(Object::Boolean(*val)),
+            Expression::Identifier(name) => env
+                .borrow()
+                .get(name)
+                .ok_or_else(|| RuntimeError::UndefinedIdentifier(name.to_string())),
+            Expression::Local { name, slot } => env
+                .borrow()
+                .get_slot(*slot, name)
+                .ok_or_else(|| RuntimeError::UndefinedIdentifier(name.to_string())),
             Expression::Prefix { operator, right } => {
-                let right_val = self.eval_expression(right, env);
-                if self.is_error(&right_val) {
-                    return right_val;
-                }
+                let right_val = self.eval_expression(right, env)?;
                 self.eval_prefix_expression(operator, right_val)
             }
             Expression::Infix {
@@ -131,113 +713,1795 @@ impl Interpreter {
                 operator,
                 right,
             } => {
-                let left_val = self.eval_expression(left, Rc::clone(&env));
-                if self.is_error(&left_val) {
-                    return left_val;
-                }
+                let left_val = self.eval_expression(left, Rc::clone(&env))?;
 
-                let right_val = self.eval_expression(right, env);
-                if self.is_error(&right_val) {
-                    return right_val;
+                // && and || short-circuit, so the right operand may never run
+                if let Token::And | Token::Or = operator {
+                    match (operator, left_val.is_truthy()) {
+                        (Token::And, false) => return Ok(Object::Boolean(false)),
+                        (Token::Or, true) => return Ok(Object::Boolean(true)),
+                        _ => {}
+                    }
+                    let right_val = self.eval_expression(right, env)?;
+                    return Ok(Object::Boolean(right_val.is_truthy()));
                 }
 
+                let right_val = self.eval_expression(right, env)?;
                 self.eval_infix_expression(operator, left_val, right_val)
             }
+            Expression::Assign { name, value, slot } => {
+                let val = self.eval_expression(value, Rc::clone(&env))?;
+                let assigned = match slot {
+                    Some(slot) => env.borrow_mut().assign_slot(*slot, name, val),
+                    None => env.borrow_mut().assign(name, val),
+                };
+                match assigned {
+                    Object::Error(_) => Err(assignment_error(&env, name)),
+                    val => Ok(val),
+                }
+            }
+            Expression::Ternary {
+                condition,
+                then,
+                otherwise,
+            } => {
+                let cond = self.eval_expression(condition, Rc::clone(&env))?;
+
+                // Only the chosen branch is evaluated
+                if cond.is_truthy() {
+                    self.eval_expression(then, env)
+                } else {
+                    self.eval_expression(otherwise, env)
+                }
+            }
+            Expression::Update {
+                name,
+                operator,
+                prefix,
+                slot,
+            } => {
+                let current = match slot {
+                    Some(slot) => env.borrow().get_slot(*slot, name),
+                    None => env.borrow().get(name),
+                };
+                let old = match current {
+                    Some(Object::Integer(n)) => n,
+                    Some(value) => {
+                        return Err(RuntimeError::NotAnInteger {
+                            op: operator.clone(),
+                            value,
+                        });
+                    }
+                    None => return Err(RuntimeError::UndefinedIdentifier(name.to_string())),
+                };
+                let new = if *operator == Token::Increment {
+                    old.checked_add(1)
+                } else {
+                    old.checked_sub(1)
+                }
+                .ok_or_else(|| RuntimeError::IntegerOverflow(operator.clone()))?;
+                let assigned = match slot {
+                    Some(slot) => env
+                        .borrow_mut()
+                        .assign_slot(*slot, name, Object::Integer(new)),
+                    None => env.borrow_mut().assign(name, Object::Integer(new)),
+                };
+                if let Object::Error(_) = assigned {
+                    return Err(assignment_error(&env, name));
+                }
+                // Prefix forms yield the updated value, postfix forms the original
+                Ok(Object::Integer(if *prefix { new } else { old }))
+            }
+            Expression::ArrayLiteral(elements) => {
+                let mut values = vec![];
+                for element in elements {
+                    values.push(self.eval_expression(element, Rc::clone(&env))?);
+                }
+                Ok(Object::Array(Rc::new(RefCell::new(values))))
+            }
+            Expression::Index { left, index } => {
+                let left = self.eval_expression(left, Rc::clone(&env))?;
+                let index = self.eval_expression(index, env)?;
+                match left {
+                    Object::Array(elements) => {
+                        let elements = elements.borrow();
+                        let i = self.array_index(&index, elements.len())?;
+                        Ok(elements[i].clone())
+                    }
+                    // Strings index by char, like strlen counts them
+                    Object::String(s) => {
+                        let i = self.array_index(&index, s.chars().count())?;
+                        Ok(Object::String(
+                            s.chars().nth(i).unwrap_or_default().to_string(),
+                        ))
+                    }
+                    other => Err(RuntimeError::NotIndexable(other)),
+                }
+            }
+            Expression::IndexAssign {
+                left,
+                index,
+                operator,
+                value,
+            } => {
+                let left = self.eval_expression(left, Rc::clone(&env))?;
+                let index = self.eval_expression(index, Rc::clone(&env))?;
+                let elements = match left {
+                    Object::Array(elements) => elements,
+                    Object::String(_) => return Err(RuntimeError::ImmutableString),
+                    other => return Err(RuntimeError::NotIndexable(other)),
+                };
+                // `a[i] += v` reads the element before evaluating `v`, as
+                // `a[i] = a[i] + v` would, but evaluates `a` and `i` only once
+                let old = match operator {
+                    Some(_) => {
+                        let elements = elements.borrow();
+                        let i = self.array_index(&index, elements.len())?;
+                        Some(elements[i].clone())
+                    }
+                    None => None,
+                };
+                let mut value = self.eval_expression(value, env)?;
+                if let (Some(operator), Some(old)) = (operator, old) {
+                    value = self.eval_infix_expression(operator, old, value)?;
+                }
+                let mut elements = elements.borrow_mut();
+                let i = self.array_index(&index, elements.len())?;
+                elements[i] = value.clone();
+                Ok(value)
+            }
             Expression::Call {
                 function,
                 arguments,
             } => {
-                let func = self.eval_expression(function, Rc::clone(&env));
-                if self.is_error(&func) {
-                    return func;
-                }
+                let func = self.eval_expression(function, Rc::clone(&env))?;
 
                 let mut args = vec![];
                 for arg in arguments {
-                    let val = self.eval_expression(arg, Rc::clone(&env));
-                    if self.is_error(&val) {
-                        return val;
-                    }
-                    args.push(val);
+                    args.push(self.eval_expression(arg, Rc::clone(&env))?);
                 }
 
-                if let Object::Function(params, body, func_env) = func {
-                    if params.len() != args.len() {
-                        return Object::Error(format!(
-                            "wrong number of arguments: want={}, got={}",
-                            params.len(),
-                            args.len()
-                        ));
-                    }
+                self.call_function(func, args)
+            }
+        }
+    }
 
-                    // New environment!
-                    let mut enclosed = Environment::new_enclosed(func_env);
-                    for (param, arg) in params.iter().zip(args) {
-                        enclosed.set(param.clone(), arg);
-                    }
+    pub fn call_function(
+        &mut self,
+        func: Object,
+        args: Vec<Object>,
+    ) -> Result<Object, RuntimeError> {
+        let name = match (&self.profiler, &func) {
+            (Some(_), Object::Function(func)) => func.name.clone(),
+            (Some(_), Object::Builtin(func)) => func.name().into(),
+            _ => return self.invoke(func, args),
+        };
+        if let Some(profiler) = &mut self.profiler {
+            profiler.enter(&name);
+        }
+        let result = self.invoke(func, args);
+        if let Some(profiler) = &mut self.profiler {
+            profiler.exit(&name);
+        }
+        result
+    }
 
-                    let result = self.eval_statement(&body, Rc::new(RefCell::new(enclosed)));
-                    // Unwrap return value if present
-                    if let Object::ReturnValue(val) = result {
-                        *val
-                    } else {
-                        result
-                    }
-                } else if let Object::Builtin(func) = func {
-                    func(args)
-                } else {
-                    Object::Error(format!("not a function: {:?}", func))
-                }
+    fn invoke(&mut self, func: Object, args: Vec<Object>) -> Result<Object, RuntimeError> {
+        if let Object::Function(func) = func {
+            // A call from outside the interpreter starts a fresh trace
+            if self.call_stack.is_empty() {
+                self.error_trace = None;
+            }
+
+            if func.params.len() != args.len() {
+                return Err(RuntimeError::WrongArity {
+                    want: func.params.len(),
+                    got: args.len(),
+                });
+            }
+
+            if self.call_stack.len() >= self.max_depth {
+                return Err(RuntimeError::RecursionLimit);
+            }
+
+            // New environment!
+            // The function can only be reached while its scope is alive
+            let Some(scope) = func.env() else {
+                return Err(RuntimeError::Custom(format!(
+                    "{}: the scope it was defined in has ended",
+                    func.name
+                )));
+            };
+            let mut enclosed = Environment::new_enclosed(scope);
+            // Parameters always take the first slots, whether or not the
+            // body was resolved; lookups by name find them there too
+            for (i, ((_, param), arg)) in func.params.iter().zip(args).enumerate() {
+                enclosed.define(i, param, arg);
+            }
+
+            self.call_stack.push(Frame {
+                name: func.name.clone(),
+                file: func.file.clone(),
+                line: func.line,
+            });
+            // The body may be in another file; the caller carries on where it was
+            let caller = (
+                std::mem::replace(&mut self.file, func.file.clone()),
+                self.line,
+            );
+            let result = self
+                .eval_statement(&func.body, Rc::new(RefCell::new(enclosed)))
+                .inspect_err(|err| self.capture_error_trace(err));
+            self.call_stack.pop();
+            (self.file, self.line) = caller;
+
+            // Unwrap return value if present
+            match result? {
+                Object::ReturnValue(val) => Ok(*val),
+                // A void function has no value, even if its last statement did
+                _ if func.return_type == Type::Void => Ok(Object::Null),
+                result => Ok(result),
+            }
+        } else if let Object::Builtin(func) = func {
+            // Builtins report failures as values; turn them into errors here
+            match func.call(args) {
+                Object::Error(msg) => Err(RuntimeError::Custom(msg)),
+                Object::Exit(code) => Err(RuntimeError::Exit(code)),
+                result => Ok(result),
             }
+        } else {
+            Err(RuntimeError::NotAFunction(func))
         }
     }
 
-    fn eval_prefix_expression(&self, operator: &Token, right: Object) -> Object {
-        match operator {
-            Token::Minus => match right {
-                Object::Integer(val) => Object::Integer(-val),
-                _ => Object::Error(format!("unknown operator: -{:?}", right)),
-            },
-            _ => Object::Error(format!("unknown operator: {:?}{:?}", operator, right)),
+    fn eval_prefix_expression(
+        &self,
+        operator: &Token,
+        right: Object,
+    ) -> Result<Object, RuntimeError> {
+        match (operator, right) {
+            (Token::Minus, Object::Integer(val)) => val
+                .checked_neg()
+                .map(Object::Integer)
+                .ok_or(RuntimeError::IntegerOverflow(Token::Minus)),
+            (Token::Minus, Object::Float(val)) => Ok(Object::Float(-val)),
+            (Token::Bang, right) => Ok(Object::Boolean(!right.is_truthy())),
+            (Token::Tilde, Object::Integer(val)) => Ok(Object::Integer(!val)),
+            (_, right) => Err(RuntimeError::UnknownPrefixOperator {
+                op: operator.clone(),
+                operand: right,
+            }),
         }
     }
 
-    fn eval_infix_expression(&self, operator: &Token, left: Object, right: Object) -> Object {
-        match (left, right) {
-            (Object::Integer(l), Object::Integer(r)) => match operator {
-                Token::Plus => Object::Integer(l + r),
-                Token::Minus => Object::Integer(l - r),
-                Token::Asterisk => Object::Integer(l * r),
-                Token::Slash => Object::Integer(l / r),
+    fn eval_infix_expression(
+        &self,
+        operator: &Token,
+        left: Object,
+        right: Object,
+    ) -> Result<Object, RuntimeError> {
+        // An integer mixed with a float is promoted to a float
+        let floats = match (&left, &right) {
+            (Object::Float(l), Object::Float(r)) => Some((*l, *r)),
+            (Object::Integer(l), Object::Float(r)) => Some((*l as f64, *r)),
+            (Object::Float(l), Object::Integer(r)) => Some((*l, *r as f64)),
+            _ => None,
+        };
+        if let Some((l, r)) = floats {
+            return self.eval_float_infix_expression(operator, l, r, left, right);
+        }
+
+        let result = match (&left, &right) {
+            (Object::Integer(l), Object::Integer(r)) => {
+                let (l, r) = (*l, *r);
+                // Rather than wrap, arithmetic that leaves i64 is an error,
+                // including i64::MIN / -1
+                let checked = |result: Option<i64>| {
+                    result
+                        .map(Object::Integer)
+                        .ok_or_else(|| RuntimeError::IntegerOverflow(operator.clone()))
+                };
+                match operator {
+                    Token::Plus => checked(l.checked_add(r))?,
+                    Token::Minus => checked(l.checked_sub(r))?,
+                    Token::Asterisk => checked(l.checked_mul(r))?,
+                    Token::Slash | Token::Percent if r == 0 => {
+                        return Err(RuntimeError::DivisionByZero);
+                    }
+                    Token::Slash => checked(l.checked_div(r))?,
+                    Token::Percent => checked(l.checked_rem(r))?,
+                    Token::LessThan => Object::Boolean(l < r),
+                    Token::GreaterThan => Object::Boolean(l > r),
+                    Token::LessEqual => Object::Boolean(l <= r),
+                    Token::GreaterEqual => Object::Boolean(l >= r),
+                    Token::Equal => Object::Boolean(l == r),
+                    Token::NotEqual => Object::Boolean(l != r),
+                    Token::Ampersand => Object::Integer(l & r),
+                    Token::Pipe => Object::Integer(l | r),
+                    Token::Caret => Object::Integer(l ^ r),
+                    // Shift counts outside 0..=63 are errors rather than silently masked.
+                    // `>>` is arithmetic, keeping the sign of negative numbers.
+                    Token::ShiftLeft | Token::ShiftRight if !(0..64).contains(&r) => {
+                        return Err(RuntimeError::ShiftOutOfRange(r));
+                    }
+                    Token::ShiftLeft => Object::Integer(l << r),
+                    Token::ShiftRight => Object::Integer(l >> r),
+                    _ => return Err(self.unknown_operator(operator, left, right)),
+                }
+            }
+            (Object::String(l), Object::String(r)) => match operator {
+                Token::Plus => Object::String(format!("{}{}", l, r)),
                 Token::LessThan => Object::Boolean(l < r),
                 Token::GreaterThan => Object::Boolean(l > r),
+                Token::LessEqual => Object::Boolean(l <= r),
+                Token::GreaterEqual => Object::Boolean(l >= r),
                 Token::Equal => Object::Boolean(l == r),
                 Token::NotEqual => Object::Boolean(l != r),
-                _ => Object::Error(format!("unknown operator: INTEGER {:?} INTEGER", operator)),
+                _ => return Err(self.unknown_operator(operator, left, right)),
             },
+            // Numbers are stringified when concatenated with a string
+            (Object::String(l), r @ (Object::Integer(_) | Object::Float(_)))
+                if *operator == Token::Plus =>
+            {
+                Object::String(format!("{}{}", l, r.inspect()))
+            }
+            (l @ (Object::Integer(_) | Object::Float(_)), Object::String(r))
+                if *operator == Token::Plus =>
+            {
+                Object::String(format!("{}{}", l.inspect(), r))
+            }
             (Object::Boolean(l), Object::Boolean(r)) => match operator {
                 Token::Equal => Object::Boolean(l == r),
                 Token::NotEqual => Object::Boolean(l != r),
-                _ => Object::Error(format!("unknown operator: BOOLEAN {:?} BOOLEAN", operator)),
+                _ => return Err(self.unknown_operator(operator, left, right)),
             },
             (l, r) => match operator {
                 Token::Equal => Object::Boolean(l == r),
                 Token::NotEqual => Object::Boolean(l != r),
-                _ => Object::Error(format!("type mismatch: {:?} {:?} {:?}", l, operator, r)),
+                _ => {
+                    return Err(RuntimeError::TypeMismatch {
+                        op: operator.clone(),
+                        lhs: left,
+                        rhs: right,
+                    });
+                }
             },
+        };
+        Ok(result)
+    }
+
+    // Float division by zero follows IEEE 754, giving inf or NaN rather than an error
+    fn eval_float_infix_expression(
+        &self,
+        operator: &Token,
+        l: f64,
+        r: f64,
+        left: Object,
+        right: Object,
+    ) -> Result<Object, RuntimeError> {
+        let result = match operator {
+            Token::Plus => Object::Float(l + r),
+            Token::Minus => Object::Float(l - r),
+            Token::Asterisk => Object::Float(l * r),
+            Token::Slash => Object::Float(l / r),
+            Token::Percent => Object::Float(l % r),
+            Token::LessThan => Object::Boolean(l < r),
+            Token::GreaterThan => Object::Boolean(l > r),
+            Token::LessEqual => Object::Boolean(l <= r),
+            Token::GreaterEqual => Object::Boolean(l >= r),
+            Token::Equal => Object::Boolean(l == r),
+            Token::NotEqual => Object::Boolean(l != r),
+            _ => return Err(self.unknown_operator(operator, left, right)),
+        };
+        Ok(result)
+    }
+
+    fn unknown_operator(&self, operator: &Token, left: Object, right: Object) -> RuntimeError {
+        RuntimeError::UnknownOperator {
+            op: operator.clone(),
+            lhs: left,
+            rhs: right,
+        }
+    }
+
+    // Checks that `index` is an integer within 0..len
+    fn array_index(&self, index: &Object, len: usize) -> Result<usize, RuntimeError> {
+        match index {
+            Object::Integer(i) if (0..len as i64).contains(i) => Ok(*i as usize),
+            Object::Integer(i) => Err(RuntimeError::IndexOutOfBounds { index: *i, len }),
+            other => Err(RuntimeError::InvalidIndex(other.clone())),
+        }
+    }
+}
+
+// The included file the top-level statement at `index` came from
+fn source_file(program: &Program, index: usize) -> Option<Symbol> {
+    program.files.get(index).cloned().flatten()
+}
+
+// Binds a declared name: in its slot if the resolver gave it one, else by name
+fn declare(
+    env: &Rc<RefCell<Environment>>,
+    slot: Option<usize>,
+    name: &Symbol,
+    val: Object,
+    constant: bool,
+) -> Result<Object, RuntimeError> {
+    let declared = match slot {
+        Some(index) => env.borrow_mut().define(index, name, val),
+        None => env.borrow_mut().set(Rc::clone(name), val),
+    };
+    // Only a constant already bound in the same scope refuses the name
+    if let Object::Error(_) = declared {
+        return Err(assignment_error(env, name));
+    }
+    if constant {
+        env.borrow_mut().make_constant(Rc::clone(name));
+    }
+    Ok(declared)
+}
+
+// The environment refuses an assignment to a name that isn't declared, or
+// whose nearest binding is a constant
+fn assignment_error(env: &Rc<RefCell<Environment>>, name: &str) -> RuntimeError {
+    match env.borrow().constant(name) {
+        Some(Constant::Declared) => RuntimeError::ConstantAssignment(name.to_string()),
+        Some(Constant::Builtin) => RuntimeError::BuiltinAssignment(name.to_string()),
+        None => RuntimeError::UndefinedIdentifier(name.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::resolver::resolve;
+    use crate::token::Lexer;
+
+    // Evaluates `input` both as parsed and with its variables resolved to
+    // slots, checking the two agree
+    fn eval(input: &str) -> Object {
+        let lexer = Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+        let mut program = parser.parse_program();
+        assert!(
+            parser.errors.is_empty(),
+            "parser errors: {:?}",
+            parser.errors
+        );
+        let result = eval_parsed(&program);
+        resolve(&mut program);
+        assert_eq!(
+            eval_parsed(&program).inspect(),
+            result.inspect(),
+            "resolved program differs: {}",
+            input
+        );
+        result
+    }
+
+    fn eval_parsed(program: &Program) -> Object {
+        let env = Rc::new(RefCell::new(Environment::new()));
+        env.borrow_mut().set_builtin("null", Object::Null);
+        // Errors become values so tests can compare the script-visible message
+        match Interpreter::new().eval_program(program, env) {
+            Ok(result) => result,
+            Err(RuntimeError::Exit(code)) => Object::Exit(code),
+            Err(err) => Object::Error(err.to_string()),
         }
     }
 
-    fn is_truthy(&self, obj: &Object) -> bool {
-        match obj {
-            Object::Null => false,
-            Object::Boolean(val) => *val,
-            Object::Integer(val) => *val != 0,
-            _ => true,
+    #[test]
+    fn test_left_associative_arithmetic() {
+        assert_eq!(eval("10 - 2 - 3;"), Object::Integer(5));
+        assert_eq!(eval("8 / 4 / 2;"), Object::Integer(1));
+        assert_eq!(eval("10 - 2 * 3;"), Object::Integer(4));
+        assert_eq!(eval("100 % 7 % 3;"), Object::Integer(2));
+        assert_eq!(eval("1 - 2 + 3;"), Object::Integer(2));
+    }
+
+    #[test]
+    fn test_function_values() {
+        let defs = "int sq(int x) { return x * x; }
+            int neg(int x) { return -x; }
+            int apply(int f, int x) { return f(x); }
+            int twice(int f) {
+                int g(int x) { return f(f(x)); }
+                return g;
+            }";
+        let run = |src: &str| eval(&format!("{} {}", defs, src));
+        assert_eq!(run("apply(sq, 3);"), Object::Integer(9));
+        assert_eq!(run("int h = neg; h(4);"), Object::Integer(-4));
+        assert_eq!(run("int fs = [sq, neg]; fs[1](5);"), Object::Integer(-5));
+        assert_eq!(run("twice(sq)(3);"), Object::Integer(81));
+        assert_eq!(run("apply(twice(neg), 7);"), Object::Integer(7));
+        assert_eq!(
+            run("int n = 2; n(1);"),
+            Object::Error("not a function: INTEGER".to_string())
+        );
+    }
+
+    #[test]
+    fn test_prototypes() {
+        let input = "int is_odd(int n);
+            int is_even(int n) { return n == 0 ? 1 : is_odd(n - 1); }
+            int is_odd(int n) { return n == 0 ? 0 : is_even(n - 1); }
+            is_even(10) * 10 + is_odd(7);";
+        assert_eq!(eval(input), Object::Integer(11));
+
+        // A prototype does nothing, even for a function that's never defined
+        assert_eq!(eval("int f(int); 1;"), Object::Integer(1));
+        assert_eq!(
+            eval("int f(int); f(1);"),
+            Object::Error("identifier not found: f".to_string())
+        );
+    }
+
+    #[test]
+    fn test_functions_are_hoisted() {
+        let input = "int x = twice(21);
+            int twice(int n) { return n * 2; }
+            x;";
+        assert_eq!(eval(input), Object::Integer(42));
+        // Mutual recursion needs no prototypes
+        let input = "int r = is_even(10) * 10 + is_odd(7);
+            int is_even(int n) { return n == 0 ? 1 : is_odd(n - 1); }
+            int is_odd(int n) { return n == 0 ? 0 : is_even(n - 1); }
+            r;";
+        assert_eq!(eval(input), Object::Integer(11));
+        // A definition is still the value of a program that ends with one
+        assert!(matches!(
+            eval("1; int f() { return 1; }"),
+            Object::Function(_)
+        ));
+
+        let input = "int f() { return 1; }\nputs(f());\nint f() { return 2; }";
+        assert_eq!(
+            runtime_error(input),
+            RuntimeError::FunctionRedefined {
+                name: "f".to_string(),
+                first: Position {
+                    file: None,
+                    line: 1
+                }
+            }
+        );
+        let program = Parser::new(Lexer::new(input)).parse_program();
+        let buffer = Rc::new(RefCell::new(Vec::new()));
+        let mut interpreter = Interpreter::with_output(buffer.clone());
+        let env = interpreter.global_environment();
+        let err = interpreter.eval_program(&program, env).unwrap_err();
+        assert_eq!(
+            interpreter.error_report(&err),
+            "runtime error at line 3: function f is already defined on line 1"
+        );
+        // Nothing ran
+        assert!(buffer.borrow().is_empty());
+
+        // Separate programs in one environment, like REPL inputs, can redefine
+        let mut interpreter = Interpreter::new();
+        let env = interpreter.global_environment();
+        for (input, want) in [
+            ("int f() { return 1; } f();", 1),
+            ("int f() { return 2; } f();", 2),
+        ] {
+            let program = Parser::new(Lexer::new(input)).parse_program();
+            let result = interpreter.eval_program(&program, Rc::clone(&env));
+            assert_eq!(result, Ok(Object::Integer(want)));
         }
     }
 
-    fn is_error(&self, obj: &Object) -> bool {
-        matches!(obj, Object::Error(_))
+    #[test]
+    fn test_closures_capture_their_environment() {
+        let input = "int make_counter(int start) {
+                int count = start;
+                int next() {
+                    count += 1;
+                    return count;
+                }
+                return next;
+            }
+            int a = make_counter(0);
+            int b = make_counter(10);
+            a();
+            a();
+            b();
+            a() * 100 + b();";
+        assert_eq!(eval(input), Object::Integer(312));
+    }
+
+    #[test]
+    fn test_scopes_are_freed() {
+        let input = "int work() {
+                int data = [1, 2, 3];
+                watch(data);
+                int helper() { return data; }
+                return 0;
+            }
+            int make(int n) {
+                int get() { return n; }
+                return get;
+            }
+            work();
+            int g = make(7);
+            g();";
+        let program = Parser::new(Lexer::new(input)).parse_program();
+        let mut interpreter = Interpreter::new();
+        let env = interpreter.global_environment();
+
+        // Watches an array through a weak pointer, to see when it's freed
+        let watched = Rc::new(RefCell::new(vec![]));
+        let watcher = Rc::clone(&watched);
+        env.borrow_mut().register_builtin("watch", move |args| {
+            if let Object::Array(array) = &args[0] {
+                watcher.borrow_mut().push(Rc::downgrade(array));
+            }
+            Object::Null
+        });
+
+        assert_eq!(
+            interpreter.eval_program(&program, Rc::clone(&env)),
+            Ok(Object::Integer(7))
+        );
+        // work's scope, and the array in it, went away when the call ended,
+        // even though the helper defined there pointed back at it
+        assert!(watched.borrow()[0].upgrade().is_none());
+
+        // The closure bound to g is all that keeps make's scope alive
+        let Some(Object::Function(g)) = env.borrow().get("g") else {
+            panic!("g is not a function");
+        };
+        let scope = Rc::downgrade(&g.env().unwrap());
+        drop(g);
+        assert!(scope.upgrade().is_some());
+        env.borrow_mut().assign("g", Object::Null);
+        assert!(scope.upgrade().is_none());
+
+        // Functions no longer keep the global scope alive either
+        let globals = Rc::downgrade(&env);
+        drop(env);
+        assert!(globals.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_modulo() {
+        assert_eq!(eval("17 % 5;"), Object::Integer(2));
+        assert_eq!(eval("2 + 9 % 4;"), Object::Integer(3));
+        assert_eq!(eval("9 % 3 == 0;"), Object::Boolean(true));
+        assert_eq!(
+            eval("5 % 0;"),
+            Object::Error("division by zero".to_string())
+        );
+    }
+
+    #[test]
+    fn test_less_greater_equal() {
+        assert_eq!(eval("3 <= 3;"), Object::Boolean(true));
+        assert_eq!(eval("4 <= 3;"), Object::Boolean(false));
+        assert_eq!(eval("3 >= 4;"), Object::Boolean(false));
+        assert_eq!(eval("-1 >= -1;"), Object::Boolean(true));
+        assert_eq!(eval("0 < -1;"), Object::Boolean(false));
+    }
+
+    #[test]
+    fn test_logical_operators() {
+        assert_eq!(eval("1 > 0 && 2 > 1;"), Object::Boolean(true));
+        assert_eq!(eval("1 > 0 && 0;"), Object::Boolean(false));
+        assert_eq!(eval("0 || 5;"), Object::Boolean(true));
+        assert_eq!(eval("0 || 0;"), Object::Boolean(false));
+        // 1 == 1 || 0 && 0 groups as 1 == 1 || (0 && 0)
+        assert_eq!(eval("1 == 1 || 0 && 0;"), Object::Boolean(true));
+    }
+
+    #[test]
+    fn test_logical_operators_short_circuit() {
+        // The right operand would be an error if it were evaluated
+        assert_eq!(eval("0 && missing;"), Object::Boolean(false));
+        assert_eq!(eval("1 || missing;"), Object::Boolean(true));
+        assert_eq!(
+            eval("1 && missing;"),
+            Object::Error("identifier not found: missing".to_string())
+        );
+    }
+
+    #[test]
+    fn test_bang_operator() {
+        assert_eq!(eval("!true;"), Object::Boolean(false));
+        assert_eq!(eval("!false;"), Object::Boolean(true));
+        assert_eq!(eval("!0;"), Object::Boolean(true));
+        assert_eq!(eval("!5;"), Object::Boolean(false));
+        assert_eq!(eval("!null;"), Object::Boolean(true));
+        assert_eq!(eval("!!5;"), Object::Boolean(true));
+        assert_eq!(eval("!(1 == 2);"), Object::Boolean(true));
+    }
+
+    #[test]
+    fn test_assignment() {
+        assert_eq!(eval("int x = 1; x = 5; x;"), Object::Integer(5));
+        assert_eq!(
+            eval("int a = 1; int b = 2; a = b = 7; a + b;"),
+            Object::Integer(14)
+        );
+        assert_eq!(
+            eval("int i = 0; int sum = 0; while (i < 5) { sum = sum + i; i = i + 1; } sum;"),
+            Object::Integer(10)
+        );
+        assert_eq!(
+            eval("int n = 0; int bump() { n = n + 1; return n; } bump(); bump();"),
+            Object::Integer(2)
+        );
+    }
+
+    #[test]
+    fn test_assignment_to_undeclared() {
+        assert_eq!(
+            eval("x = 5;"),
+            Object::Error("identifier not found: x".to_string())
+        );
+    }
+
+    #[test]
+    fn test_global_variables() {
+        // Assignment from inside a function walks out to the global, with or
+        // without the resolver, rather than creating a local copy
+        let input = "int counter = 0;
+            void bump() { counter = counter + 1; }
+            int main() {
+                bump();
+                bump();
+                bump();
+                return counter == 3;
+            }";
+        for resolved in [false, true] {
+            let mut program = Parser::new(Lexer::new(input)).parse_program();
+            if resolved {
+                resolve(&mut program);
+            }
+            let mut interpreter = Interpreter::new();
+            let env = interpreter.global_environment();
+            interpreter.eval_program(&program, Rc::clone(&env)).unwrap();
+            assert_eq!(
+                interpreter.call_main(Rc::clone(&env)),
+                Some(Ok(Object::Boolean(true)))
+            );
+            assert_eq!(env.borrow().get("counter"), Some(Object::Integer(3)));
+        }
+
+        // Compound assignment and ++ reach it too, from nested blocks
+        assert_eq!(
+            eval(
+                "int total = 0;
+                void add(int n) { while (n > 0) { total += n; n--; } total++; }
+                add(3); add(1); total;"
+            ),
+            Object::Integer(9)
+        );
+
+        // A parameter or local of the same name shadows the global: the
+        // function assigns its own variable and the global is left alone
+        assert_eq!(
+            eval(
+                "int x = 10;
+                int set(int x) { x = 99; return x; }
+                int local() { int x = 1; x += 5; return x; }
+                set(1) * 1000 + local() * 100 + x;"
+            ),
+            Object::Integer(99610)
+        );
+    }
+
+    #[test]
+    fn test_constants() {
+        assert_eq!(
+            eval("const int MAX = 100; int f() { return MAX * 2; } f();"),
+            Object::Integer(200)
+        );
+
+        // Every way of changing a constant fails, from any scope, resolved or not
+        for (input, name) in [
+            ("const int MAX = 100; MAX = 5;", "MAX"),
+            ("const int MAX = 100; MAX += 5;", "MAX"),
+            ("const int MAX = 100; MAX++;", "MAX"),
+            ("const int MAX = 100; void f() { MAX = 1; } f();", "MAX"),
+            ("const int MAX = 100; int MAX = 5;", "MAX"),
+            (
+                "void f() { const int g = 1; int g() { return 1; } } f();",
+                "g",
+            ),
+            ("void f() { const int a = 1, b = 2; { b--; } } f();", "b"),
+            ("void f() { const int a = 1; int a = 2; } f();", "a"),
+        ] {
+            assert_eq!(
+                eval(input),
+                Object::Error(format!("cannot assign to constant {}", name)),
+                "{}",
+                input
+            );
+        }
+        // A new scope can declare the name again, and the constant is untouched
+        assert_eq!(
+            eval(
+                "const int N = 3;
+                int twice(int N) { N = N * 2; return N; }
+                int f() { int N = 10; N++; return N; }
+                twice(5) + f() + N;"
+            ),
+            Object::Integer(24)
+        );
+
+        // Arrays held in constants can still be modified in place
+        assert_eq!(
+            eval("const int xs = [1, 2]; xs[0] = 5; xs[0] + xs[1];"),
+            Object::Integer(7)
+        );
+    }
+
+    #[test]
+    fn test_boolean_literals() {
+        assert_eq!(
+            eval("int x = 0; if (true) { x = 1; } x;"),
+            Object::Integer(1)
+        );
+        assert_eq!(eval("int b = false; b;"), Object::Boolean(false));
+        assert_eq!(eval("!true;"), Object::Boolean(false));
+        assert_eq!(
+            eval("true == !false && 1 < 2 == true;"),
+            Object::Boolean(true)
+        );
+
+        // They are literals, not bindings an environment has to provide
+        let program = Parser::new(Lexer::new("true || false;")).parse_program();
+        let env = Rc::new(RefCell::new(Environment::new()));
+        assert_eq!(
+            Interpreter::new().eval_program(&program, env),
+            Ok(Object::Boolean(true))
+        );
+    }
+
+    #[test]
+    fn test_builtins_are_protected() {
+        // At the top level, preset globals and builtins can't be replaced
+        for (input, name) in [
+            ("null = 1;", "null"),
+            ("int puts = 5;", "puts"),
+            ("stderr = stdout;", "stderr"),
+            ("int x = 1, strlen = 2;", "strlen"),
+            ("int max(int a, int b) { return a; }", "max"),
+            ("stdout = null;", "stdout"),
+            ("ENOENT = 3;", "ENOENT"),
+            ("ARGC++;", "ARGC"),
+            ("void f() { null = 0; } f();", "null"),
+            ("void f() { puts = 0; } f();", "puts"),
+        ] {
+            assert_eq!(
+                runtime_error(input),
+                RuntimeError::BuiltinAssignment(name.to_string()),
+                "{}",
+                input
+            );
+        }
+        assert_eq!(
+            runtime_error("int puts = 5;").to_string(),
+            "cannot assign to builtin puts; only a parameter or local variable can shadow it"
+        );
+
+        // A parameter or local variable can shadow one inside its own scope,
+        // leaving it intact everywhere else
+        let input = "int pick(int max, int min) { return max - min; }
+            int f() { int puts = 40; puts += 2; return puts; }
+            int g() { { int strlen = 1; } return strlen(\"abc\"); }
+            void h() { int null = 0; }
+            h();
+            pick(9, 2) * 1000 + f() * 10 + g() + (null == null ? 1 : 0);";
+        for resolved in [false, true] {
+            let mut program = Parser::new(Lexer::new(input)).parse_program();
+            if resolved {
+                resolve(&mut program);
+            }
+            let mut interpreter = Interpreter::with_output(Rc::new(RefCell::new(vec![])));
+            let env = interpreter.global_environment();
+            assert_eq!(
+                interpreter.eval_program(&program, env),
+                Ok(Object::Integer(7424))
+            );
+        }
+    }
+
+    #[test]
+    fn test_for_loop() {
+        assert_eq!(
+            eval("int sum = 0; for (int i = 0; i < 5; i = i + 1) { sum = sum + i; } sum;"),
+            Object::Integer(10)
+        );
+        assert_eq!(
+            eval("int i = 0; for (; i < 3;) { i = i + 1; } i;"),
+            Object::Integer(3)
+        );
+        assert_eq!(
+            eval("int f() { for (;;) { return 7; } } f();"),
+            Object::Integer(7)
+        );
+    }
+
+    #[test]
+    fn test_for_loop_init_is_scoped() {
+        assert_eq!(
+            eval("for (int i = 0; i < 1; i = i + 1) { } i;"),
+            Object::Error("identifier not found: i".to_string())
+        );
+    }
+
+    #[test]
+    fn test_block_scope() {
+        assert_eq!(
+            eval("if (true) { int t = 1; } t;"),
+            Object::Error("identifier not found: t".to_string())
+        );
+        assert_eq!(eval("int x = 1; { int x = 2; } x;"), Object::Integer(1));
+        assert_eq!(
+            eval("int n = 0; while (n < 3) { int step = 1; n = n + step; } n;"),
+            Object::Integer(3)
+        );
+
+        // A declaration as an unbraced body is scoped to that body too
+        for input in [
+            "int x = 1; if (1) int x = 2; x;",
+            "int x = 1; if (0) x; else int x = 2; x;",
+            "int x = 1; int i = 0; while (i < 1) int x = i++; x;",
+            "int x = 1; int i = 0; do int x = i++; while (i < 2); x;",
+            "int x = 1; for (int i = 0; i < 2; i++) int x = i, y = x; x;",
+            "int x = 1; if (1) int x() { return 2; } x;",
+        ] {
+            assert_eq!(eval(input), Object::Integer(1), "{}", input);
+        }
+        assert_eq!(
+            eval("int i = 0; while (i < 1) int y = i++; y;"),
+            Object::Error("identifier not found: y".to_string())
+        );
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        assert_eq!(
+            eval("5 / 0;"),
+            Object::Error("division by zero".to_string())
+        );
+        assert_eq!(
+            eval("5 / (2 - 2);"),
+            Object::Error("division by zero".to_string())
+        );
+        assert_eq!(
+            eval("int f() { int x = 1 / 0; return 1; } f();"),
+            Object::Error("division by zero".to_string())
+        );
+    }
+
+    #[test]
+    fn test_string_operators() {
+        assert_eq!(
+            eval("\"foo\" + \"bar\";"),
+            Object::String("foobar".to_string())
+        );
+        assert_eq!(
+            eval("\"count: \" + 3;"),
+            Object::String("count: 3".to_string())
+        );
+        assert_eq!(eval("\"abc\" < \"abd\";"), Object::Boolean(true));
+        assert_eq!(eval("\"b\" > \"abc\";"), Object::Boolean(true));
+        assert_eq!(eval("\"a\" == \"a\";"), Object::Boolean(true));
+        assert!(matches!(eval("\"a\" < 1;"), Object::Error(_)));
+        assert!(matches!(eval("\"a\" - \"b\";"), Object::Error(_)));
+    }
+
+    #[test]
+    fn test_uninitialized_declaration() {
+        assert_eq!(eval("int x; x;"), Object::Integer(0));
+        assert_eq!(eval("int x; x = 5; x;"), Object::Integer(5));
+    }
+
+    #[test]
+    fn test_multiple_declarators() {
+        assert_eq!(eval("int a = 1, b = a + 1; b;"), Object::Integer(2));
+        assert_eq!(eval("int a = 1, b, c = 3; a + b + c;"), Object::Integer(4));
+    }
+
+    #[test]
+    fn test_max_recursion_depth() {
+        // Test threads get a small stack; the CLI also runs on a large one
+        let result = std::thread::Builder::new()
+            .stack_size(256 * 1024 * 1024)
+            .spawn(|| {
+                assert_eq!(
+                    eval("int f(int n) { return f(n + 1); } f(0);"),
+                    Object::Error("maximum recursion depth exceeded".to_string())
+                );
+                // Recursion below the limit still works
+                assert_eq!(
+                    eval("int f(int n) { if (n == 0) { return 0; } return 1 + f(n - 1); } f(900);"),
+                    Object::Integer(900)
+                );
+            })
+            .unwrap()
+            .join();
+        assert!(result.is_ok());
+    }
+
+    // Runs `input` and returns the runtime error it raises
+    fn runtime_error(input: &str) -> RuntimeError {
+        let program = Parser::new(Lexer::new(input)).parse_program();
+        let mut interpreter = Interpreter::new();
+        let env = interpreter.global_environment();
+        interpreter.eval_program(&program, env).unwrap_err()
+    }
+
+    #[test]
+    fn test_runtime_error_variants() {
+        assert_eq!(
+            runtime_error("x;"),
+            RuntimeError::UndefinedIdentifier("x".to_string())
+        );
+        assert_eq!(
+            runtime_error("1 + \"a\" - true;"),
+            RuntimeError::TypeMismatch {
+                op: Token::Minus,
+                lhs: Object::String("1a".to_string()),
+                rhs: Object::Boolean(true),
+            }
+        );
+        assert_eq!(
+            runtime_error("1 + \"a\" - true;").to_string(),
+            "type mismatch: STRING - BOOLEAN"
+        );
+        assert_eq!(runtime_error("5 % 0;"), RuntimeError::DivisionByZero);
+        assert_eq!(
+            runtime_error("int f(int a) { return a; } f();"),
+            RuntimeError::WrongArity { want: 1, got: 0 }
+        );
+        assert_eq!(
+            runtime_error("strlen(1);"),
+            RuntimeError::Custom("strlen: argument 1 must be a string, got 1".to_string())
+        );
+        assert_eq!(
+            runtime_error("\"a\" - \"b\";").to_string(),
+            "unknown operator: STRING - STRING"
+        );
+    }
+
+    #[test]
+    fn test_custom_max_depth() {
+        let mut parser = Parser::new(Lexer::new(
+            "int f(int n) { if (n == 0) { return 0; } return f(n - 1); } f(10);",
+        ));
+        let program = parser.parse_program();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_max_depth(5);
+        let env = Rc::new(RefCell::new(Environment::new()));
+        assert_eq!(
+            interpreter.eval_program(&program, env),
+            Err(RuntimeError::RecursionLimit)
+        );
+    }
+
+    #[test]
+    fn test_step_limit_stops_infinite_loops() {
+        for input in ["while (1) {}", "for (;;) {}", "do {} while (true);"] {
+            let program = Parser::new(Lexer::new(input)).parse_program();
+            let mut interpreter = Interpreter::new();
+            interpreter.set_max_steps(1000);
+            let env = interpreter.global_environment();
+            assert_eq!(
+                interpreter.eval_program(&program, env),
+                Err(RuntimeError::StepLimit),
+                "{}",
+                input
+            );
+        }
+
+        // A program that finishes within the limit is unaffected
+        let program = Parser::new(Lexer::new("int i = 0; while (i < 10) i++; i;")).parse_program();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_max_steps(100);
+        let env = interpreter.global_environment();
+        assert_eq!(
+            interpreter.eval_program(&program, env),
+            Ok(Object::Integer(10))
+        );
+    }
+
+    #[test]
+    fn test_timeout_stops_infinite_loops() {
+        let program = Parser::new(Lexer::new("while (1) {}")).parse_program();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_timeout(Duration::from_millis(20));
+        let env = interpreter.global_environment();
+        let err = interpreter.eval_program(&program, env).unwrap_err();
+        assert_eq!(err.to_string(), "execution timed out after 20 ms");
+    }
+
+    // Run with `cargo test --release -- --ignored --nocapture` to time recursive calls
+    #[test]
+    #[ignore]
+    fn bench_recursive_fib() {
+        let mut program = Parser::new(Lexer::new(
+            "int fib(int n) { if (n < 2) { return n; } return fib(n - 1) + fib(n - 2); } fib(25);",
+        ))
+        .parse_program();
+        let start = std::time::Instant::now();
+        assert_eq!(eval_parsed(&program), Object::Integer(75025));
+        println!("fib(25) took {:?}", start.elapsed());
+
+        resolve(&mut program);
+        let start = std::time::Instant::now();
+        assert_eq!(eval_parsed(&program), Object::Integer(75025));
+        println!("fib(25) resolved took {:?}", start.elapsed());
+    }
+
+    // A 500-statement function defined inside another, so calling the outer
+    // one in a loop also re-runs the definition each time
+    #[test]
+    #[ignore]
+    fn bench_large_function() {
+        let body = "x = x + 1;\n".repeat(500);
+        let input = format!(
+            "int outer() {{ int big(int x) {{ {} return x; }} return big(0); }}
+            int total = 0;
+            for (int i = 0; i < 200; i++) total += outer();
+            total;",
+            body
+        );
+        let program = Parser::new(Lexer::new(&input)).parse_program();
+        let start = std::time::Instant::now();
+        assert_eq!(eval_parsed(&program), Object::Integer(100_000));
+        println!(
+            "200 calls of a 500-statement function took {:?}",
+            start.elapsed()
+        );
+    }
+
+    #[test]
+    fn test_exit_unwinds() {
+        let mut parser = Parser::new(Lexer::new(
+            "int n = 0; int f() { while (true) { n = n + 1; int x = exit(3) + 1; } } f(); n = 100;",
+        ));
+        let program = parser.parse_program();
+        let mut interpreter = Interpreter::new();
+        let env = interpreter.global_environment();
+        assert_eq!(
+            interpreter.eval_program(&program, Rc::clone(&env)),
+            Err(RuntimeError::Exit(3))
+        );
+        assert_eq!(env.borrow().get("n"), Some(Object::Integer(1)));
+    }
+
+    #[test]
+    fn test_char_literals() {
+        assert_eq!(eval("'A';"), Object::String("A".to_string()));
+        assert_eq!(eval("\"\\n\" == '\\n';"), Object::Boolean(true));
+        assert_eq!(eval("'a' < 'b';"), Object::Boolean(true));
+    }
+
+    #[test]
+    fn test_char_declarations() {
+        let input = "char c = 'a'; int twice(char x, int n) { return x + x; } twice(c, 2);";
+        assert_eq!(eval(input), Object::String("aa".to_string()));
+    }
+
+    #[test]
+    fn test_void_function_returns_null() {
+        assert_eq!(eval("void f() { 5; } f();"), Object::Null);
+        assert_eq!(
+            eval("int main(void) { return 3; } main();"),
+            Object::Integer(3)
+        );
+    }
+
+    #[test]
+    fn test_do_while() {
+        // The body runs once even though the condition starts out false
+        assert_eq!(
+            eval("int n = 0; do { n = n + 1; } while (n > 5); n;"),
+            Object::Integer(1)
+        );
+        assert_eq!(
+            eval("int n = 0; do n = n + 2; while (n < 7); n;"),
+            Object::Integer(8)
+        );
+        assert_eq!(
+            eval("int f() { do { return 3; } while (1); } f();"),
+            Object::Integer(3)
+        );
+    }
+
+    #[test]
+    fn test_switch() {
+        let classify = "int classify(int x) {
+            switch (x) {
+                case 1: return \"one\";
+                case 2:
+                case 3: return \"few\";
+                case 'a': return \"letter\";
+                case -1: return \"negative\";
+                default: return \"many\";
+            }
+        }";
+        for (arg, expected) in [
+            ("1", "one"),
+            ("2", "few"),
+            ("3", "few"),
+            ("'a'", "letter"),
+            ("-1", "negative"),
+            ("9", "many"),
+        ] {
+            let input = format!("{} classify({});", classify, arg);
+            assert_eq!(eval(&input), Object::String(expected.to_string()));
+        }
+
+        // Only the matching case runs, with or without a trailing break
+        let input = "int n = 0; switch (\"b\") { case \"a\": n = 1; break; case \"b\": n = n + 2; break; case \"c\": n = n + 4; } n;";
+        assert_eq!(eval(input), Object::Integer(2));
+        assert_eq!(
+            eval("int n = 0; switch (5) { case 1: n = 1; } n;"),
+            Object::Integer(0)
+        );
+    }
+
+    #[test]
+    fn test_compound_assignment() {
+        assert_eq!(
+            eval("int x = 10; x += 5; x -= 3; x *= 2; x;"),
+            Object::Integer(24)
+        );
+        assert_eq!(
+            eval("int a = 1; int b = 2; a += b += 1; a + b * 10;"),
+            Object::Integer(34)
+        );
+        assert_eq!(
+            eval("int s = \"a\"; s += \"b\"; s;"),
+            Object::String("ab".to_string())
+        );
+        assert!(matches!(eval("int x = 1; x /= 0;"), Object::Error(_)));
+    }
+
+    // Like `eval`, with the stdlib registered
+    fn eval_with_stdlib(input: &str) -> Object {
+        let program = Parser::new(Lexer::new(input)).parse_program();
+        let mut interpreter = Interpreter::new();
+        let env = interpreter.global_environment();
+        match interpreter.eval_program(&program, env) {
+            Ok(result) => result,
+            Err(err) => Object::Error(err.to_string()),
+        }
+    }
+
+    fn array(elements: Vec<Object>) -> Object {
+        Object::Array(Rc::new(RefCell::new(elements)))
+    }
+
+    #[test]
+    fn test_arrays() {
+        assert_eq!(
+            eval("[1, 2 * 3, \"x\"];"),
+            array(vec![
+                Object::Integer(1),
+                Object::Integer(6),
+                Object::String("x".to_string())
+            ])
+        );
+        assert_eq!(eval("[];"), array(vec![]));
+        assert_eq!(eval("int a = [10, 20, 30]; a[1 + 1];"), Object::Integer(30));
+        assert_eq!(
+            eval("int a = [1, 2]; a[0] = 5; a[1] += 10; a;"),
+            array(vec![Object::Integer(5), Object::Integer(12)])
+        );
+        assert_eq!(
+            eval("int grid = [[1, 2], [3, 4]]; grid[1][0] = 7; grid[1][0] + grid[0][1];"),
+            Object::Integer(9)
+        );
+        assert_eq!(eval("[1, 2] == [1, 2];"), Object::Boolean(true));
+    }
+
+    #[test]
+    fn test_arrays_containing_themselves() {
+        let result = eval_with_stdlib("int a = [1]; push(a, a); push(a, [\"s\", a]); a;");
+        assert_eq!(result.inspect(), "[1, [...], [s, [...]]]");
+        assert_eq!(result.repr(), "[1, [...], [\"s\", [...]]]");
+
+        // Comparing them ends too, whether the cycles are the same array or not
+        assert_eq!(
+            eval_with_stdlib(
+                "int a = [1]; push(a, a);
+                int b = [1]; push(b, b);
+                int c = [2]; push(c, c);
+                [a == a, a == b, a == c, a[1] == b];"
+            )
+            .inspect(),
+            "[true, true, false, true]"
+        );
+    }
+
+    #[test]
+    fn test_temp_files_kept_open_by_a_cycle_are_removed() {
+        let temp_path = |file: &Object| match file {
+            Object::File(handle) => handle.borrow().temp_path().unwrap().to_path_buf(),
+            other => panic!("not a file: {:?}", other),
+        };
+        let program =
+            Parser::new(Lexer::new("int a = [tmpfile()]; push(a, a); a[0];")).parse_program();
+
+        let mut interpreter = Interpreter::new();
+        let env = interpreter.global_environment();
+        let file = interpreter.eval_program(&program, Rc::clone(&env)).unwrap();
+        let path = temp_path(&file);
+        drop((env, file));
+        assert!(path.exists());
+        interpreter.remove_temp_files();
+        assert!(!path.exists());
+
+        // Dropping the interpreter does the same
+        let path = temp_path(&eval_with_stdlib("int a = [tmpfile()]; push(a, a); a[0];"));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_compound_index_assignment_evaluates_once() {
+        assert_eq!(
+            eval("int a = [1, 2, 3]; int i = 0; a[i++] += 5; [i, a];"),
+            array(vec![
+                Object::Integer(1),
+                array(vec![
+                    Object::Integer(6),
+                    Object::Integer(2),
+                    Object::Integer(3)
+                ])
+            ])
+        );
+        assert_eq!(
+            eval(
+                "int calls = 0;
+                int at() { calls++; return 1; }
+                int grid = [[0, 0], [0, 0]];
+                int rows() { calls++; return grid; }
+                rows()[at()][at()] *= 2;
+                grid[1][1] -= 4;
+                calls * 100 + grid[1][1];"
+            ),
+            Object::Integer(296)
+        );
+        assert_eq!(
+            eval("int a = [1]; a[0] += \"x\"; a[0];"),
+            Object::String("1x".to_string())
+        );
+        assert_eq!(
+            eval("int s = \"ab\"; s[0] += \"c\";"),
+            Object::Error("strings are immutable; build a new string instead".to_string())
+        );
+    }
+
+    #[test]
+    fn test_arrays_are_shared() {
+        let input = "void add(int xs, int v) { push(xs, v); xs[0] = 0; }
+            int a = [1];
+            add(a, 2);
+            int b = a;
+            push(b, 3);
+            a;";
+        assert_eq!(
+            eval_with_stdlib(input),
+            array(vec![
+                Object::Integer(0),
+                Object::Integer(2),
+                Object::Integer(3)
+            ])
+        );
+        assert_eq!(
+            eval_with_stdlib("int a = [1, [2]]; push(a[1], 3); int n = pop(a); len(n) + len(a);"),
+            Object::Integer(3)
+        );
+    }
+
+    #[test]
+    fn test_string_indexing() {
+        assert_eq!(eval("\"héllo\"[1];"), Object::String("é".to_string()));
+        assert_eq!(
+            eval("int s = \"abc\"; s[0] + s[2];"),
+            Object::String("ac".to_string())
+        );
+        assert_eq!(eval("\"abc\"[2] == 'c';"), Object::Boolean(true));
+        assert_eq!(
+            eval("\"abc\"[3];"),
+            Object::Error("index 3 out of bounds for length 3".to_string())
+        );
+        assert_eq!(
+            eval("\"abc\"[-1];"),
+            Object::Error("index -1 out of bounds for length 3".to_string())
+        );
+        assert_eq!(
+            eval("int s = \"abc\"; s[0] = \"x\";"),
+            Object::Error("strings are immutable; build a new string instead".to_string())
+        );
+    }
+
+    #[test]
+    fn test_failed_assert_stops_the_program() {
+        let input = "
+            int n = 0;
+            int check(int x) {
+                assert(x < 2, \"too big\");
+                return x;
+            }
+            while (1) {
+                check(n);
+                n = n + 1;
+            }
+            n;
+        ";
+        assert_eq!(
+            eval_with_stdlib(input),
+            Object::Error("assertion failed: too big".to_string())
+        );
+        assert_eq!(
+            eval_with_stdlib("int f() { assert_eq(1 + 1, 3); return 1; } f(); 2;"),
+            Object::Error("assertion failed: expected 3, got 2".to_string())
+        );
+    }
+
+    #[test]
+    fn test_typeof_functions() {
+        assert_eq!(
+            eval_with_stdlib("int f() { return 1; } typeof(f);"),
+            Object::String("function".to_string())
+        );
+        assert_eq!(
+            eval_with_stdlib("typeof(puts);"),
+            Object::String("builtin".to_string())
+        );
+    }
+
+    #[test]
+    fn test_trace() {
+        let input = "int sq(int x) {\n    return x * x;\n}\nint i = 0;\nfor (; i < 2; i++) {\n    sq(i);\n}\ndo i--; while (i);";
+        let program = Parser::new(Lexer::new(input)).parse_program();
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        let mut interpreter = Interpreter::new();
+        interpreter.set_trace(sink.clone());
+        let env = interpreter.global_environment();
+        interpreter.eval_program(&program, env).unwrap();
+
+        let trace = String::from_utf8(sink.borrow().clone()).unwrap();
+        let expected = [
+            "TRACE line 1: Function int sq(int x)",
+            "TRACE line 4: Let int i = 0;",
+            "TRACE line 5: For (; (i < 2); (i++))",
+            "TRACE line 6: sq(i);",
+            "TRACE line 2:   Return (x * x);",
+            "TRACE line 5: For (; (i < 2); (i++))",
+            "TRACE line 6: sq(i);",
+            "TRACE line 2:   Return (x * x);",
+            "TRACE line 5: For (; (i < 2); (i++))",
+            "TRACE line 8: Do ... While (i);",
+            "TRACE line 8: (i--);",
+            "TRACE line 8: Do ... While (i);",
+            "TRACE line 8: (i--);",
+            "TRACE line 8: Do ... While (i);",
+        ];
+        assert_eq!(trace.lines().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn test_trace_stops_at_the_step_limit() {
+        let program =
+            Parser::new(Lexer::new("int i = 0;\nwhile (1) {\n    i++;\n}")).parse_program();
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        let mut interpreter = Interpreter::new();
+        interpreter.set_trace(sink.clone());
+        interpreter.set_max_steps(4);
+        let env = interpreter.global_environment();
+        assert_eq!(
+            interpreter.eval_program(&program, env),
+            Err(RuntimeError::StepLimit)
+        );
+
+        // The statement that ran out of steps is the last one traced
+        let trace = String::from_utf8(sink.borrow().clone()).unwrap();
+        assert_eq!(trace.lines().last(), Some("TRACE line 3: (i++);"));
+    }
+
+    #[test]
+    fn test_profiling_counts_calls() {
+        let input = "int fib(int n) { if (n < 2) { return n; } return fib(n - 1) + fib(n - 2); }
+            fib(10);
+            strlen(\"abc\");";
+        let program = Parser::new(Lexer::new(input)).parse_program();
+        let mut interpreter = Interpreter::new();
+        interpreter.enable_profiling();
+        let env = interpreter.global_environment();
+        interpreter.eval_program(&program, env).unwrap();
+
+        let mut calls: Vec<(&str, u64)> = interpreter
+            .profiler()
+            .unwrap()
+            .functions()
+            .into_iter()
+            .map(|(name, profile)| (name, profile.calls))
+            .collect();
+        calls.sort();
+        assert_eq!(calls, vec![("fib", 177), ("strlen", 1)]);
+    }
+
+    #[test]
+    fn test_array_errors() {
+        assert_eq!(
+            eval("int a = [1, 2]; a[2];"),
+            Object::Error("index 2 out of bounds for length 2".to_string())
+        );
+        assert_eq!(
+            eval("int a = [1, 2]; a[-1] = 0;"),
+            Object::Error("index -1 out of bounds for length 2".to_string())
+        );
+        assert_eq!(
+            eval("[1][\"0\"];"),
+            Object::Error("index must be an integer, got STRING".to_string())
+        );
+        assert_eq!(
+            eval("int n = 5; n[0];"),
+            Object::Error("index operator not supported: INTEGER".to_string())
+        );
+        assert_eq!(
+            eval_with_stdlib("pop([]);"),
+            Object::Error("pop: array is empty".to_string())
+        );
+    }
+
+    #[test]
+    fn test_floats() {
+        assert_eq!(eval("1.5 + 2.25;"), Object::Float(3.75));
+        assert_eq!(eval("-2.5 * 2;"), Object::Float(-5.0));
+        assert_eq!(eval("1 + 0.5;"), Object::Float(1.5));
+        assert_eq!(eval("7 / 2;"), Object::Integer(3));
+        assert_eq!(eval("7 / 2.0;"), Object::Float(3.5));
+        assert_eq!(eval("7.5 % 2;"), Object::Float(1.5));
+        assert_eq!(eval("1.0 / 0.0;"), Object::Float(f64::INFINITY));
+        assert_eq!(eval("-1 / 0.0;"), Object::Float(f64::NEG_INFINITY));
+        assert!(matches!(eval("0.0 / 0.0;"), Object::Float(n) if n.is_nan()));
+
+        assert_eq!(eval("1 == 1.0;"), Object::Boolean(true));
+        assert_eq!(eval("0.1 + 0.2 != 0.3;"), Object::Boolean(true));
+        assert_eq!(eval("2 < 2.5 && 2.5 <= 2.5;"), Object::Boolean(true));
+        assert_eq!(eval("0.0 ? 1 : 2;"), Object::Integer(2));
+        assert_eq!(eval("int x = 1.5; x += 1; x;"), Object::Float(2.5));
+        assert_eq!(eval("\"pi=\" + 3.5;"), Object::String("pi=3.5".to_string()));
+        assert_eq!(
+            eval("1.5 & 1;"),
+            Object::Error("unknown operator: FLOAT & INTEGER".to_string())
+        );
+        assert_eq!(
+            eval("~1.5;"),
+            Object::Error("unknown operator: ~FLOAT".to_string())
+        );
+    }
+
+    #[test]
+    fn test_increment_decrement() {
+        assert_eq!(
+            eval("int i = 5; int a = i++ + 1; a * 10 + i;"),
+            Object::Integer(66)
+        );
+        assert_eq!(
+            eval("int i = 5; int a = ++i + 1; a * 10 + i;"),
+            Object::Integer(76)
+        );
+        assert_eq!(
+            eval("int n = 3; int a = n--; int b = --n; a * 10 + b;"),
+            Object::Integer(31)
+        );
+        assert_eq!(
+            eval("int total = 0; for (int i = 0; i < 4; i++) { total += i; } total;"),
+            Object::Integer(6)
+        );
+        assert_eq!(
+            eval("int s = \"a\"; s++;"),
+            Object::Error("++ requires an integer, got STRING".to_string())
+        );
+    }
+
+    #[test]
+    fn test_integer_overflow() {
+        let bounds = "int hi = 9223372036854775807; int lo = -hi - 1;";
+        for (expr, op) in [
+            ("hi + 1", Token::Plus),
+            ("lo - 1", Token::Minus),
+            ("hi * 2", Token::Asterisk),
+            ("lo / -1", Token::Slash),
+            ("lo % -1", Token::Percent),
+            ("-lo", Token::Minus),
+        ] {
+            assert_eq!(
+                runtime_error(&format!("{} {};", bounds, expr)),
+                RuntimeError::IntegerOverflow(op),
+                "{}",
+                expr
+            );
+        }
+        assert_eq!(
+            runtime_error(&format!("{} hi++;", bounds)).to_string(),
+            "integer overflow in ++"
+        );
+        assert_eq!(
+            runtime_error(&format!("{} --lo;", bounds)).to_string(),
+            "integer overflow in --"
+        );
+        // Results that fit are unaffected
+        assert_eq!(eval(&format!("{} hi + lo;", bounds)), Object::Integer(-1));
+        assert_eq!(
+            eval(&format!("{} -(lo + 1);", bounds)),
+            Object::Integer(i64::MAX)
+        );
+        assert_eq!(
+            eval(&format!("{} lo / 1;", bounds)),
+            Object::Integer(i64::MIN)
+        );
+    }
+
+    #[test]
+    fn test_bitwise_operators() {
+        assert_eq!(eval("(0xFF & 0x0F) << 4 | 1;"), Object::Integer(241));
+        assert_eq!(eval("6 ^ 3;"), Object::Integer(5));
+        assert_eq!(eval("~0;"), Object::Integer(-1));
+        assert_eq!(eval("-16 >> 2;"), Object::Integer(-4));
+        assert_eq!(eval("1 << 63;"), Object::Integer(i64::MIN));
+        assert_eq!(
+            eval("1 << 64;"),
+            Object::Error("shift count 64 out of range 0..=63".to_string())
+        );
+        assert!(matches!(eval("\"a\" & 1;"), Object::Error(_)));
+        assert!(matches!(eval("~\"a\";"), Object::Error(_)));
+    }
+
+    #[test]
+    fn test_ternary() {
+        assert_eq!(
+            eval("int x = 2; x == 1 ? \"one\" : x == 2 ? \"two\" : \"many\";"),
+            Object::String("two".to_string())
+        );
+        assert_eq!(eval("int n = 0; 1 ? n++ : n--; n;"), Object::Integer(1));
+        // The untaken branch is never evaluated, so its error doesn't surface
+        assert_eq!(eval("0 ? missing : 7;"), Object::Integer(7));
+        assert_eq!(eval("1 ? 7 : 1 / 0;"), Object::Integer(7));
+    }
+
+    // Runs `input` and returns the error report for the runtime error it raises
+    fn error_report(input: &str) -> String {
+        let program = Parser::new(Lexer::new(input)).parse_program();
+        let mut interpreter = Interpreter::new();
+        let env = interpreter.global_environment();
+        match interpreter.eval_program(&program, env) {
+            Err(err) => interpreter.error_report(&err),
+            other => panic!("expected an error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_error_report_has_line_and_call_stack() {
+        let input = "int count(int s) {\n    int n = 0;\n    return n + cnt;\n}\nint main() {\n    return count(1);\n}\nmain();";
+        assert_eq!(
+            error_report(input),
+            "runtime error at line 3: identifier not found: cnt\n  in count (line 1)\n  in main (line 5)"
+        );
+        assert_eq!(
+            error_report("int x = 1;\n\nx / 0;"),
+            "runtime error at line 3: division by zero"
+        );
+    }
+
+    #[test]
+    fn test_error_report_caps_frames() {
+        let report = error_report(
+            "int f(int n) {\n    return n / 0 + f(n - 1);\n}\nint g(int n) { return n ? g(n - 1) : f(0); }\ng(20);",
+        );
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines[0], "runtime error at line 2: division by zero");
+        assert_eq!(lines[1], "  in f (line 1)");
+        assert_eq!(lines[2], "  in g (line 4)");
+        assert_eq!(lines.len(), 1 + MAX_TRACE_FRAMES + 1);
+        assert_eq!(lines[lines.len() - 1], "  ... 12 more calls");
+    }
+
+    // Runs `input` as a program invoked with `args`, including its main function
+    fn run_with_args(input: &str, args: &[&str]) -> Option<Result<Object, RuntimeError>> {
+        let program = Parser::new(Lexer::new(input)).parse_program();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_args(args.iter().map(|arg| arg.to_string()).collect());
+        let env = interpreter.global_environment();
+        interpreter.eval_program(&program, Rc::clone(&env)).unwrap();
+        interpreter.call_main(env)
+    }
+
+    #[test]
+    fn test_argc_argv() {
+        let args = ["script.c", "foo", "--bar"];
+        assert_eq!(
+            run_with_args(
+                "int main() { return ARGV[1] == \"foo\" ? ARGC : -1; }",
+                &args
+            ),
+            Some(Ok(Object::Integer(3)))
+        );
+        assert_eq!(
+            run_with_args(
+                "int main(int argc, int argv) { return argv[argc - 1]; }",
+                &args
+            ),
+            Some(Ok(Object::String("--bar".to_string())))
+        );
+        // Only main() and main(argc, argv) are entry points
+        assert_eq!(run_with_args("int main(int a) { return a; }", &args), None);
+        assert_eq!(run_with_args("int f() { return 1; }", &args), None);
+    }
+
+    #[test]
+    fn test_program_returned() {
+        let mut interpreter = Interpreter::new();
+        let env = interpreter.global_environment();
+        let program =
+            Parser::new(Lexer::new("int x = 3; return x; puts(\"unreachable\");")).parse_program();
+        assert_eq!(
+            interpreter.eval_program(&program, Rc::clone(&env)),
+            Ok(Object::Integer(3))
+        );
+        assert!(interpreter.program_returned());
+
+        let program = Parser::new(Lexer::new("int f() { return 1; } f();")).parse_program();
+        assert_eq!(
+            interpreter.eval_program(&program, Rc::clone(&env)),
+            Ok(Object::Integer(1))
+        );
+        assert!(!interpreter.program_returned());
+
+        // A return inside top-level blocks and loops ends the program the
+        // same way, with its value unwrapped
+        for input in [
+            "{ int x = 4; return x; } puts(\"unreachable\");",
+            "if (1) { while (1) { return 4; } } puts(\"unreachable\");",
+            "for (int i = 0; ; i++) { if (i == 4) return i; }",
+            "switch (2) { case 2: { return 4; } } puts(\"unreachable\");",
+            "do return 4; while (1);",
+        ] {
+            let program = Parser::new(Lexer::new(input)).parse_program();
+            assert_eq!(
+                interpreter.eval_program(&program, Rc::clone(&env)),
+                Ok(Object::Integer(4)),
+                "{}",
+                input
+            );
+            assert!(interpreter.program_returned(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_return_from_nested_statements() {
+        let input = "int find(int xs, int n, int want) {
+                int i = 0;
+                while (i < n) {
+                    if (xs[i] == want) {
+                        { return i; }
+                    }
+                    i++;
+                }
+                return -1;
+            }
+            find([5, 6, 7], 3, 7) * 10 + find([5], 1, 9);";
+        assert_eq!(eval(input), Object::Integer(19));
+
+        let input = "int first_even(int n) {
+                for (int i = 1; i <= n; i++) {
+                    switch (i % 2) {
+                        case 0:
+                            do { return i; } while (0);
+                    }
+                }
+                return 0;
+            }
+            first_even(5);";
+        assert_eq!(eval(input), Object::Integer(2));
     }
 }
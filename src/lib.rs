@@ -0,0 +1,174 @@
+//! TinyC: a small C-style language with a tree-walk interpreter.
+//!
+//! The quickest way to run a program is [`run_source`]:
+//!
+//! ```
+//! use tcc::{Object, run_source};
+//!
+//! let result = run_source("int add(int a, int b) { return a + b; } add(2, 3);");
+//! assert_eq!(result, Ok(Object::Integer(5)));
+//! ```
+
+pub mod ast;
+pub mod debugger;
+pub mod env;
+pub mod interpreter;
+pub mod loader;
+pub mod parser;
+pub mod profiler;
+pub mod resolver;
+pub mod stdlib;
+pub mod token;
+
+pub use env::{Environment, Object};
+pub use interpreter::{Interpreter, RuntimeError};
+pub use loader::load_program;
+pub use parser::{ParseError, Parser};
+pub use stdlib::{Output, StdlibConfig};
+pub use token::Lexer;
+
+use std::cell::RefCell;
+use std::fmt;
+use std::path::Path;
+use std::rc::Rc;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum TinyCError {
+    Parse(Vec<ParseError>),
+    Runtime(RuntimeError),
+}
+
+impl fmt::Display for TinyCError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TinyCError::Parse(errors) => {
+                let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "{}", messages.join("\n"))
+            }
+            TinyCError::Runtime(err) => write!(f, "ERROR: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for TinyCError {}
+
+/// Creates a global environment with the stdlib and the `null` constant
+/// registered, printing to stdout.
+pub fn new_environment() -> Rc<RefCell<Environment>> {
+    Interpreter::new().global_environment()
+}
+
+/// Lexes, parses and evaluates `src` in a fresh global environment, returning
+/// the value of the last top-level statement. A program that calls `exit(n)`
+/// returns `Ok(Object::Exit(n))` rather than terminating the host process.
+pub fn run_source(src: &str) -> Result<Object, TinyCError> {
+    run_source_with_output(src, stdlib::stdout_output())
+}
+
+/// Like [`run_source`], but everything the program prints goes to `output`.
+pub fn run_source_with_output(src: &str, output: Output) -> Result<Object, TinyCError> {
+    // Included files are found relative to the working directory
+    let (program, errors) = load_program(src, None, Path::new(""));
+    if !errors.is_empty() {
+        return Err(TinyCError::Parse(errors));
+    }
+
+    let mut interpreter = Interpreter::with_output(output);
+    let env = interpreter.global_environment();
+    match interpreter.eval_program(&program, env) {
+        Ok(result) => Ok(result),
+        Err(RuntimeError::Exit(code)) => Ok(Object::Exit(code)),
+        Err(err) => Err(TinyCError::Runtime(err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_source() {
+        assert_eq!(run_source("1 + 2;"), Ok(Object::Integer(3)));
+        assert_eq!(run_source("strlen(\"abc\");"), Ok(Object::Integer(3)));
+        assert_eq!(
+            run_source("-9223372036854775808;"),
+            Ok(Object::Integer(i64::MIN))
+        );
+        assert_eq!(
+            run_source("1 / 0;"),
+            Err(TinyCError::Runtime(RuntimeError::DivisionByZero))
+        );
+        assert!(matches!(
+            run_source("if (1 {"),
+            Err(TinyCError::Parse(errors)) if errors.len() == 1
+        ));
+    }
+
+    #[test]
+    fn test_register_stateful_builtin() {
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        let env = new_environment();
+        let captured = Rc::clone(&sink);
+        env.borrow_mut().register_builtin("log", move |args| {
+            captured.borrow_mut().push(args[0].inspect());
+            Object::Null
+        });
+
+        let mut parser = Parser::new(Lexer::new("log(\"a\"); log(1 + 1);"));
+        let program = parser.parse_program();
+        Interpreter::new().eval_program(&program, env).unwrap();
+
+        assert_eq!(*sink.borrow(), vec!["a".to_string(), "2".to_string()]);
+    }
+
+    // Runs `src`, returning its result and everything it printed
+    pub(crate) fn run_captured(src: &str) -> (Result<Object, TinyCError>, String) {
+        let buffer = Rc::new(RefCell::new(Vec::new()));
+        let result = run_source_with_output(src, buffer.clone());
+        let printed = String::from_utf8(buffer.borrow().clone()).unwrap();
+        (result, printed)
+    }
+
+    #[test]
+    fn test_captured_output() {
+        assert_eq!(
+            run_captured("puts(\"hi\");"),
+            (Ok(Object::Null), "hi\n".to_string())
+        );
+        assert_eq!(
+            run_captured("printf(\"%d-\", 1); putchar(\"xy\"); 5;").1,
+            "1-x"
+        );
+    }
+
+    #[test]
+    fn test_float_program() {
+        let src = "int xs = [3, 4.5, 2];
+            int sum = 0;
+            for (int i = 0; i < len(xs); i++) sum += xs[i];
+            printf(\"%.2f %d\\n\", sum / len(xs), 7 / 2);";
+        assert_eq!(run_captured(src).1, "3.17 3\n");
+    }
+
+    #[test]
+    fn test_std_streams() {
+        let src = "printf(\"a\");
+            fputs(\"b\", stdout);
+            fprintf(stdout, \"%d\", 3);
+            fputc(\"c\", stdout);
+            fputs(\"\", stderr);
+            printf(\" %d %d\", ftell(stdout), fseek(stderr, 0, 0));";
+        assert_eq!(
+            run_captured(src),
+            (Ok(Object::Null), "ab3c -1 -1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_exit_returns_code() {
+        assert_eq!(
+            run_captured("puts(\"a\"); exit(4); puts(\"b\");"),
+            (Ok(Object::Exit(4)), "a\n".to_string())
+        );
+    }
+}
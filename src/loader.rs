@@ -0,0 +1,330 @@
+use crate::ast::{Program, Statement};
+use crate::parser::{Defines, ParseError, Parser};
+use crate::token::{Lexer, Symbol, Token, is_i64_min_magnitude};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Parses `source` and splices in the statements of each top-level
+// `#include "path"`, resolved relative to `dir` (and nested includes relative
+// to the including file). `file` names the source in parse errors; errors in an
+// included file name that file. Each file is included at most once, so two
+// files including the same helpers don't define them twice, but a file that
+// includes itself, directly or not, is an error naming the cycle.
+pub fn load_program(source: &str, file: Option<&str>, dir: &Path) -> (Program, Vec<ParseError>) {
+    let mut loader = Loader {
+        stack: vec![],
+        included: HashSet::new(),
+        defines: Defines::new(),
+        errors: vec![],
+    };
+    if let Some(root) = file.and_then(|file| fs::canonicalize(file).ok()) {
+        loader.included.insert(root.clone());
+        loader
+            .stack
+            .push((root, file.unwrap_or_default().to_string()));
+    }
+    let (statements, files) = loader.load(source, file, None, dir).into_iter().unzip();
+    (Program { statements, files }, loader.errors)
+}
+
+struct Loader {
+    stack: Vec<(PathBuf, String)>, // Files being loaded: canonical path and display name
+    included: HashSet<PathBuf>,
+    defines: Defines, // Handed from file to file, so an included file's #defines carry over
+    errors: Vec<ParseError>,
+}
+
+// A statement and the included file it came from, None for the main source
+type Sourced = (Statement, Option<Symbol>);
+
+impl Loader {
+    fn load(
+        &mut self,
+        source: &str,
+        file: Option<&str>,
+        included: Option<Symbol>,
+        dir: &Path,
+    ) -> Vec<Sourced> {
+        let defines = std::mem::take(&mut self.defines);
+        let mut parser = Parser::with_defines(Lexer::new(source), defines);
+        let mut statements = vec![];
+        let mut reported = HashSet::new();
+        loop {
+            let stmt = parser.parse_top_level();
+            // Drained as we go so errors stay in order with those of included files
+            for mut err in parser.errors.drain(..) {
+                reported.insert((err.line, err.column));
+                err.file = file.map(str::to_string);
+                self.errors.push(err);
+            }
+            match stmt {
+                Some(Statement::Include { path, line }) => {
+                    self.defines = parser.take_defines();
+                    statements.extend(self.include(&path, dir, file, line));
+                    parser.set_defines(std::mem::take(&mut self.defines));
+                }
+                Some(stmt) => statements.push((stmt, included.clone())),
+                None => break,
+            }
+        }
+        self.defines = parser.take_defines();
+
+        // Error recovery can skip over tokens the lexer rejected, so those are
+        // reported here unless the parser already did
+        let mut lexer = Lexer::new(source);
+        let mut previous = Token::Eof;
+        loop {
+            let (token, span) = lexer.next_token_spanned();
+            let negated_min = previous == Token::Minus && is_i64_min_magnitude(&token);
+            if token == Token::Eof {
+                break;
+            }
+            if let Some(message) = token.lex_error()
+                && !reported.contains(&(span.line, span.column))
+                && !negated_min
+            {
+                self.errors.push(ParseError {
+                    message,
+                    line: span.line,
+                    column: span.column,
+                    file: file.map(str::to_string),
+                })
+            }
+            previous = token;
+        }
+        statements
+    }
+
+    // The statements of `path`, or none if it was already included or can't be loaded
+    fn include(&mut self, path: &str, dir: &Path, from: Option<&str>, line: usize) -> Vec<Sourced> {
+        let target = dir.join(path);
+        let name = target.display().to_string();
+        let loaded = fs::canonicalize(&target)
+            .and_then(|canonical| Ok((canonical, fs::read_to_string(&target)?)));
+        let (canonical, source) = match loaded {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                self.error(from, line, format!("cannot include \"{}\": {}", path, e));
+                return vec![];
+            }
+        };
+
+        if let Some(start) = self.stack.iter().position(|(open, _)| *open == canonical) {
+            let cycle: Vec<&str> = self.stack[start..]
+                .iter()
+                .map(|(_, name)| name.as_str())
+                .chain([name.as_str()])
+                .collect();
+            self.error(from, line, format!("include cycle: {}", cycle.join(" -> ")));
+            return vec![];
+        }
+        if !self.included.insert(canonical.clone()) {
+            return vec![];
+        }
+
+        self.stack.push((canonical, name.clone()));
+        let dir = target.parent().unwrap_or(Path::new(""));
+        let statements = self.load(&source, Some(&name), Some(name.as_str().into()), dir);
+        self.stack.pop();
+        statements
+    }
+
+    fn error(&mut self, file: Option<&str>, line: usize, message: String) {
+        self.errors.push(ParseError {
+            message,
+            line,
+            column: 1,
+            file: file.map(str::to_string),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Interpreter;
+
+    // A fresh directory holding `files`, as (relative path, contents)
+    fn temp_tree(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("tinyc-test-{}", std::process::id()))
+            .join(test);
+        let _ = fs::remove_dir_all(&dir);
+        for (path, contents) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    fn error_messages(errors: &[ParseError]) -> Vec<String> {
+        errors.iter().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn test_includes_are_spliced_in() {
+        let dir = temp_tree(
+            "splice",
+            &[
+                (
+                    "lib/math.c",
+                    "#include \"square.c\"\nint cube(int x) { return x * square(x); }",
+                ),
+                ("lib/square.c", "int square(int x) { return x * x; }"),
+                ("lib/both.c", "#include \"math.c\";\n#include \"square.c\""),
+            ],
+        );
+        let (program, errors) = load_program(
+            "#include \"lib/math.c\"\n#include \"lib/both.c\"\ncube(3);",
+            None,
+            &dir,
+        );
+        assert!(errors.is_empty(), "{:?}", errors);
+        // square.c comes first and only once, even though three files include it
+        let names: Vec<String> = program
+            .statements
+            .iter()
+            .map(|stmt| match stmt {
+                Statement::Function { name, .. } => name.to_string(),
+                other => other.to_string(),
+            })
+            .collect();
+        assert_eq!(names, vec!["square", "cube", "cube(3);"]);
+    }
+
+    #[test]
+    fn test_errors_name_the_included_file() {
+        let dir = temp_tree("errors", &[("bad.c", "int ok = 1;\nint x = ;")]);
+        let (_, errors) = load_program(
+            "#include \"bad.c\"\n#include \"missing.c\"",
+            Some("main.c"),
+            &dir,
+        );
+        let bad = dir.join("bad.c").display().to_string();
+        let messages = error_messages(&errors);
+        assert_eq!(
+            messages[0],
+            format!("{}:2:9: expected an expression but found ';'", bad)
+        );
+        assert!(messages[1].starts_with("main.c:2:1: cannot include \"missing.c\": "));
+    }
+
+    #[test]
+    fn test_include_cycle_is_error() {
+        let dir = temp_tree(
+            "cycle",
+            &[
+                ("a.c", "#include \"b.c\""),
+                ("b.c", "int b = 1;\n#include \"a.c\""),
+            ],
+        );
+        let (a, b) = (dir.join("a.c"), dir.join("b.c"));
+        let (_, errors) = load_program("#include \"a.c\"", None, &dir);
+        assert_eq!(
+            error_messages(&errors),
+            vec![format!(
+                "{}:2:1: include cycle: {} -> {} -> {}",
+                b.display(),
+                a.display(),
+                b.display(),
+                a.display()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_defines_carry_across_includes() {
+        let dir = temp_tree(
+            "defines",
+            &[(
+                "config.h",
+                "#define SIZE (SCALE * 2)\n#define NAME \"tiny\"",
+            )],
+        );
+        let (program, errors) = load_program(
+            "#define SCALE 3\n#include \"config.h\"\nSIZE;\nNAME;",
+            None,
+            &dir,
+        );
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(program.to_string(), "(3 * 2);\n\"tiny\";\n");
+    }
+
+    #[test]
+    fn test_skipped_illegal_tokens_are_reported() {
+        let (_, errors) = load_program(
+            "int x = ;\nint y = ) @;\n#define UNUSED $",
+            None,
+            Path::new(""),
+        );
+        assert_eq!(
+            error_messages(&errors),
+            vec![
+                "1:9: expected an expression but found ';'",
+                "2:9: expected an expression but found ')'",
+                // The parser reports a #define's body even if it's never used
+                "3:16: illegal token '$'",
+                "2:11: illegal token '@'",
+            ]
+        );
+
+        // A used #define doesn't report it a second time
+        let (_, errors) = load_program("#define BAD 1 @\nBAD;\nBAD;", None, Path::new(""));
+        assert_eq!(error_messages(&errors), vec!["1:15: illegal token '@'"]);
+    }
+
+    #[test]
+    fn test_runtime_errors_name_the_included_file() {
+        let dir = temp_tree(
+            "runtime",
+            &[
+                (
+                    "lib.c",
+                    "int z = 0;\nint ratio(int n) {\n    return n / z;\n}\nint twice() { return 2; }",
+                ),
+                ("dup.c", "int twice() { return 3; }"),
+            ],
+        );
+        let lib = dir.join("lib.c").display().to_string();
+        let run = |source: &str| {
+            let (program, errors) = load_program(source, Some("main.c"), &dir);
+            assert!(errors.is_empty(), "{:?}", errors);
+            let mut interpreter = Interpreter::new();
+            let env = interpreter.global_environment();
+            let err = interpreter.eval_program(&program, env).unwrap_err();
+            interpreter.error_report(&err)
+        };
+
+        assert_eq!(
+            run("#include \"lib.c\"\nint main() {\n    return ratio(4);\n}\nmain();"),
+            format!(
+                "runtime error at {}:3: division by zero\n  in ratio ({}:2)\n  in main (line 2)",
+                lib, lib
+            )
+        );
+        // The caller's own lines are reported as its own once the call returns
+        assert_eq!(
+            run("#include \"lib.c\"\nint x = twice();\nx / z;"),
+            "runtime error at line 3: division by zero"
+        );
+        assert_eq!(
+            run("#include \"lib.c\"\n#include \"dup.c\""),
+            format!(
+                "runtime error at {}:1: function twice is already defined at {}:5",
+                dir.join("dup.c").display(),
+                lib
+            )
+        );
+    }
+
+    #[test]
+    fn test_include_only_at_top_level() {
+        let (_, errors) = load_program("int f() {\n#include \"x.c\"\n}", None, Path::new(""));
+        assert_eq!(
+            errors[0].to_string(),
+            "2:1: #include is only allowed at the top level"
+        );
+    }
+}
@@ -1,72 +1,325 @@
-mod ast;
-mod env;
-mod interpreter;
-mod parser;
-mod stdlib;
-mod token;
-
-use env::Environment;
-use interpreter::Interpreter;
-use parser::Parser;
+mod repl;
+
 use std::cell::RefCell;
 use std::rc::Rc;
-use token::Lexer;
+use std::thread;
+use std::time::{Duration, Instant};
+use tcc::debugger::Debugger;
+use tcc::interpreter::DEFAULT_MAX_DEPTH;
+use tcc::resolver;
+use tcc::stdlib::stdout_output;
+use tcc::token::Token;
+use tcc::{Interpreter, Lexer, Object, RuntimeError, StdlibConfig, load_program};
 
 use std::env as std_env;
 use std::fs;
+use std::io::{BufReader, Read};
+use std::path::Path;
 use std::process;
 
+// Deep TinyC recursion nests many Rust frames per call, so the interpreter runs
+// on a thread with a much larger stack than the default main thread
+const INTERPRETER_STACK_SIZE: usize = 512 * 1024 * 1024;
+
+// A program that stops with a runtime error exits with this status
+// (EX_SOFTWARE from sysexits.h), so it can be told apart from exit(1)
+const RUNTIME_ERROR_EXIT_CODE: i32 = 70;
+
 fn main() {
+    let interpreter = thread::Builder::new()
+        .stack_size(INTERPRETER_STACK_SIZE)
+        .spawn(run)
+        .expect("failed to spawn interpreter thread");
+    if interpreter.join().is_err() {
+        process::exit(101);
+    }
+}
+
+fn usage(program: &str) -> ! {
+    eprintln!(
+        "Usage: {} [--tokens] [--ast] [--check] [--resolve] [--trace] [--debug] [--time] [--profile] [--max-depth N] [--max-steps N] [--timeout-ms N] [--sandbox] [-e program]... [filename | -] [args...]",
+        program
+    );
+    process::exit(1);
+}
+
+fn run() {
     let args: Vec<String> = std_env::args().collect();
 
-    if args.len() < 2 {
-        eprintln!("Usage: {} <filename>", args[0]);
-        process::exit(1);
+    let mut dump_tokens = false;
+    let mut dump_ast = false;
+    let mut check = false;
+    let mut resolve = false;
+    let mut trace = false;
+    let mut debug = false;
+    let mut time = false;
+    let mut profile = false;
+    let mut max_depth = DEFAULT_MAX_DEPTH;
+    let mut max_steps = None;
+    let mut timeout_ms = None;
+    let mut sandbox = false;
+    let mut filename = None;
+    let mut eval_sources = vec![];
+    let mut script_args = vec![];
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "-e" => match rest.next() {
+                Some(source) => eval_sources.push(source.as_str()),
+                None => {
+                    eprintln!("-e expects a program");
+                    usage(&args[0]);
+                }
+            },
+            "--tokens" => dump_tokens = true,
+            "--ast" => dump_ast = true,
+            "--check" => check = true,
+            "--resolve" => resolve = true,
+            "--trace" => trace = true,
+            "--debug" => debug = true,
+            "--time" => time = true,
+            "--profile" => {
+                time = true;
+                profile = true;
+            }
+            "--sandbox" => sandbox = true,
+            "--max-depth" => match rest.next().map(|n| n.parse()) {
+                Some(Ok(n)) => max_depth = n,
+                _ => {
+                    eprintln!("--max-depth expects a non-negative integer");
+                    usage(&args[0]);
+                }
+            },
+            "--max-steps" => match rest.next().map(|n| n.parse()) {
+                Some(Ok(n)) => max_steps = Some(n),
+                _ => {
+                    eprintln!("--max-steps expects a non-negative integer");
+                    usage(&args[0]);
+                }
+            },
+            "--timeout-ms" => match rest.next().map(|n| n.parse()) {
+                Some(Ok(n)) => timeout_ms = Some(n),
+                _ => {
+                    eprintln!("--timeout-ms expects a non-negative integer");
+                    usage(&args[0]);
+                }
+            },
+            flag if flag.starts_with("--") => {
+                eprintln!("Unknown option: {}", flag);
+                usage(&args[0]);
+            }
+            // Everything after the filename belongs to the script, even `--` flags.
+            // With -e there is no filename, so the script's arguments start here.
+            _ => {
+                if eval_sources.is_empty() {
+                    filename = Some(arg.as_str());
+                } else {
+                    script_args.push(arg.clone());
+                }
+                break;
+            }
+        }
+    }
+    script_args.extend(rest.cloned());
+
+    let mut interpreter = Interpreter::new();
+    interpreter.set_max_depth(max_depth);
+    if let Some(max_steps) = max_steps {
+        interpreter.set_max_steps(max_steps);
     }
+    if trace {
+        // stderr, so the trace doesn't mix with what the program prints
+        interpreter.set_trace(Rc::new(RefCell::new(std::io::stderr())));
+    }
+    if profile {
+        interpreter.enable_profiling();
+    }
+    if debug {
+        if filename == Some("-") {
+            eprintln!("--debug reads commands from stdin, so the program can't come from there");
+            usage(&args[0]);
+        }
+        let commands = Box::new(BufReader::new(std::io::stdin()));
+        interpreter.set_debugger(Debugger::new(commands, stdout_output()));
+    }
+    let stdlib_config = if sandbox {
+        StdlibConfig::sandbox()
+    } else {
+        StdlibConfig::default()
+    };
+    interpreter.set_stdlib_config(stdlib_config);
 
-    let filename = &args[1];
-    let input = match fs::read_to_string(filename) {
-        Ok(content) => content,
-        Err(e) => {
-            eprintln!("Error reading file {}: {}", filename, e);
+    let (filename, input) = if !eval_sources.is_empty() {
+        // Like perl and ruby, several -e programs run as one, a line each
+        ("<cmdline>", eval_sources.join("\n"))
+    } else if filename == Some("-") {
+        let mut content = String::new();
+        if let Err(e) = std::io::stdin().read_to_string(&mut content) {
+            eprintln!("Error reading program from stdin: {}", e);
             process::exit(1);
         }
+        // The program used up stdin, so the script can't read from it
+        let mut config = stdlib_config;
+        config.allow_stdin = false;
+        interpreter.set_stdlib_config(config);
+        ("<stdin>", content)
+    } else if let Some(filename) = filename {
+        match fs::read_to_string(filename) {
+            Ok(content) => (filename, content),
+            Err(e) => {
+                eprintln!("Error reading file {}: {}", filename, e);
+                process::exit(1);
+            }
+        }
+    } else {
+        repl::start(interpreter);
+        return;
     };
+    interpreter.set_args(
+        std::iter::once(filename.to_string())
+            .chain(script_args)
+            .collect(),
+    );
+
+    if dump_tokens {
+        process::exit(print_tokens(filename, &input));
+    }
+
+    // Includes are relative to the program's file; -e and stdin use the working directory
+    let dir = Path::new(filename).parent().unwrap_or(Path::new(""));
+    let (mut program, errors) = load_program(&input, Some(filename), dir);
+
+    // Parse-only validation: report errors, then dump the AST if asked, but never run
+    if check {
+        for err in &errors {
+            eprintln!("{}", err);
+        }
+        if dump_ast {
+            print!("{}", program);
+        }
+        process::exit(if errors.is_empty() { 0 } else { 1 });
+    }
 
-    let lexer = Lexer::new(&input);
-    let mut parser = Parser::new(lexer);
-    let program = parser.parse_program();
+    if dump_ast {
+        print!("{}", program);
+    }
 
-    if !parser.errors.is_empty() {
-        println!("Parser errors:");
-        for err in parser.errors {
-            println!("\t{}", err);
+    if !errors.is_empty() {
+        for err in errors {
+            eprintln!("{}", err);
         }
         process::exit(1);
+    } else if dump_ast {
+        process::exit(0);
     } else {
-        let env = Rc::new(RefCell::new(Environment::new()));
-
-        // Register stdlib
-        crate::stdlib::register_stdlib(Rc::clone(&env));
-
-        // Add constants
-        env.borrow_mut()
-            .set("null".to_string(), crate::env::Object::Null);
-        env.borrow_mut()
-            .set("true".to_string(), crate::env::Object::Boolean(true));
-        env.borrow_mut()
-            .set("false".to_string(), crate::env::Object::Boolean(false));
-
-        let mut interpreter = Interpreter::new();
-
-        let result = interpreter.eval_program(&program, env);
-        // Only print result if it's not Null (stdlib functions return Null mostly)
-        // Or keep printing it.
-        // println!("Interpreter Result: {}", result.inspect());
-        // User asked to not print source, maybe they don't want result printed if it's just script execution?
-        // But let's keep it for now or check if it's non-null.
-        if result != crate::env::Object::Null {
-            println!("Interpreter Result: {}", result.inspect());
+        if resolve {
+            resolver::resolve(&mut program);
+        }
+        let env = interpreter.global_environment();
+        // The clock starts once the program is parsed and about to run
+        if let Some(timeout_ms) = timeout_ms {
+            interpreter.set_timeout(Duration::from_millis(timeout_ms));
+        }
+
+        let start = Instant::now();
+        let code = match interpreter.eval_program(&program, Rc::clone(&env)) {
+            // A top-level `return n;` ends the program like returning from main
+            Ok(Object::Integer(n)) if interpreter.program_returned() => exit_code(n),
+            // An error that surfaced as a value still fails the program
+            Ok(Object::Error(message)) => {
+                report_error(&interpreter, &RuntimeError::Custom(message))
+            }
+            Ok(result) => {
+                let main_result = if interpreter.program_returned() {
+                    None
+                } else {
+                    interpreter.call_main(Rc::clone(&env))
+                };
+                match main_result {
+                    Some(Ok(Object::Integer(n))) | Some(Err(RuntimeError::Exit(n))) => exit_code(n),
+                    Some(Ok(Object::Error(message))) => {
+                        report_error(&interpreter, &RuntimeError::Custom(message))
+                    }
+                    Some(Ok(_)) => 0,
+                    Some(Err(err)) => report_error(&interpreter, &err),
+                    None => {
+                        // Only print result if it's not Null (stdlib functions return Null mostly)
+                        if result != Object::Null {
+                            println!("Interpreter Result: {}", result.inspect());
+                        }
+                        0
+                    }
+                }
+            }
+            Err(RuntimeError::Exit(code)) => exit_code(code),
+            Err(err) => report_error(&interpreter, &err),
+        };
+        // Open files are flushed and closed before the reports, and temporary
+        // files removed even if a reference cycle kept them open
+        drop(env);
+        interpreter.remove_temp_files();
+
+        if time {
+            eprintln!(
+                "execution time: {:.3} ms",
+                start.elapsed().as_secs_f64() * 1000.0
+            );
+        }
+        if let Some(profiler) = interpreter.profiler() {
+            eprint!("{}", profiler.report());
+        }
+        process::exit(code);
+    }
+}
+
+// Prints where `err` happened to stderr, returning the exit status for it
+fn report_error(interpreter: &Interpreter, err: &RuntimeError) -> i32 {
+    eprintln!("{}", interpreter.error_report(err));
+    RUNTIME_ERROR_EXIT_CODE
+}
+
+// Like C, only the low 8 bits of an exit status survive, so -1 becomes 255
+fn exit_code(code: i64) -> i32 {
+    code.rem_euclid(256) as i32
+}
+
+// Prints one token per line, returning a non-zero exit code if any token is illegal
+fn print_tokens(filename: &str, input: &str) -> i32 {
+    let mut lexer = Lexer::new(input);
+    let mut code = 0;
+
+    for index in 0.. {
+        let (token, span) = lexer.next_token_spanned();
+        if let Some(message) = token.lex_error() {
+            println!(
+                "{:>4}  {}:{}  !!! ILLEGAL {:?}",
+                index,
+                span.line,
+                span.column,
+                token.literal()
+            );
+            eprintln!("{}:{}:{}: {}", filename, span.line, span.column, message);
+            code = 1;
+        } else {
+            println!("{:>4}  {}:{}  {:?}", index, span.line, span.column, token);
         }
+        if token == Token::Eof {
+            break;
+        }
+    }
+
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_code_wraps_like_c() {
+        assert_eq!(exit_code(3), 3);
+        assert_eq!(exit_code(256), 0);
+        assert_eq!(exit_code(300), 44);
+        assert_eq!(exit_code(-1), 255);
     }
 }
@@ -1,134 +1,559 @@
-use crate::ast::{Expression, Program, Statement};
-use crate::token::{Lexer, Token};
+use crate::ast::{Expression, Param, Program, Statement, Type};
+use crate::token::{Lexer, Span, Symbol, Token, is_i64_min_magnitude};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::rc::Rc;
 
 #[derive(PartialEq, PartialOrd, Debug, Copy, Clone)]
 enum Precedence {
     Lowest,
+    Assign,      // =
+    Ternary,     // ? :
+    LogicalOr,   // ||
+    LogicalAnd,  // &&
+    BitOr,       // |
+    BitXor,      // ^
+    BitAnd,      // &
     Equals,      // ==
-    LessGreater, // > or <
+    LessGreater, // > < >= <=
+    Shift,       // << >>
     Sum,         // +
-    Product,     // *
-    Prefix,      // -X or !X
+    Product,     // * / %
+    Prefix,      // -X, !X or ~X
     Call,        // myFunction(X)
 }
 
 fn token_precedence(token: &Token) -> Precedence {
     match token {
+        Token::Assign
+        | Token::PlusAssign
+        | Token::MinusAssign
+        | Token::AsteriskAssign
+        | Token::SlashAssign
+        | Token::PercentAssign => Precedence::Assign,
+        Token::Question => Precedence::Ternary,
+        Token::Or => Precedence::LogicalOr,
+        Token::And => Precedence::LogicalAnd,
+        Token::Pipe => Precedence::BitOr,
+        Token::Caret => Precedence::BitXor,
+        Token::Ampersand => Precedence::BitAnd,
         Token::Equal | Token::NotEqual => Precedence::Equals,
-        Token::LessThan | Token::GreaterThan => Precedence::LessGreater,
+        Token::LessThan | Token::GreaterThan | Token::LessEqual | Token::GreaterEqual => {
+            Precedence::LessGreater
+        }
+        Token::ShiftLeft | Token::ShiftRight => Precedence::Shift,
         Token::Plus | Token::Minus => Precedence::Sum,
-        Token::Asterisk | Token::Slash => Precedence::Product,
-        Token::LParen => Precedence::Call,
+        Token::Asterisk | Token::Slash | Token::Percent => Precedence::Product,
+        Token::LParen | Token::LBracket | Token::Increment | Token::Decrement => Precedence::Call,
         _ => Precedence::Lowest,
     }
 }
 
+// Case labels are integer, char or string literals, optionally negated
+fn is_case_literal(label: &Expression) -> bool {
+    match label {
+        Expression::Integer(_) | Expression::Character(_) | Expression::String(_) => true,
+        Expression::Prefix {
+            operator: Token::Minus,
+            right,
+        } => matches!(**right, Expression::Integer(_)),
+        _ => false,
+    }
+}
+
+// The error for a keyword written where a name belongs, spelled as in the source
+fn keyword_as_identifier(keyword: &Token) -> String {
+    format!(
+        "cannot use keyword '{}' as an identifier",
+        keyword.literal()
+    )
+}
+
+// Keywords that can only begin a statement, where error recovery can safely resume
+fn starts_statement(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Int
+            | Token::Char
+            | Token::Void
+            | Token::If
+            | Token::While
+            | Token::Do
+            | Token::Switch
+            | Token::For
+            | Token::Const
+            | Token::Return
+            | Token::Include
+    )
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParseError {
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+    pub file: Option<String>, // Set by load_program for errors in included files
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+        }
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+// Statements and expressions nested deeper than this are an error, since a
+// pathological input would otherwise overflow the stack of the recursive parser.
+// An expression level takes around 4 KB of stack in a debug build, so this
+// keeps within the 2 MB Rust gives a spawned thread.
+const MAX_NESTING: usize = 256;
+
+// `#define` names and the tokens they expand to
+pub type Defines = HashMap<Symbol, Vec<Token>>;
+
 pub struct Parser<'a> {
     lexer: Lexer<'a>,
+    // A token read past the end of a #define line, not yet expanded
+    pushed_back: Option<(Token, Span)>,
+    expansion: VecDeque<(Token, Span)>, // The rest of a macro being substituted
+    defines: Defines,
     cur_token: Token,
+    cur_span: Span,
     peek_token: Token,
-    pub errors: Vec<String>,
+    peek_span: Span,
+    depth: usize,   // Statements and expressions being parsed, innermost included
+    too_deep: bool, // Nesting went past MAX_NESTING and the rest of the input was skipped
+    pub errors: Vec<ParseError>,
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(mut lexer: Lexer<'a>) -> Self {
-        let cur_token = lexer.next_token();
-        let peek_token = lexer.next_token();
-        Parser {
+    pub fn new(lexer: Lexer<'a>) -> Self {
+        Self::with_defines(lexer, Defines::new())
+    }
+
+    // A parser that starts out with `defines` already defined
+    pub fn with_defines(lexer: Lexer<'a>, defines: Defines) -> Self {
+        let mut parser = Parser {
             lexer,
-            cur_token,
-            peek_token,
+            pushed_back: None,
+            expansion: VecDeque::new(),
+            defines,
+            cur_token: Token::Eof,
+            cur_span: Span { line: 1, column: 1 },
+            peek_token: Token::Eof,
+            peek_span: Span { line: 1, column: 1 },
+            depth: 0,
+            too_deep: false,
             errors: vec![],
+        };
+        (parser.cur_token, parser.cur_span) = parser.preprocessed_token();
+        (parser.peek_token, parser.peek_span) = parser.preprocessed_token();
+        parser
+    }
+
+    pub fn take_defines(&mut self) -> Defines {
+        std::mem::take(&mut self.defines)
+    }
+
+    // Replaces the definitions, such as with those of an included file. The
+    // current and peek tokens were read before, so they are expanded again.
+    pub fn set_defines(&mut self, defines: Defines) {
+        self.defines = defines;
+        let lookahead = [
+            (
+                std::mem::replace(&mut self.cur_token, Token::Eof),
+                self.cur_span,
+            ),
+            (
+                std::mem::replace(&mut self.peek_token, Token::Eof),
+                self.peek_span,
+            ),
+        ];
+        let mut expansion = VecDeque::new();
+        for (token, span) in lookahead {
+            match &token {
+                Token::Identifier(name) if self.defines.contains_key(name) => {
+                    expansion.extend(self.defines[name].iter().map(|t| (t.clone(), span)))
+                }
+                _ => expansion.push_back((token, span)),
+            }
         }
+        expansion.append(&mut self.expansion);
+        self.expansion = expansion;
+        (self.cur_token, self.cur_span) = self.preprocessed_token();
+        (self.peek_token, self.peek_span) = self.preprocessed_token();
     }
 
     pub fn next_token(&mut self) {
         self.cur_token = self.peek_token.clone();
-        self.peek_token = self.lexer.next_token();
+        self.cur_span = self.peek_span;
+        (self.peek_token, self.peek_span) = self.preprocessed_token();
+    }
+
+    fn raw_token(&mut self) -> (Token, Span) {
+        self.pushed_back
+            .take()
+            .unwrap_or_else(|| self.lexer.next_token_spanned())
+    }
+
+    // The next token after handling #define and #undef and substituting defined
+    // names. Substituted tokens take the position of the name they replace.
+    fn preprocessed_token(&mut self) -> (Token, Span) {
+        loop {
+            if let Some(token) = self.expansion.pop_front() {
+                return token;
+            }
+            let (token, span) = self.raw_token();
+            match token {
+                Token::Define => self.parse_define(span),
+                Token::Undef => {
+                    if let Some(name) = self.directive_name("#undef", span) {
+                        self.defines.remove(&name);
+                    }
+                }
+                Token::Identifier(name) if self.defines.contains_key(&name) => {
+                    let value = self.defines[&name].iter().map(|t| (t.clone(), span));
+                    self.expansion.extend(value);
+                }
+                token => return (token, span),
+            }
+        }
+    }
+
+    // The name following #define or #undef on the directive's own line
+    fn directive_name(&mut self, directive: &str, span: Span) -> Option<Symbol> {
+        match self.raw_token() {
+            (Token::Identifier(name), name_span) if name_span.line == span.line => Some(name),
+            (token, token_span) => {
+                self.error_at(span, format!("expected a name after {}", directive));
+                if token_span.line != span.line {
+                    self.pushed_back = Some((token, token_span));
+                }
+                None
+            }
+        }
+    }
+
+    // #define NAME tokens...: the value is the rest of the line. Names defined
+    // earlier are substituted into it now, so a name can't expand to itself.
+    // A token the lexer rejected is reported here rather than at every use,
+    // and leaves the name undefined.
+    fn parse_define(&mut self, span: Span) {
+        let Some(name) = self.directive_name("#define", span) else {
+            return;
+        };
+        let mut value = vec![];
+        let mut rejected = false;
+        loop {
+            let (token, token_span) = self.raw_token();
+            if token_span.line != span.line || token == Token::Eof {
+                self.pushed_back = Some((token, token_span));
+                break;
+            }
+            if let Some(message) = token.lex_error() {
+                self.error_at(token_span, message);
+                rejected = true;
+                continue;
+            }
+            match token {
+                Token::Identifier(used) if self.defines.contains_key(&used) => {
+                    value.extend(self.defines[&used].iter().cloned())
+                }
+                token => value.push(token),
+            }
+        }
+        match self.defines.get(&name) {
+            _ if rejected => {}
+            Some(old) if *old != value => self.error_at(
+                span,
+                format!("{} is already defined; #undef it before redefining", name),
+            ),
+            _ => {
+                self.defines.insert(name, value);
+            }
+        }
     }
 
     pub fn parse_program(&mut self) -> Program {
         let mut statements = vec![];
+        while let Some(stmt) = self.parse_top_level() {
+            statements.push(stmt);
+        }
+        Program {
+            statements,
+            files: vec![],
+        }
+    }
 
-        while self.cur_token != Token::EOF {
-            if let Some(stmt) = self.parse_statement() {
-                statements.push(stmt);
+    // The next top-level statement, skipping over any with errors, or None at
+    // the end of the input
+    pub fn parse_top_level(&mut self) -> Option<Statement> {
+        while self.cur_token != Token::Eof {
+            let stmt = match self.cur_token {
+                Token::Include => self.parse_include(),
+                _ => self.parse_statement(),
+            };
+            match stmt {
+                Some(stmt) => {
+                    self.next_token();
+                    return Some(stmt);
+                }
+                None => {
+                    self.synchronize();
+                    // Nothing encloses the top level, so a stray `}` is just skipped
+                    if self.cur_token == Token::RBrace {
+                        self.next_token();
+                    }
+                }
             }
-            self.next_token();
         }
+        None
+    }
+
+    // After a parse error, skips the rest of the broken statement so parsing can
+    // resume and report later errors. Stops on the first token of the next
+    // statement: after a `;`, after a balanced `{ ... }` block, or at a keyword
+    // that starts a statement. An unmatched `}` belongs to the enclosing block
+    // and is left as the current token.
+    fn synchronize(&mut self) {
+        let mut depth = 0;
+        loop {
+            match self.cur_token {
+                Token::Eof => return,
+                Token::Semicolon if depth == 0 => {
+                    self.next_token();
+                    return;
+                }
+                Token::LBrace => depth += 1,
+                Token::RBrace if depth == 0 => return,
+                Token::RBrace => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.next_token();
+                        return;
+                    }
+                }
+                _ => {}
+            }
 
-        Program { statements }
+            self.next_token();
+            if depth == 0 && starts_statement(&self.cur_token) {
+                return;
+            }
+        }
     }
 
     fn parse_statement(&mut self) -> Option<Statement> {
+        if !self.nest("statement") {
+            return None;
+        }
+        let stmt = self.parse_statement_unguarded();
+        self.depth -= 1;
+        stmt
+    }
+
+    fn parse_statement_unguarded(&mut self) -> Option<Statement> {
         match self.cur_token {
-            Token::Int => {
-                // Heuristic: if it's "int fname() {", it's a function.
-                // if it's "int x = 5;", it's a let/var decl.
-                // Ideally we check the peek token properly.
-                // Assuming "int <ident>"
-                if let Token::Identifier(_) = self.peek_token {
-                    // Peek one more to check if it's '(' (func) or '=' (var) or ';' (var)
-                    // But our Lexer doesn't support peeking 2 ahead easily unless we modify it or consume tokens.
-                    // Let's consume 'int'.
-
-                    // Actually, let's just cheat a bit and say 'int' starts a Let statement for now,
-                    // unless we see '(', then it's a function?
-                    // In C, `int main()` vs `int x;`.
-                    // A proper parser would handle types. Here, `int` is just a keyword starting a decl.
-
-                    // Let's implement parse_let_statement which might also turn out to be a function if we are at top level?
-                    // For simplicity, let's treat `Token::Int` as the start of a `Let` statement for now,
-                    // and handle functions separately or detect them here.
-                    self.parse_let_statement()
-                } else {
-                    None
-                }
-            }
+            // `int x ...` is a declaration; parse_let_statement turns it
+            // into a function definition when the name is followed by '('
+            Token::Int | Token::Char | Token::Void => self.parse_let_statement(false),
+            Token::Const => self.parse_const_statement(),
             Token::Return => self.parse_return_statement(),
             Token::LBrace => Some(Statement::Block(self.parse_block_statement())),
             Token::If => self.parse_if_statement(),
             Token::While => self.parse_while_statement(),
+            Token::Do => self.parse_do_while_statement(),
+            Token::Switch => self.parse_switch_statement(),
+            Token::Break => {
+                self.error(
+                    "break is only allowed at the end of a switch case; cases never fall through"
+                        .to_string(),
+                );
+                None
+            }
+            Token::For => self.parse_for_statement(),
+            Token::Include => {
+                self.error("#include is only allowed at the top level".to_string());
+                None
+            }
             _ => self.parse_expression_statement(),
         }
     }
 
-    fn parse_let_statement(&mut self) -> Option<Statement> {
-        // match `int identifier`
-        self.next_token(); // consume 'int' (or type keyword)
+    // #include "path", with an optional trailing semicolon
+    fn parse_include(&mut self) -> Option<Statement> {
+        let line = self.cur_span.line;
+        let path = match &self.peek_token {
+            Token::String(path) => path.clone(),
+            other => {
+                let message = format!("expected a quoted path after #include but found {}", other);
+                self.peek_error(message);
+                return None;
+            }
+        };
+        self.next_token();
+        if self.peek_token == Token::Semicolon {
+            self.next_token();
+        }
+        Some(Statement::Include { path, line })
+    }
+
+    // `const int MAX = 100;` declares variables that can't be assigned again
+    fn parse_const_statement(&mut self) -> Option<Statement> {
+        if Type::from_token(&self.peek_token).is_none() {
+            self.peek_error(format!(
+                "expected a type after 'const' but found {}",
+                self.peek_token
+            ));
+            return None;
+        }
+        self.next_token(); // consume 'const'
+        self.parse_let_statement(true)
+    }
+
+    fn parse_let_statement(&mut self, constant: bool) -> Option<Statement> {
+        let line = self.cur_span.line;
+        // match `type identifier`
+        let ty = Type::from_token(&self.cur_token)?;
+        self.next_token(); // consume type keyword
 
         let name = match &self.cur_token {
             Token::Identifier(n) => n.clone(),
-            _ => return None,
+            keyword if keyword.is_keyword() => {
+                self.error(keyword_as_identifier(keyword));
+                return None;
+            }
+            other => {
+                self.error(format!(
+                    "expected an identifier after {} but found {}",
+                    ty, other
+                ));
+                return None;
+            }
         };
 
         if self.peek_token == Token::LParen {
+            if constant {
+                self.error(format!("function {} cannot be declared const", name));
+                return None;
+            }
             // It's a function definition! `int main() { ... }`
-            return self.parse_function_statement(name);
+            return self.parse_function_statement(ty, name, line);
         }
 
-        if !self.expect_peek(Token::Assign) {
-            // maybe `int x;`? Support uninitialized?
-            // For now, require assignment.
+        if ty == Type::Void {
+            self.error(format!("variable {} declared void", name));
             return None;
         }
 
-        self.next_token(); // consume '='
-
-        let value = self.parse_expression(Precedence::Lowest)?;
+        // `int a = 1, b, c = a + 1;` declares several variables left to right
+        let mut declarators = vec![];
+        let mut name = name;
+        loop {
+            let value = if self.peek_token == Token::Assign {
+                self.next_token(); // move to '='
+                self.next_token(); // consume '='
+                Some(self.parse_expression(Precedence::Lowest)?)
+            } else {
+                None
+            };
 
-        if self.peek_token == Token::Semicolon {
-            self.next_token();
+            match self.peek_token {
+                Token::Comma => {
+                    self.next_token();
+                    if let Token::Identifier(next) = &self.peek_token {
+                        let next = next.clone();
+                        declarators.push((name, value.unwrap_or(Expression::Integer(0))));
+                        name = next;
+                        self.next_token();
+                    } else if self.peek_token.is_keyword() {
+                        self.peek_error(keyword_as_identifier(&self.peek_token));
+                        // Recovery starts past the keyword rather than at it
+                        self.next_token();
+                        return None;
+                    } else {
+                        self.peek_error(format!(
+                            "expected an identifier after ',' in declaration but found {}",
+                            self.peek_token
+                        ));
+                        return None;
+                    }
+                }
+                Token::Semicolon => {
+                    self.next_token();
+                    declarators.push((name, value.unwrap_or(Expression::Integer(0))));
+                    break;
+                }
+                _ => match value {
+                    Some(value) => {
+                        declarators.push((name, value));
+                        break;
+                    }
+                    None => {
+                        self.peek_error(format!(
+                            "expected '=', ',' or ';' after declaration of {} but found {}",
+                            name, self.peek_token
+                        ));
+                        return None;
+                    }
+                },
+            }
         }
 
-        Some(Statement::Let { name, value })
+        if declarators.len() == 1 {
+            let (name, value) = declarators.remove(0);
+            Some(Statement::Let {
+                ty,
+                constant,
+                name,
+                value,
+                slot: None,
+                line,
+            })
+        } else {
+            Some(Statement::MultiLet {
+                ty,
+                constant,
+                declarators,
+                slots: None,
+                line,
+            })
+        }
     }
 
-    fn parse_function_statement(&mut self, name: String) -> Option<Statement> {
+    fn parse_function_statement(
+        &mut self,
+        return_type: Type,
+        name: Symbol,
+        line: usize,
+    ) -> Option<Statement> {
         // cur_token is Identifier(name). peek is LParen.
         self.next_token(); // consume Identifier. Now cur is LParen.
 
-        let params = self.parse_function_parameters()?;
+        let (params, unnamed) = self.parse_function_parameters()?;
+
+        // `int f(int x);` declares a function defined elsewhere, as in C
+        if self.peek_token == Token::Semicolon {
+            self.next_token();
+            return Some(Statement::Prototype {
+                return_type,
+                name,
+                params,
+                line,
+            });
+        }
+
+        // Only prototypes may leave parameters unnamed
+        if let Some(error) = unnamed {
+            self.errors.push(error);
+            return None;
+        }
+        let params = params
+            .into_iter()
+            .map(|(ty, name)| (ty, name.expect("every parameter is named")))
+            .collect();
 
         if !self.expect_peek(Token::LBrace) {
             return None;
@@ -137,37 +562,84 @@ impl<'a> Parser<'a> {
         let body = Statement::Block(self.parse_block_statement());
 
         Some(Statement::Function {
+            return_type,
             name,
-            params,
-            body: Box::new(body),
+            params: Rc::new(params),
+            body: Rc::new(body),
+            slot: None,
+            line,
         })
     }
 
-    fn parse_function_parameters(&mut self) -> Option<Vec<String>> {
-        let mut identifiers = vec![];
+    // Parses `(type name, ...)`. A parameter without a name is allowed here,
+    // since a prototype may omit it, and the error for the first one is
+    // returned for a definition to report.
+    fn parse_function_parameters(&mut self) -> Option<(Vec<Param>, Option<ParseError>)> {
+        let mut params = vec![];
+        let mut unnamed = None;
 
         if self.peek_token == Token::RParen {
             self.next_token();
-            return Some(identifiers);
+            return Some((params, unnamed));
         }
 
         self.next_token();
 
-        // Expect "int x" not just "x"
-        // Simplification: assume parameters are just typed `int x`
-        // We need to consume Type then Identifier.
+        // `f(void)` declares no parameters, as in C
+        if self.cur_token == Token::Void && self.peek_token == Token::RParen {
+            self.next_token();
+            return Some((params, unnamed));
+        }
 
         // Loop for params: type ident, type ident
         loop {
-            match self.cur_token {
-                Token::Int => {
+            let ty = match Type::from_token(&self.cur_token) {
+                Some(ty) => ty,
+                None => {
+                    self.error(format!(
+                        "expected a parameter type but found {}",
+                        self.cur_token
+                    ));
+                    return None;
+                }
+            };
+            match &self.peek_token {
+                Token::Comma | Token::RParen if ty != Type::Void => {
+                    if unnamed.is_none() {
+                        let message = format!(
+                            "expected a parameter name after {} but found {}",
+                            ty, self.peek_token
+                        );
+                        unnamed = Some(ParseError {
+                            message,
+                            line: self.peek_span.line,
+                            column: self.peek_span.column,
+                            file: None,
+                        });
+                    }
+                    params.push((ty, None));
+                }
+                _ => {
                     self.next_token(); // consume type
                     match &self.cur_token {
-                        Token::Identifier(ident) => identifiers.push(ident.clone()),
-                        _ => return None,
+                        Token::Identifier(ident) if ty == Type::Void => {
+                            self.error(format!("parameter {} declared void", ident));
+                            return None;
+                        }
+                        Token::Identifier(ident) => params.push((ty, Some(ident.clone()))),
+                        keyword if keyword.is_keyword() => {
+                            self.error(keyword_as_identifier(keyword));
+                            return None;
+                        }
+                        other => {
+                            self.error(format!(
+                                "expected a parameter name after {} but found {}",
+                                ty, other
+                            ));
+                            return None;
+                        }
                     }
                 }
-                _ => return None,
             }
 
             if self.peek_token == Token::Comma {
@@ -182,10 +654,11 @@ impl<'a> Parser<'a> {
             return None;
         }
 
-        Some(identifiers)
+        Some((params, unnamed))
     }
 
     fn parse_return_statement(&mut self) -> Option<Statement> {
+        let line = self.cur_span.line;
         self.next_token(); // consume 'return'
 
         let return_value = self.parse_expression(Precedence::Lowest)?;
@@ -194,7 +667,10 @@ impl<'a> Parser<'a> {
             self.next_token();
         }
 
-        Some(Statement::Return(return_value))
+        Some(Statement::Return {
+            value: return_value,
+            line,
+        })
     }
 
     fn parse_block_statement(&mut self) -> Vec<Statement> {
@@ -203,17 +679,25 @@ impl<'a> Parser<'a> {
 
         let mut statements = vec![];
 
-        while self.cur_token != Token::RBrace && self.cur_token != Token::EOF {
-            if let Some(stmt) = self.parse_statement() {
-                statements.push(stmt);
+        while self.cur_token != Token::RBrace && self.cur_token != Token::Eof {
+            match self.parse_statement() {
+                Some(stmt) => {
+                    statements.push(stmt);
+                    self.next_token();
+                }
+                None => self.synchronize(),
             }
-            self.next_token();
+        }
+
+        if self.cur_token == Token::Eof && !self.too_deep {
+            self.error("expected '}' but found end of file".to_string());
         }
 
         statements
     }
 
     fn parse_if_statement(&mut self) -> Option<Statement> {
+        let line = self.cur_span.line;
         if !self.expect_peek(Token::LParen) {
             return None;
         }
@@ -225,30 +709,26 @@ impl<'a> Parser<'a> {
             return None;
         }
 
-        if !self.expect_peek(Token::LBrace) {
-            return None;
-        }
-
-        let consequence = Box::new(Statement::Block(self.parse_block_statement()));
+        let consequence = self.parse_body()?;
         let mut alternative = None;
 
+        // Checked right after the consequence, so a dangling else binds to the innermost if
         if self.peek_token == Token::Else {
             self.next_token();
-
-            if !self.expect_peek(Token::LBrace) {
-                return None;
-            }
-            alternative = Some(Box::new(Statement::Block(self.parse_block_statement())));
+            // `else if` is just an if-statement as the alternative
+            alternative = Some(self.parse_body()?);
         }
 
         Some(Statement::If {
             condition,
             consequence,
             alternative,
+            line,
         })
     }
 
     fn parse_while_statement(&mut self) -> Option<Statement> {
+        let line = self.cur_span.line;
         if !self.expect_peek(Token::LParen) {
             return None;
         }
@@ -259,31 +739,286 @@ impl<'a> Parser<'a> {
         if !self.expect_peek(Token::RParen) {
             return None;
         }
-        if !self.expect_peek(Token::LBrace) {
+
+        let body = self.parse_body()?;
+
+        Some(Statement::While {
+            condition,
+            body,
+            line,
+        })
+    }
+
+    fn parse_do_while_statement(&mut self) -> Option<Statement> {
+        let line = self.cur_span.line;
+        let body = self.parse_body()?;
+
+        if !self.expect_peek(Token::While) || !self.expect_peek(Token::LParen) {
             return None;
         }
 
-        let body = Box::new(Statement::Block(self.parse_block_statement()));
+        self.next_token();
+        let condition = self.parse_expression(Precedence::Lowest)?;
 
-        Some(Statement::While { condition, body })
+        if !self.expect_peek(Token::RParen) {
+            return None;
+        }
+        if self.peek_token != Token::Semicolon {
+            self.peek_error(format!(
+                "expected ';' after do-while condition but found {}",
+                self.peek_token
+            ));
+            return None;
+        }
+        self.next_token();
+
+        Some(Statement::DoWhile {
+            body,
+            condition,
+            line,
+        })
+    }
+
+    fn parse_switch_statement(&mut self) -> Option<Statement> {
+        let line = self.cur_span.line;
+        if !self.expect_peek(Token::LParen) {
+            return None;
+        }
+
+        self.next_token();
+        let subject = self.parse_expression(Precedence::Lowest)?;
+
+        if !self.expect_peek(Token::RParen) || !self.expect_peek(Token::LBrace) {
+            return None;
+        }
+        self.next_token();
+
+        let mut cases = vec![];
+        let mut default = None;
+        // Labels written back to back (`case 1: case 2:`) share the next body
+        let mut pending = vec![];
+        let mut pending_default = false;
+
+        while self.cur_token != Token::RBrace {
+            let label = match self.cur_token {
+                Token::Case => {
+                    self.next_token();
+                    let label = self.parse_expression(Precedence::Lowest)?;
+                    if !is_case_literal(&label) {
+                        self.error(format!("case label must be a literal but found {}", label));
+                        return None;
+                    }
+                    Some(label)
+                }
+                Token::Default if default.is_some() || pending_default => {
+                    self.error("multiple default labels in switch".to_string());
+                    return None;
+                }
+                Token::Default => None,
+                _ => {
+                    self.error(format!(
+                        "expected 'case', 'default' or '}}' in switch but found {}",
+                        self.cur_token
+                    ));
+                    return None;
+                }
+            };
+
+            if !self.expect_peek(Token::Colon) {
+                return None;
+            }
+            self.next_token();
+
+            let (body, ended_by_break) = self.parse_case_body()?;
+            match label {
+                Some(label) => pending.push(label),
+                None => pending_default = true,
+            }
+            if body.is_empty() && !ended_by_break && self.cur_token != Token::RBrace {
+                continue;
+            }
+
+            for label in pending.drain(..) {
+                cases.push((label, body.clone()));
+            }
+            if pending_default {
+                pending_default = false;
+                default = Some(body);
+            }
+        }
+
+        Some(Statement::Switch {
+            subject,
+            cases,
+            default,
+            line,
+        })
+    }
+
+    // Parses the statements after a case label, up to the next label or the end
+    // of the switch. A `break;` may end the case, but since cases never fall
+    // through it is optional. Returns the body and whether it ended with `break`.
+    fn parse_case_body(&mut self) -> Option<(Vec<Statement>, bool)> {
+        let mut body = vec![];
+
+        loop {
+            match self.cur_token {
+                Token::Case | Token::Default | Token::RBrace => return Some((body, false)),
+                Token::Eof => {
+                    self.error("expected '}' but found end of file".to_string());
+                    return None;
+                }
+                Token::Break => {
+                    if !self.expect_peek(Token::Semicolon) {
+                        return None;
+                    }
+                    self.next_token();
+                    if !matches!(self.cur_token, Token::Case | Token::Default | Token::RBrace) {
+                        self.error(format!(
+                            "break must be the last statement of a case; cases never fall through, found {} after it",
+                            self.cur_token
+                        ));
+                        return None;
+                    }
+                    return Some((body, true));
+                }
+                _ => {
+                    body.push(self.parse_statement()?);
+                    self.next_token();
+                }
+            }
+        }
+    }
+
+    // Parses the statement after `if (...)`, `else` or a loop header: either a
+    // braced block or a single statement. A lone declaration is wrapped in a
+    // block, so it gets a scope of its own instead of leaking into the
+    // enclosing one.
+    fn parse_body(&mut self) -> Option<Box<Statement>> {
+        self.next_token();
+        if self.cur_token == Token::LBrace {
+            return Some(Box::new(Statement::Block(self.parse_block_statement())));
+        }
+        let stmt = self.parse_statement()?;
+        Some(Box::new(match stmt {
+            Statement::Let { .. } | Statement::MultiLet { .. } | Statement::Function { .. } => {
+                Statement::Block(vec![stmt])
+            }
+            stmt => stmt,
+        }))
+    }
+
+    fn parse_for_statement(&mut self) -> Option<Statement> {
+        let line = self.cur_span.line;
+        if !self.expect_peek(Token::LParen) {
+            return None;
+        }
+
+        self.next_token(); // consume LParen
+
+        // Each clause is optional: `for (;;)` loops forever
+        let init = if self.cur_token == Token::Semicolon {
+            None
+        } else {
+            let stmt = match self.cur_token {
+                Token::Int | Token::Char => self.parse_let_statement(false)?,
+                Token::Const => self.parse_const_statement()?,
+                _ => self.parse_expression_statement()?,
+            };
+            if self.cur_token != Token::Semicolon {
+                self.next_token(); // move to what follows the initializer
+                self.error(format!(
+                    "expected ';' after for initializer but found {}",
+                    self.cur_token
+                ));
+                return None;
+            }
+            Some(Box::new(stmt))
+        };
+
+        let condition = if self.peek_token == Token::Semicolon {
+            None
+        } else {
+            self.next_token();
+            Some(self.parse_expression(Precedence::Lowest)?)
+        };
+
+        if !self.expect_peek(Token::Semicolon) {
+            return None;
+        }
+
+        let post = if self.peek_token == Token::RParen {
+            None
+        } else {
+            self.next_token();
+            Some(self.parse_expression(Precedence::Lowest)?)
+        };
+
+        if !self.expect_peek(Token::RParen) {
+            return None;
+        }
+
+        let body = self.parse_body()?;
+
+        Some(Statement::For {
+            init,
+            condition,
+            post,
+            body,
+            line,
+        })
     }
 
     fn parse_expression_statement(&mut self) -> Option<Statement> {
+        let line = self.cur_span.line;
         let expr = self.parse_expression(Precedence::Lowest)?;
 
         if self.peek_token == Token::Semicolon {
             self.next_token();
         }
 
-        Some(Statement::Expression(expr))
+        Some(Statement::Expression { expr, line })
     }
 
     fn parse_expression(&mut self, precedence: Precedence) -> Option<Expression> {
+        if !self.nest("expression") {
+            return None;
+        }
+        let expr = self.parse_expression_unguarded(precedence);
+        self.depth -= 1;
+        expr
+    }
+
+    // Goes one level of nesting deeper, or reports that's too deep. Recovering
+    // from there would only find the same problem again further on, so the
+    // rest of the input is skipped.
+    fn nest(&mut self, what: &str) -> bool {
+        if self.depth >= MAX_NESTING {
+            self.error(format!("{} nesting too deep", what));
+            self.too_deep = true;
+            while self.cur_token != Token::Eof {
+                self.next_token();
+            }
+            return false;
+        }
+        self.depth += 1;
+        true
+    }
+
+    fn parse_expression_unguarded(&mut self, precedence: Precedence) -> Option<Expression> {
         let mut left = match &self.cur_token {
             Token::Identifier(i) => Expression::Identifier(i.clone()),
             Token::Integer(i) => Expression::Integer(*i),
+            Token::Float(n) => Expression::Float(*n),
             Token::String(s) => Expression::String(s.clone()),
-            Token::Minus => {
+            Token::Character(c) => Expression::Character(*c),
+            Token::True => Expression::Boolean(true),
+            Token::False => Expression::Boolean(false),
+            Token::Minus if is_i64_min_magnitude(&self.peek_token) => {
+                self.next_token();
+                Expression::Integer(i64::MIN)
+            }
+            Token::Minus | Token::Bang | Token::Tilde => {
                 let op = self.cur_token.clone();
                 self.next_token();
                 let right = self.parse_expression(Precedence::Prefix)?;
@@ -292,6 +1027,12 @@ impl<'a> Parser<'a> {
                     right: Box::new(right),
                 }
             }
+            Token::Increment | Token::Decrement => {
+                let operator = self.cur_token.clone();
+                self.next_token();
+                let target = self.parse_expression(Precedence::Prefix)?;
+                self.parse_update_expression(operator, target, true)?
+            }
             Token::LParen => {
                 self.next_token();
                 let expr = self.parse_expression(Precedence::Lowest)?;
@@ -300,7 +1041,29 @@ impl<'a> Parser<'a> {
                 }
                 expr
             }
-            _ => return None,
+            Token::LBracket => {
+                Expression::ArrayLiteral(self.parse_expression_list(Token::RBracket)?)
+            }
+            Token::Illegal(_) | Token::Malformed(_) => {
+                if let Some(message) = self.cur_token.lex_error() {
+                    self.error(message);
+                }
+                return None;
+            }
+            // Call syntax on a keyword, as in `x = default(1);`. A stray
+            // `else (...)` is more likely a misplaced else than a call.
+            keyword
+                if keyword.is_keyword()
+                    && *keyword != Token::Else
+                    && self.peek_token == Token::LParen =>
+            {
+                self.error(keyword_as_identifier(keyword));
+                return None;
+            }
+            other => {
+                self.error(format!("expected an expression but found {}", other));
+                return None;
+            }
         };
 
         while self.peek_token != Token::Semicolon && precedence < token_precedence(&self.peek_token)
@@ -310,14 +1073,46 @@ impl<'a> Parser<'a> {
                     self.next_token();
                     left = self.parse_call_expression(left)?;
                 }
+                Token::LBracket => {
+                    self.next_token();
+                    left = self.parse_index_expression(left)?;
+                }
+                Token::Question => {
+                    self.next_token();
+                    left = self.parse_ternary_expression(left)?;
+                }
+                Token::Increment | Token::Decrement => {
+                    self.next_token();
+                    let operator = self.cur_token.clone();
+                    left = self.parse_update_expression(operator, left, false)?;
+                }
+                Token::Assign
+                | Token::PlusAssign
+                | Token::MinusAssign
+                | Token::AsteriskAssign
+                | Token::SlashAssign
+                | Token::PercentAssign => {
+                    self.next_token();
+                    left = self.parse_assign_expression(left)?;
+                }
                 Token::Plus
                 | Token::Minus
                 | Token::Slash
                 | Token::Asterisk
+                | Token::Percent
                 | Token::Equal
                 | Token::NotEqual
                 | Token::LessThan
-                | Token::GreaterThan => {
+                | Token::GreaterThan
+                | Token::LessEqual
+                | Token::GreaterEqual
+                | Token::And
+                | Token::Or
+                | Token::Ampersand
+                | Token::Pipe
+                | Token::Caret
+                | Token::ShiftLeft
+                | Token::ShiftRight => {
                     self.next_token();
                     let op = self.cur_token.clone();
 
@@ -337,35 +1132,148 @@ impl<'a> Parser<'a> {
         Some(left)
     }
 
+    fn parse_assign_expression(&mut self, target: Expression) -> Option<Expression> {
+        // cur_token is Assign or a compound assignment like PlusAssign
+        let operator = match self.cur_token {
+            Token::PlusAssign => Some(Token::Plus),
+            Token::MinusAssign => Some(Token::Minus),
+            Token::AsteriskAssign => Some(Token::Asterisk),
+            Token::SlashAssign => Some(Token::Slash),
+            Token::PercentAssign => Some(Token::Percent),
+            _ => None,
+        };
+        match target {
+            Expression::Identifier(ref name) => Some(Expression::Assign {
+                name: name.clone(),
+                value: self.parse_assigned_value(operator, target)?,
+                slot: None,
+            }),
+            // The array and index may have side effects, so a compound
+            // assignment keeps its operator to evaluate them only once
+            Expression::Index { left, index } => {
+                self.next_token();
+                let value = self.parse_expression(Precedence::Lowest)?;
+                Some(Expression::IndexAssign {
+                    left,
+                    index,
+                    operator,
+                    value: Box::new(value),
+                })
+            }
+            other => {
+                self.error(format!("invalid assignment target: {}", other));
+                None
+            }
+        }
+    }
+
+    // Parses the right-hand side of an assignment to `target`
+    fn parse_assigned_value(
+        &mut self,
+        operator: Option<Token>,
+        target: Expression,
+    ) -> Option<Box<Expression>> {
+        self.next_token();
+        // Assignment is right-associative: `a = b = 1` assigns `b` first
+        let value = self.parse_expression(Precedence::Lowest)?;
+
+        // `x += v` is sugar for `x = x + v`
+        match operator {
+            Some(operator) => Some(Box::new(Expression::Infix {
+                left: Box::new(target),
+                operator,
+                right: Box::new(value),
+            })),
+            None => Some(Box::new(value)),
+        }
+    }
+
+    fn parse_ternary_expression(&mut self, condition: Expression) -> Option<Expression> {
+        // cur_token is Question
+        self.next_token();
+        let then = self.parse_expression(Precedence::Lowest)?;
+
+        if !self.expect_peek(Token::Colon) {
+            return None;
+        }
+        self.next_token();
+        // Parsing the else branch just below Ternary makes `a ? b : c ? d : e`
+        // group to the right, as in C
+        let otherwise = self.parse_expression(Precedence::Assign)?;
+
+        Some(Expression::Ternary {
+            condition: Box::new(condition),
+            then: Box::new(then),
+            otherwise: Box::new(otherwise),
+        })
+    }
+
+    fn parse_update_expression(
+        &mut self,
+        operator: Token,
+        target: Expression,
+        prefix: bool,
+    ) -> Option<Expression> {
+        match target {
+            Expression::Identifier(name) => Some(Expression::Update {
+                name,
+                operator,
+                prefix,
+                slot: None,
+            }),
+            other => {
+                self.error(format!("invalid {} target: {}", operator, other));
+                None
+            }
+        }
+    }
+
     fn parse_call_expression(&mut self, function: Expression) -> Option<Expression> {
         // cur_token is LParen
-        let mut args = vec![];
+        let arguments = self.parse_expression_list(Token::RParen)?;
+        Some(Expression::Call {
+            function: Box::new(function),
+            arguments,
+        })
+    }
 
-        if self.peek_token == Token::RParen {
+    fn parse_index_expression(&mut self, left: Expression) -> Option<Expression> {
+        // cur_token is LBracket
+        self.next_token();
+        let index = self.parse_expression(Precedence::Lowest)?;
+        if !self.expect_peek(Token::RBracket) {
+            return None;
+        }
+        Some(Expression::Index {
+            left: Box::new(left),
+            index: Box::new(index),
+        })
+    }
+
+    // Parses comma-separated expressions up to `end`, for call arguments and
+    // array literals. cur_token is the opening delimiter.
+    fn parse_expression_list(&mut self, end: Token) -> Option<Vec<Expression>> {
+        let mut list = vec![];
+
+        if self.peek_token == end {
             self.next_token();
-            return Some(Expression::Call {
-                function: Box::new(function),
-                arguments: args,
-            });
+            return Some(list);
         }
 
         self.next_token();
-        args.push(self.parse_expression(Precedence::Lowest)?);
+        list.push(self.parse_expression(Precedence::Lowest)?);
 
         while self.peek_token == Token::Comma {
             self.next_token();
             self.next_token();
-            args.push(self.parse_expression(Precedence::Lowest)?);
+            list.push(self.parse_expression(Precedence::Lowest)?);
         }
 
-        if !self.expect_peek(Token::RParen) {
+        if !self.expect_peek(end) {
             return None;
         }
 
-        Some(Expression::Call {
-            function: Box::new(function),
-            arguments: args,
-        })
+        Some(list)
     }
 
     fn expect_peek(&mut self, expected: Token) -> bool {
@@ -373,12 +1281,667 @@ impl<'a> Parser<'a> {
             self.next_token();
             true
         } else {
-            // Here we could add an error "Expected X got Y"
-            self.errors.push(format!(
-                "Expected {:?}, got {:?}",
+            self.peek_error(format!(
+                "expected {} but found {}",
                 expected, self.peek_token
             ));
             false
         }
     }
+
+    // Records an error at the current token
+    fn error(&mut self, message: String) {
+        self.errors.push(ParseError {
+            message,
+            line: self.cur_span.line,
+            column: self.cur_span.column,
+            file: None,
+        });
+    }
+
+    fn error_at(&mut self, span: Span, message: String) {
+        self.errors.push(ParseError {
+            message,
+            line: span.line,
+            column: span.column,
+            file: None,
+        });
+    }
+
+    // Records an error at the peek token, for when the next token is the unexpected one
+    fn peek_error(&mut self, message: String) {
+        self.errors.push(ParseError {
+            message,
+            line: self.peek_span.line,
+            column: self.peek_span.column,
+            file: None,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_errors(input: &str) -> Vec<ParseError> {
+        let mut parser = Parser::new(Lexer::new(input));
+        parser.parse_program();
+        parser.errors
+    }
+
+    #[test]
+    fn test_error_location() {
+        let errors = parse_errors("int x = 1;\nif (x > 0 {\n}");
+        assert_eq!(
+            errors[0],
+            ParseError {
+                message: "expected ')' but found '{'".to_string(),
+                line: 2,
+                column: 11,
+                file: None,
+            }
+        );
+        assert_eq!(errors[0].to_string(), "2:11: expected ')' but found '{'");
+    }
+
+    #[test]
+    fn test_overflowing_literal_is_error() {
+        let errors = parse_errors("int x = 99999999999999999999;");
+        assert_eq!(
+            errors[0].to_string(),
+            "1:9: integer literal out of range: 99999999999999999999"
+        );
+
+        // C would read this as octal
+        assert_eq!(
+            parse_errors("010;")[0].message,
+            "integer literal with a leading zero: 010"
+        );
+    }
+
+    #[test]
+    fn test_i64_min_literal() {
+        // Only in range once negated, so it's a literal rather than a negation
+        assert_eq!(dump("-9223372036854775808;"), "-9223372036854775808;\n");
+        assert_eq!(
+            dump("-9223372036854775808 * 2;"),
+            "(-9223372036854775808 * 2);\n"
+        );
+        assert_eq!(dump("-9223372036854775807;"), "(-9223372036854775807);\n");
+        // Subtracting it is still out of range
+        assert_eq!(
+            parse_errors("1 - 9223372036854775808;")[0].message,
+            "integer literal out of range: 9223372036854775808"
+        );
+    }
+
+    #[test]
+    fn test_void_functions() {
+        let mut parser = Parser::new(Lexer::new("void f() {} int main(void) {}"));
+        let program = parser.parse_program();
+        assert!(parser.errors.is_empty());
+        assert_eq!(
+            program.statements,
+            vec![
+                Statement::Function {
+                    return_type: Type::Void,
+                    name: "f".into(),
+                    params: Rc::new(vec![]),
+                    body: Rc::new(Statement::Block(vec![])),
+                    slot: None,
+                    line: 1,
+                },
+                Statement::Function {
+                    return_type: Type::Int,
+                    name: "main".into(),
+                    params: Rc::new(vec![]),
+                    body: Rc::new(Statement::Block(vec![])),
+                    slot: None,
+                    line: 1,
+                },
+            ]
+        );
+
+        let errors = parse_errors("void x = 5;");
+        assert_eq!(errors[0].to_string(), "1:6: variable x declared void");
+    }
+
+    #[test]
+    fn test_prototypes() {
+        let mut parser = Parser::new(Lexer::new("int f(int x, char);"));
+        let program = parser.parse_program();
+        assert!(parser.errors.is_empty(), "{:?}", parser.errors);
+        assert_eq!(
+            program.statements,
+            vec![Statement::Prototype {
+                return_type: Type::Int,
+                name: "f".into(),
+                params: vec![(Type::Int, Some("x".into())), (Type::Char, None)],
+                line: 1,
+            }]
+        );
+        assert_eq!(
+            dump("void g(void); int h();"),
+            "Prototype void g();\nPrototype int h();\n"
+        );
+
+        // Only a prototype may leave a parameter unnamed
+        assert_eq!(
+            parse_errors("int f(int a, int, int) { return a; }")[0].to_string(),
+            "1:17: expected a parameter name after int but found ','"
+        );
+        assert_eq!(
+            parse_errors("int f(void, int);")[0].to_string(),
+            "1:11: expected a parameter name after void but found ','"
+        );
+    }
+
+    #[test]
+    fn test_every_failed_statement_is_reported() {
+        let errors = parse_errors("int = 5;\nreturn ;\nint f(x) {}\nint g(int) {}\nwhile (1) {");
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "1:5: expected an identifier after int but found '='",
+                "2:8: expected an expression but found ';'",
+                "3:7: expected a parameter type but found identifier 'x'",
+                "4:10: expected a parameter name after int but found ')'",
+                "5:12: expected '}' but found end of file",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_keywords_as_identifiers() {
+        let keywords = [
+            "int", "char", "void", "return", "if", "else", "while", "do", "switch", "case",
+            "default", "break", "for", "const", "true", "false",
+        ];
+        for kw in keywords {
+            let message = format!("cannot use keyword '{}' as an identifier", kw);
+            let cases = [
+                (format!("int {} = 3;", kw), 5),
+                (format!("int a, {};", kw), 8),
+                (format!("int {}() {{ return 1; }}", kw), 5),
+                (format!("int f(int {}) {{ return 1; }}", kw), 11),
+                (format!("int x = {}(1);", kw), 9),
+            ];
+            for (input, column) in cases {
+                // A stray `else (` reads as a misplaced else, not a call,
+                // and `true(1)` as a call on a literal
+                if matches!(kw, "else" | "true" | "false") && input.starts_with("int x") {
+                    continue;
+                }
+                let errors = parse_errors(&format!("{}\nputs(1);", input));
+                let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                assert_eq!(
+                    messages,
+                    vec![format!("1:{}: {}", column, message)],
+                    "{}",
+                    input
+                );
+            }
+        }
+
+        // Statements that begin with a keyword and a parenthesis are still fine
+        assert_eq!(dump("return(5);"), "Return 5;\n");
+        assert_eq!(dump("while(0) x;"), "While (0) x;\n");
+        assert_eq!(
+            parse_errors("x = else(1);")[0].message,
+            "expected an expression but found 'else'"
+        );
+    }
+
+    #[test]
+    fn test_boolean_literals() {
+        let mut parser = Parser::new(Lexer::new("int b = false;"));
+        let program = parser.parse_program();
+        assert!(parser.errors.is_empty());
+        assert_eq!(
+            program.statements,
+            vec![Statement::Let {
+                ty: Type::Int,
+                constant: false,
+                name: "b".into(),
+                value: Expression::Boolean(false),
+                slot: None,
+                line: 1,
+            }]
+        );
+        assert_eq!(dump("if (true) x;"), "If (true) x;\n");
+        assert_eq!(dump("!true == false;"), "((!true) == false);\n");
+
+        assert_eq!(
+            parse_errors("true = 1;")[0].message,
+            "invalid assignment target: true"
+        );
+    }
+
+    #[test]
+    fn test_recovery_keeps_later_statements() {
+        let mut parser = Parser::new(Lexer::new("{ int = 1; int y = 2; }"));
+        let program = parser.parse_program();
+        assert_eq!(parser.errors.len(), 1);
+        assert_eq!(
+            program.statements,
+            vec![Statement::Block(vec![Statement::Let {
+                ty: Type::Int,
+                constant: false,
+                name: "y".into(),
+                value: Expression::Integer(2),
+                slot: None,
+                line: 1,
+            }])]
+        );
+    }
+
+    #[test]
+    fn test_synchronize_reports_each_error_once() {
+        let errors = parse_errors(include_str!("../tests/fixtures/parse_errors.c"));
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "3:9: expected an identifier after int but found '='",
+                "7:19: expected ')' but found '{'",
+                "12:17: expected ')' but found identifier 'count'",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_deep_nesting_is_an_error() {
+        let n = 100_000;
+        let inputs = [
+            (
+                format!("{}1{};", "(".repeat(n), ")".repeat(n)),
+                "expression",
+            ),
+            (format!("{};", "!".repeat(n)), "expression"),
+            (format!("{}{}", "{".repeat(n), "}".repeat(n)), "statement"),
+            ("{".repeat(n), "statement"),
+            (
+                format!("int x; {}x = 2;", "if (x) x = 1; else ".repeat(n)),
+                "expression",
+            ),
+        ];
+        for (input, what) in inputs {
+            let errors = parse_errors(&input);
+            let messages: Vec<String> = errors.iter().map(|e| e.message.clone()).collect();
+            assert_eq!(messages, vec![format!("{} nesting too deep", what)]);
+        }
+
+        // Well within the limit is fine
+        let nested = format!("{}1{};", "(".repeat(200), ")".repeat(200));
+        assert_eq!(dump(&nested), "1;\n");
+    }
+
+    // Parses without errors and renders the AST dump
+    fn dump(input: &str) -> String {
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+        assert_eq!(parser.errors, vec![]);
+        program.to_string()
+    }
+
+    #[test]
+    fn test_else_if_chain() {
+        assert_eq!(
+            dump("if (x) { a; } else if (y) { b; } else { c; }"),
+            "If (x) {\n    a;\n} Else If (y) {\n    b;\n} Else {\n    c;\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_single_statement_if() {
+        assert_eq!(
+            dump("if (x) return 1; else x = 2;"),
+            "If (x) Return 1; Else (x = 2);\n"
+        );
+    }
+
+    #[test]
+    fn test_single_statement_loops() {
+        assert_eq!(dump("while (x) x = x - 1;"), "While (x) (x = (x - 1));\n");
+        assert_eq!(
+            dump("for (int i = 0; i < 3; i = i + 1) puts(i);"),
+            "For (Let int i = 0; (i < 3); (i = (i + 1))) puts(i);\n"
+        );
+    }
+
+    #[test]
+    fn test_for_initializer_error() {
+        // Reported at the token after the initializer, not the initializer's last
+        for input in ["for (int i = 0 j < 3; i++) {}", "for (i = 0 j < 3; i++) {}"] {
+            let column = input.find('j').unwrap() + 1;
+            assert_eq!(
+                parse_errors(input)[0].to_string(),
+                format!(
+                    "1:{}: expected ';' after for initializer but found identifier 'j'",
+                    column
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn test_do_while() {
+        assert_eq!(
+            dump("do { x = x + 1; } while (x < 3);"),
+            "Do {\n    (x = (x + 1));\n} While ((x < 3));\n"
+        );
+
+        let errors = parse_errors("do { x; } while (x) y;");
+        assert_eq!(
+            errors[0].to_string(),
+            "1:21: expected ';' after do-while condition but found identifier 'y'"
+        );
+    }
+
+    #[test]
+    fn test_switch() {
+        assert_eq!(
+            dump("switch (x) { case 1: case 2: a; break; default: b; }"),
+            "Switch (x) {\n    Case 1:\n        a;\n    Case 2:\n        a;\n    Default:\n        b;\n}\n"
+        );
+
+        let messages: Vec<String> = parse_errors(
+            "switch (x) { case 1: break; a; }\nswitch (x) { case y: a; }\nbreak;\nswitch (x) { default: a; default: b; }",
+        )
+        .iter()
+        .map(|e| e.to_string())
+        .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "1:29: break must be the last statement of a case; cases never fall through, found identifier 'a' after it",
+                "2:19: case label must be a literal but found y",
+                "3:1: break is only allowed at the end of a switch case; cases never fall through",
+                "4:26: multiple default labels in switch",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compound_assignment() {
+        assert_eq!(dump("x += 1 + 2;"), "(x = (x + (1 + 2)));\n");
+        assert_eq!(dump("a += b -= 1;"), "(a = (a + (b = (b - 1))));\n");
+        assert_eq!(
+            dump("x *= 2; x /= 3; x %= 4;"),
+            "(x = (x * 2));\n(x = (x / 3));\n(x = (x % 4));\n"
+        );
+    }
+
+    #[test]
+    fn test_float_literals() {
+        assert_eq!(dump("1.5 * -2.0e3;"), "(1.5 * (-2000.0));\n");
+        assert_eq!(parse_errors("1e999;")[0].message, "illegal token '1e999'");
+    }
+
+    #[test]
+    fn test_arrays() {
+        assert_eq!(
+            dump("int a = [1, [2], f(3)];"),
+            "Let int a = [1, [2], f(3)];\n"
+        );
+        assert_eq!(dump("a[i + 1][0] * 2;"), "(((a[(i + 1)])[0]) * 2);\n");
+        assert_eq!(dump("a[0] = b[1] = 2;"), "(a[0] = (b[1] = 2));\n");
+        assert_eq!(dump("a[i] += 1;"), "(a[i] += 1);\n");
+        assert_eq!(dump("f()[0];"), "(f()[0]);\n");
+        assert_eq!(parse_errors("[1, 2;").len(), 1);
+    }
+
+    #[test]
+    fn test_increment_decrement() {
+        assert_eq!(dump("++i;"), "(++i);\n");
+        assert_eq!(dump("a = i-- + 1;"), "(a = ((i--) + 1));\n");
+        assert_eq!(dump("-i++;"), "(-(i++));\n");
+
+        let errors = parse_errors("(a + b)++;\n++5;");
+        assert_eq!(errors[0].to_string(), "1:8: invalid '++' target: (a + b)");
+        assert_eq!(errors[1].to_string(), "2:3: invalid '++' target: 5");
+    }
+
+    #[test]
+    fn test_binary_operators_are_left_associative() {
+        let ident = |name: &str| Box::new(Expression::Identifier(name.into()));
+        let mut parser = Parser::new(Lexer::new("a - b - c;"));
+        let program = parser.parse_program();
+        assert_eq!(
+            program.statements,
+            vec![Statement::Expression {
+                expr: Expression::Infix {
+                    left: Box::new(Expression::Infix {
+                        left: ident("a"),
+                        operator: Token::Minus,
+                        right: ident("b"),
+                    }),
+                    operator: Token::Minus,
+                    right: ident("c"),
+                },
+                line: 1,
+            }]
+        );
+
+        assert_eq!(dump("a / b / c;"), "((a / b) / c);\n");
+        assert_eq!(dump("a - b * c;"), "(a - (b * c));\n");
+        assert_eq!(dump("a - b + c - d;"), "(((a - b) + c) - d);\n");
+        assert_eq!(dump("a << b >> c;"), "((a << b) >> c);\n");
+        assert_eq!(dump("a == b != c;"), "((a == b) != c);\n");
+    }
+
+    #[test]
+    fn test_chained_calls() {
+        let call = |function: Expression, arguments: Vec<Expression>| Expression::Call {
+            function: Box::new(function),
+            arguments,
+        };
+        let mut parser = Parser::new(Lexer::new("f()(1)(2);"));
+        let program = parser.parse_program();
+        assert_eq!(
+            program.statements,
+            vec![Statement::Expression {
+                expr: call(
+                    call(
+                        call(Expression::Identifier("f".into()), vec![]),
+                        vec![Expression::Integer(1)]
+                    ),
+                    vec![Expression::Integer(2)]
+                ),
+                line: 1,
+            }]
+        );
+
+        assert_eq!(dump("(f)(x);"), "f(x);\n");
+        assert_eq!(dump("fs[0](1)(2);"), "(fs[0])(1)(2);\n");
+        assert_eq!(dump("apply(f, 3) + 1;"), "(apply(f, 3) + 1);\n");
+        assert_eq!(dump("-f(1)(2);"), "(-f(1)(2));\n");
+    }
+
+    #[test]
+    fn test_bitwise_precedence() {
+        assert_eq!(dump("a | b ^ c & d;"), "(a | (b ^ (c & d)));\n");
+        assert_eq!(dump("a & b == c;"), "(a & (b == c));\n");
+        assert_eq!(dump("1 << 2 + 3 < 4;"), "((1 << (2 + 3)) < 4);\n");
+        assert_eq!(dump("a || b | c && d;"), "(a || ((b | c) && d));\n");
+        assert_eq!(dump("~x & 1;"), "((~x) & 1);\n");
+    }
+
+    #[test]
+    fn test_ternary() {
+        assert_eq!(dump("a ? b : c ? d : e;"), "(a ? b : (c ? d : e));\n");
+        assert_eq!(
+            dump("x = a == 1 ? b + 1 : c || d;"),
+            "(x = ((a == 1) ? (b + 1) : (c || d)));\n"
+        );
+        assert_eq!(dump("a ? b ? c : d : e;"), "(a ? (b ? c : d) : e);\n");
+
+        let errors = parse_errors("a ? b;");
+        assert_eq!(errors[0].to_string(), "1:6: expected ':' but found ';'");
+    }
+
+    #[test]
+    fn test_dangling_else_binds_to_inner_if() {
+        assert_eq!(
+            dump("if (a) if (b) x; else y;"),
+            "If (a) If (b) x; Else y;\n"
+        );
+
+        let mut parser = Parser::new(Lexer::new("if (a) if (b) x; else y;"));
+        let program = parser.parse_program();
+        match &program.statements[0] {
+            Statement::If {
+                consequence,
+                alternative,
+                ..
+            } => {
+                assert!(alternative.is_none());
+                assert!(matches!(
+                    **consequence,
+                    Statement::If {
+                        alternative: Some(_),
+                        ..
+                    }
+                ));
+            }
+            other => panic!("expected if statement, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_uninitialized_declaration() {
+        let mut parser = Parser::new(Lexer::new("int x;"));
+        let program = parser.parse_program();
+        assert!(parser.errors.is_empty());
+        assert_eq!(
+            program.statements,
+            vec![Statement::Let {
+                ty: Type::Int,
+                constant: false,
+                name: "x".into(),
+                value: Expression::Integer(0),
+                slot: None,
+                line: 1,
+            }]
+        );
+
+        let errors = parse_errors("int x 5;");
+        assert_eq!(
+            errors[0].message,
+            "expected '=', ',' or ';' after declaration of x but found integer '5'"
+        );
+    }
+
+    #[test]
+    fn test_const_declarations() {
+        let mut parser = Parser::new(Lexer::new("const int MAX = 100;"));
+        let program = parser.parse_program();
+        assert!(parser.errors.is_empty());
+        assert_eq!(
+            program.statements,
+            vec![Statement::Let {
+                ty: Type::Int,
+                constant: true,
+                name: "MAX".into(),
+                value: Expression::Integer(100),
+                slot: None,
+                line: 1,
+            }]
+        );
+        assert_eq!(
+            dump("const char a = 'a', b = 'b'; for (const int i = 0; ; ) {}"),
+            "Let const char a = 'a', b = 'b';\nFor (Let const int i = 0; ; ) {\n}\n"
+        );
+
+        let errors: Vec<String> =
+            parse_errors("const x = 1;\nconst int f() { return 1; }\nconst int const = 2;")
+                .iter()
+                .map(|err| err.to_string())
+                .collect();
+        assert_eq!(
+            errors,
+            vec![
+                "1:7: expected a type after 'const' but found identifier 'x'",
+                "2:11: function f cannot be declared const",
+                "3:11: cannot use keyword 'const' as an identifier",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_multiple_declarators() {
+        let mut parser = Parser::new(Lexer::new("int a = 1, b, c = a + 1;"));
+        let program = parser.parse_program();
+        assert!(parser.errors.is_empty());
+        assert_eq!(
+            program.statements,
+            vec![Statement::MultiLet {
+                ty: Type::Int,
+                constant: false,
+                declarators: vec![
+                    ("a".into(), Expression::Integer(1)),
+                    ("b".into(), Expression::Integer(0)),
+                    (
+                        "c".into(),
+                        Expression::Infix {
+                            left: Box::new(Expression::Identifier("a".into())),
+                            operator: Token::Plus,
+                            right: Box::new(Expression::Integer(1)),
+                        }
+                    ),
+                ],
+                slots: None,
+                line: 1,
+            }]
+        );
+
+        let errors = parse_errors("int a = 1,;");
+        assert_eq!(
+            errors[0].message,
+            "expected an identifier after ',' in declaration but found ';'"
+        );
+    }
+
+    #[test]
+    fn test_define() {
+        let input = "#define MAX 100\n#define LIMIT (MAX + 1)\nint x = LIMIT;\nputs(\"MAX\");\n#undef MAX\nMAX;";
+        assert_eq!(
+            dump(input),
+            "Let int x = (100 + 1);\nputs(\"MAX\");\nMAX;\n"
+        );
+        // An empty definition expands to nothing; a name can't expand to itself
+        assert_eq!(dump("#define EMPTY\n#define N N\nEMPTY N;"), "N;\n");
+        // Identical redefinitions are allowed
+        assert!(parse_errors("#define A 1\n#define A 1\nA;").is_empty());
+    }
+
+    #[test]
+    fn test_define_errors_keep_original_lines() {
+        let errors = parse_errors("#define BAD 1 +\n\nint x = BAD;");
+        assert_eq!(
+            errors[0].to_string(),
+            "3:12: expected an expression but found ';'"
+        );
+
+        let errors = parse_errors("#define A 1\n#define A 2\n#define\nA;");
+        assert_eq!(
+            errors[0].to_string(),
+            "2:1: A is already defined; #undef it before redefining"
+        );
+        assert_eq!(errors[1].to_string(), "3:1: expected a name after #define");
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    fn test_define_with_illegal_token() {
+        // Reported once, where it's written, not again wherever the name is used
+        let errors = parse_errors(
+            "#define BAD 1 @ 2
+int x = BAD;
+int y = BAD;",
+        );
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(messages, vec!["1:15: illegal token '@'"]);
+    }
 }
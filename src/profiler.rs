@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// Calls to one function and the time spent inside them
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FunctionProfile {
+    pub calls: u64,
+    pub total: Duration,
+}
+
+// Per-function call counts and times for --profile. Only the outermost of
+// nested calls to the same function is timed, so a recursive function's total
+// is the time spent inside it, not that time multiplied by its depth.
+#[derive(Debug, Default)]
+pub struct Profiler {
+    functions: HashMap<String, FunctionProfile>,
+    active: HashMap<String, (usize, Instant)>, // Calls in progress and when the outermost began
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn enter(&mut self, name: &str) {
+        self.functions.entry(name.to_string()).or_default().calls += 1;
+        let (depth, start) = self
+            .active
+            .entry(name.to_string())
+            .or_insert((0, Instant::now()));
+        if *depth == 0 {
+            *start = Instant::now();
+        }
+        *depth += 1;
+    }
+
+    pub fn exit(&mut self, name: &str) {
+        let Some((depth, start)) = self.active.get_mut(name) else {
+            return;
+        };
+        *depth -= 1;
+        if *depth == 0 {
+            let elapsed = start.elapsed();
+            self.functions.entry(name.to_string()).or_default().total += elapsed;
+        }
+    }
+
+    // Every function called, slowest first
+    pub fn functions(&self) -> Vec<(&str, &FunctionProfile)> {
+        let mut functions: Vec<(&str, &FunctionProfile)> = self
+            .functions
+            .iter()
+            .map(|(name, profile)| (name.as_str(), profile))
+            .collect();
+        functions.sort_by(|a, b| b.1.total.cmp(&a.1.total).then(a.0.cmp(b.0)));
+        functions
+    }
+
+    // The functions as a table:
+    //
+    //     function                  calls   total ms
+    //     fib                       21891    151.207
+    pub fn report(&self) -> String {
+        let mut report = format!("{:<20} {:>10} {:>10}\n", "function", "calls", "total ms");
+        for (name, profile) in self.functions() {
+            report.push_str(&format!(
+                "{:<20} {:>10} {:>10.3}\n",
+                name,
+                profile.calls,
+                profile.total.as_secs_f64() * 1000.0
+            ));
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recursive_calls_are_timed_once() {
+        let mut profiler = Profiler::new();
+        profiler.enter("outer");
+        profiler.enter("fib");
+        profiler.enter("fib");
+        std::thread::sleep(Duration::from_millis(20));
+        profiler.exit("fib");
+        profiler.exit("fib");
+        profiler.exit("outer");
+
+        let functions: HashMap<&str, &FunctionProfile> = profiler.functions().into_iter().collect();
+        assert_eq!(functions["fib"].calls, 2);
+        assert!(functions["fib"].total >= Duration::from_millis(20));
+        // Counted once rather than once per level, so never more than its caller
+        assert!(functions["fib"].total <= functions["outer"].total);
+    }
+}
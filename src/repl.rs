@@ -0,0 +1,95 @@
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::rc::Rc;
+use tcc::token::Token;
+use tcc::{Interpreter, Lexer, Object, RuntimeError, load_program};
+
+const PROMPT: &str = ">> ";
+const CONTINUATION_PROMPT: &str = "... ";
+
+pub fn start(mut interpreter: Interpreter) {
+    let stdin = io::stdin();
+    let env = interpreter.global_environment();
+    let mut source = String::new();
+
+    loop {
+        let prompt = if source.is_empty() {
+            PROMPT
+        } else {
+            CONTINUATION_PROMPT
+        };
+        print!("{}", prompt);
+        io::stdout().flush().unwrap();
+
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) => {
+                // Ctrl-D
+                println!();
+                return;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("Error reading input: {}", e);
+                return;
+            }
+        }
+        source.push_str(&line);
+
+        // Keep reading continuation lines until every `{` and `(` is closed
+        if is_incomplete(&source) {
+            continue;
+        }
+
+        let (program, errors) = load_program(&source, None, Path::new(""));
+
+        if !errors.is_empty() {
+            println!("Parser errors:");
+            for err in &errors {
+                println!("\t{}", err);
+            }
+        } else {
+            match interpreter.eval_program(&program, Rc::clone(&env)) {
+                Ok(Object::Null) => {}
+                Ok(result) => println!("{}", result.inspect()),
+                Err(RuntimeError::Exit(code)) => {
+                    interpreter.remove_temp_files();
+                    std::process::exit(crate::exit_code(code))
+                }
+                Err(err) => println!("ERROR: {}", err),
+            }
+        }
+
+        source.clear();
+    }
+}
+
+fn is_incomplete(source: &str) -> bool {
+    let mut lexer = Lexer::new(source);
+    let mut depth = 0;
+
+    loop {
+        match lexer.next_token() {
+            Token::LParen | Token::LBrace | Token::LBracket => depth += 1,
+            Token::RParen | Token::RBrace | Token::RBracket => depth -= 1,
+            Token::Eof => break,
+            _ => {}
+        }
+    }
+
+    depth > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_incomplete() {
+        assert!(is_incomplete("int f() {"));
+        assert!(is_incomplete("printf(\"%d\",\n"));
+        assert!(is_incomplete("int a = [1,"));
+        assert!(!is_incomplete("int f() { return 1; }"));
+        assert!(!is_incomplete("puts(\"{\");"));
+    }
+}
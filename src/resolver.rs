@@ -0,0 +1,455 @@
+use crate::ast::{Expression, Program, Slot, Statement};
+use crate::token::Symbol;
+use std::rc::Rc;
+
+// Gives every local variable a slot ahead of time, so the interpreter finds it
+// by index instead of hashing its name in each scope out to its declaration.
+// Scopes mirror the environments the interpreter creates: one per call for the
+// parameters, one per block, one per for loop and one per switch case. Names
+// declared at the top level are globals and, like builtins, stay name-based.
+pub fn resolve(program: &mut Program) {
+    let mut resolver = Resolver { scopes: vec![] };
+    for stmt in &mut program.statements {
+        resolver.statement(stmt);
+    }
+}
+
+struct Resolver {
+    scopes: Vec<Vec<Symbol>>, // Each local scope's names by slot, innermost last
+}
+
+impl Resolver {
+    // Resolves `f` inside a new scope holding `names`
+    fn scoped(&mut self, names: Vec<Symbol>, f: impl FnOnce(&mut Self)) {
+        self.scopes.push(names);
+        f(self);
+        self.scopes.pop();
+    }
+
+    // The innermost scope declaring `name`, and its slot there
+    fn lookup(&self, name: &str) -> Option<Slot> {
+        self.scopes
+            .iter()
+            .rev()
+            .enumerate()
+            .find_map(|(depth, scope)| {
+                let index = scope.iter().rposition(|declared| &**declared == name)?;
+                Some(Slot { depth, index })
+            })
+    }
+
+    // The slot for a declaration of `name` in the current scope, or None at
+    // the top level
+    fn declared(&self, name: &str) -> Option<usize> {
+        self.lookup(name)
+            .filter(|slot| slot.depth == 0)
+            .map(|slot| slot.index)
+    }
+
+    fn block(&mut self, statements: &mut [Statement]) {
+        let mut names = vec![];
+        for stmt in statements.iter() {
+            declarations(stmt, &mut names);
+        }
+        self.scoped(names, |resolver| {
+            for stmt in statements {
+                resolver.statement(stmt);
+            }
+        });
+    }
+
+    fn statement(&mut self, stmt: &mut Statement) {
+        match stmt {
+            Statement::Let {
+                name, value, slot, ..
+            } => {
+                self.expression(value);
+                *slot = self.declared(name);
+            }
+            Statement::MultiLet {
+                declarators, slots, ..
+            } => {
+                for (_, value) in declarators.iter_mut() {
+                    self.expression(value);
+                }
+                *slots = declarators
+                    .iter()
+                    .map(|(name, _)| self.declared(name))
+                    .collect();
+            }
+            Statement::Return { value, .. } => self.expression(value),
+            Statement::Expression { expr, .. } => self.expression(expr),
+            Statement::Block(statements) => self.block(statements),
+            Statement::If {
+                condition,
+                consequence,
+                alternative,
+                ..
+            } => {
+                self.expression(condition);
+                self.statement(consequence);
+                if let Some(alternative) = alternative {
+                    self.statement(alternative);
+                }
+            }
+            Statement::While {
+                condition, body, ..
+            }
+            | Statement::DoWhile {
+                body, condition, ..
+            } => {
+                self.expression(condition);
+                self.statement(body);
+            }
+            Statement::Switch {
+                subject,
+                cases,
+                default,
+                ..
+            } => {
+                self.expression(subject);
+                for (label, body) in cases {
+                    self.expression(label);
+                    self.block(body);
+                }
+                if let Some(body) = default {
+                    self.block(body);
+                }
+            }
+            Statement::For {
+                init,
+                condition,
+                post,
+                body,
+                ..
+            } => {
+                let mut names = vec![];
+                if let Some(init) = init {
+                    declarations(init, &mut names);
+                }
+                declarations(body, &mut names);
+                self.scoped(names, |resolver| {
+                    if let Some(init) = init {
+                        resolver.statement(init);
+                    }
+                    if let Some(condition) = condition {
+                        resolver.expression(condition);
+                    }
+                    if let Some(post) = post {
+                        resolver.expression(post);
+                    }
+                    resolver.statement(body);
+                });
+            }
+            Statement::Function {
+                name,
+                params,
+                body,
+                slot,
+                ..
+            } => {
+                *slot = self.declared(name);
+                // Parameters take the first slots in order, so a repeated
+                // name finds the last of them, as the interpreter binds it
+                let params = params.iter().map(|(_, name)| name.clone()).collect();
+                // Resolving runs before any function value shares the body
+                self.scoped(params, |resolver| resolver.statement(Rc::make_mut(body)));
+            }
+            Statement::Prototype { .. } | Statement::Include { .. } => {}
+        }
+    }
+
+    fn expression(&mut self, expr: &mut Expression) {
+        match expr {
+            Expression::Identifier(name) => {
+                if let Some(slot) = self.lookup(name) {
+                    let name = name.clone();
+                    *expr = Expression::Local { name, slot };
+                }
+            }
+            Expression::Assign { name, value, slot } => {
+                self.expression(value);
+                *slot = self.lookup(name);
+            }
+            Expression::Update { name, slot, .. } => *slot = self.lookup(name),
+            Expression::Prefix { right, .. } => self.expression(right),
+            Expression::Infix { left, right, .. } => {
+                self.expression(left);
+                self.expression(right);
+            }
+            Expression::Call {
+                function,
+                arguments,
+            } => {
+                self.expression(function);
+                for arg in arguments {
+                    self.expression(arg);
+                }
+            }
+            Expression::Ternary {
+                condition,
+                then,
+                otherwise,
+            } => {
+                self.expression(condition);
+                self.expression(then);
+                self.expression(otherwise);
+            }
+            Expression::ArrayLiteral(elements) => {
+                for element in elements {
+                    self.expression(element);
+                }
+            }
+            Expression::Index { left, index } => {
+                self.expression(left);
+                self.expression(index);
+            }
+            Expression::IndexAssign {
+                left, index, value, ..
+            } => {
+                self.expression(left);
+                self.expression(index);
+                self.expression(value);
+            }
+            Expression::Local { .. }
+            | Expression::Integer(_)
+            | Expression::Float(_)
+            | Expression::String(_)
+            | Expression::Character(_)
+            | Expression::Boolean(_) => {}
+        }
+    }
+}
+
+// Adds the names `stmt` declares in the scope it runs in. Every declaration in
+// a scope gets a slot up front, so a name used before its declaration in the
+// same block resolves to that slot; while the slot is still empty, the
+// interpreter looks the name up further out, as it would without resolving.
+fn declarations(stmt: &Statement, names: &mut Vec<Symbol>) {
+    let mut add = |name: &Symbol| {
+        if !names.contains(name) {
+            names.push(name.clone());
+        }
+    };
+    match stmt {
+        Statement::Let { name, .. } | Statement::Function { name, .. } => add(name),
+        Statement::MultiLet { declarators, .. } => {
+            for (name, _) in declarators {
+                add(name);
+            }
+        }
+        // Blocks, the bodies of ifs and loops, which the parser wraps in a
+        // block when they declare anything, and switch cases have scopes of
+        // their own
+        Statement::Return { .. }
+        | Statement::Expression { .. }
+        | Statement::Block(_)
+        | Statement::If { .. }
+        | Statement::While { .. }
+        | Statement::DoWhile { .. }
+        | Statement::Switch { .. }
+        | Statement::For { .. }
+        | Statement::Prototype { .. }
+        | Statement::Include { .. } => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Interpreter;
+    use crate::parser::Parser;
+    use crate::token::Lexer;
+    use crate::token::Token;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn parse(input: &str) -> Program {
+        let mut parser = Parser::new(Lexer::new(input));
+        let program = parser.parse_program();
+        assert!(
+            parser.errors.is_empty(),
+            "parser errors: {:?}",
+            parser.errors
+        );
+        program
+    }
+
+    // What `program` prints
+    fn run(program: &Program) -> String {
+        let buffer = Rc::new(RefCell::new(Vec::new()));
+        let mut interpreter = Interpreter::with_output(buffer.clone());
+        let env = interpreter.global_environment();
+        interpreter.eval_program(program, env).unwrap();
+        String::from_utf8(buffer.borrow().clone()).unwrap()
+    }
+
+    // What `input` prints once resolved, checked against running it unresolved
+    fn run_resolved(input: &str) -> String {
+        let mut program = parse(input);
+        let unresolved = run(&program);
+        resolve(&mut program);
+        let resolved = run(&program);
+        assert_eq!(resolved, unresolved, "{}", input);
+        resolved
+    }
+
+    fn local(name: &str, depth: usize, index: usize) -> Expression {
+        Expression::Local {
+            name: name.into(),
+            slot: Slot { depth, index },
+        }
+    }
+
+    #[test]
+    fn test_locals_get_slots() {
+        let mut program = parse("int g = 1; int f(int a, int b) { int c = a; return b + c + g; }");
+        resolve(&mut program);
+
+        // Globals stay name-based
+        assert!(matches!(
+            &program.statements[0],
+            Statement::Let { slot: None, .. }
+        ));
+        let Statement::Function { body, slot, .. } = &program.statements[1] else {
+            panic!("not a function: {:?}", program.statements[1]);
+        };
+        assert_eq!(*slot, None);
+        let Statement::Block(body) = &**body else {
+            panic!("not a block: {:?}", body);
+        };
+        // The body's block is one scope in from the parameters
+        assert!(matches!(
+            &body[0],
+            Statement::Let { slot: Some(0), value, .. } if *value == local("a", 1, 0)
+        ));
+        let Statement::Return { value, .. } = &body[1] else {
+            panic!("not a return: {:?}", body[1]);
+        };
+        assert_eq!(
+            value.clone(),
+            Expression::Infix {
+                left: Box::new(Expression::Infix {
+                    left: Box::new(local("b", 1, 1)),
+                    operator: Token::Plus,
+                    right: Box::new(local("c", 0, 0)),
+                }),
+                operator: Token::Plus,
+                right: Box::new(Expression::Identifier("g".into())),
+            }
+        );
+    }
+
+    #[test]
+    fn test_shadowing() {
+        let input = "int x = 1;
+            int f(int x) {
+                puts(x);
+                {
+                    puts(x);
+                    int x = 3;
+                    x += 10;
+                    puts(x);
+                }
+                for (int x = 20; x < 22; x++) puts(x);
+                x--;
+                return x;
+            }
+            puts(f(2));
+            puts(x);";
+        assert_eq!(run_resolved(input), "2\n2\n13\n20\n21\n1\n1\n");
+
+        // Until its declaration runs, a name still means the outer variable
+        let input = "int f() {
+                int x = 1;
+                {
+                    int y = x;
+                    int x = x + 1;
+                    printf(\"%d %d\\n\", y, x);
+                }
+                return x;
+            }
+            puts(f());";
+        assert_eq!(run_resolved(input), "1 2\n1\n");
+
+        // The last of repeated parameters wins
+        assert_eq!(
+            run_resolved("int f(int a, int a) { return a; } puts(f(1, 2));"),
+            "2\n"
+        );
+    }
+
+    #[test]
+    fn test_closures_reach_outer_frames() {
+        let input = "int make_counter(int start) {
+                int count = start;
+                int next() {
+                    count++;
+                    return count;
+                }
+                return next;
+            }
+            int a = make_counter(10);
+            int b = make_counter(0);
+            a(); a();
+            printf(\"%d %d\\n\", a(), b());";
+        assert_eq!(run_resolved(input), "13 1\n");
+
+        // A nested function sees locals declared after it, once they exist
+        let input = "int outer() {
+                int show() { return later * 2; }
+                int later = 21;
+                return show();
+            }
+            puts(outer());";
+        assert_eq!(run_resolved(input), "42\n");
+
+        // Recursion through a local function finds it in the enclosing scope
+        let input = "int outer(int n) {
+                int fact(int n) { return n < 2 ? 1 : n * fact(n - 1); }
+                return fact(n);
+            }
+            puts(outer(5));";
+        assert_eq!(run_resolved(input), "120\n");
+    }
+
+    #[test]
+    fn test_switch_cases_and_unbraced_bodies() {
+        let input = "int f(int n) {
+                switch (n) {
+                    case 1:
+                        int x = 10;
+                        return x;
+                    default:
+                        int x = 20;
+                        if (n > 5) x = 30;
+                        return x;
+                }
+            }
+            int g(int n) {
+                int y = 1;
+                if (n) int y = 5; else int y = 6;
+                return y;
+            }
+            printf(\"%d %d %d %d\\n\", f(1), f(2), f(9), g(0));";
+        assert_eq!(run_resolved(input), "10 20 30 1\n");
+    }
+
+    #[test]
+    fn test_undefined_names_are_still_errors() {
+        let mut program = parse("int f() { { int x = 1; } return x; } f();");
+        resolve(&mut program);
+        let mut interpreter = Interpreter::new();
+        let env = interpreter.global_environment();
+        assert_eq!(
+            interpreter
+                .eval_program(&program, env)
+                .map_err(|e| e.to_string()),
+            Err("identifier not found: x".to_string())
+        );
+        // Resolving twice changes nothing
+        let resolved = program.clone();
+        resolve(&mut program);
+        assert_eq!(program, resolved);
+    }
+}
@@ -1,10 +1,113 @@
-use crate::env::{Environment, FileHandle, Object};
-use std::cell::RefCell;
-use std::fs::File;
-use std::io::{Read, Seek, SeekFrom, Write};
+use crate::env::{Environment, FileHandle, Object, Stream};
+use std::cell::{Cell, RefCell, RefMut};
+use std::fs::OpenOptions;
+use std::io::{BufRead, Read, Seek, SeekFrom, Write};
+use std::ops::RangeInclusive;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-fn format_output(args: Vec<Object>) -> Result<String, String> {
+// A parsed `%[flags][width][.precision]conversion` directive
+struct FormatSpec {
+    left_align: bool,
+    zero_pad: bool,
+    width: usize,
+    precision: Option<usize>,
+    conversion: char,
+}
+
+impl FormatSpec {
+    fn pad(&self, body: String, numeric: bool) -> String {
+        let len = body.chars().count();
+        if len >= self.width {
+            return body;
+        }
+        let fill = self.width - len;
+        if self.left_align {
+            format!("{}{}", body, " ".repeat(fill))
+        } else if self.zero_pad && numeric {
+            // Zeros go between the sign and the digits
+            match body.strip_prefix('-') {
+                Some(digits) => format!("-{}{}", "0".repeat(fill), digits),
+                None => format!("{}{}", "0".repeat(fill), body),
+            }
+        } else {
+            format!("{}{}", " ".repeat(fill), body)
+        }
+    }
+}
+
+fn format_value(spec: &FormatSpec, arg: &Object, position: usize) -> Result<String, String> {
+    let integer = |arg: &Object| match arg {
+        Object::Integer(i) => Ok(*i),
+        Object::Boolean(b) => Ok(*b as i64),
+        other => Err(format!(
+            "%{} at position {} expects an integer, got {}",
+            spec.conversion,
+            position,
+            other.inspect()
+        )),
+    };
+
+    match spec.conversion {
+        // A precision limits how many characters of the string are printed
+        's' => {
+            let s = arg.inspect();
+            let s = match spec.precision {
+                Some(max) => s.chars().take(max).collect(),
+                None => s,
+            };
+            Ok(spec.pad(s, false))
+        }
+        'f' => {
+            let value = match arg {
+                Object::Float(n) => *n,
+                Object::Integer(n) => *n as f64,
+                other => {
+                    return Err(format!(
+                        "%f at position {} expects a number, got {}",
+                        position,
+                        other.inspect()
+                    ));
+                }
+            };
+            let precision = spec.precision.unwrap_or(6);
+            Ok(spec.pad(format!("{:.*}", precision, value), true))
+        }
+        'd' | 'i' => Ok(spec.pad(integer(arg)?.to_string(), true)),
+        'x' => Ok(spec.pad(format!("{:x}", integer(arg)?), true)),
+        'X' => Ok(spec.pad(format!("{:X}", integer(arg)?), true)),
+        'o' => Ok(spec.pad(format!("{:o}", integer(arg)?), true)),
+        'c' => {
+            let c = match arg {
+                Object::String(s) => s.chars().next().map(String::from).unwrap_or_default(),
+                Object::Integer(i) => match u32::try_from(*i).ok().and_then(char::from_u32) {
+                    Some(c) => c.to_string(),
+                    None => {
+                        return Err(format!(
+                            "%c at position {} got invalid character code {}",
+                            position, i
+                        ));
+                    }
+                },
+                other => {
+                    return Err(format!(
+                        "%c at position {} expects a string or integer, got {}",
+                        position,
+                        other.inspect()
+                    ));
+                }
+            };
+            Ok(spec.pad(c, false))
+        }
+        other => Err(format!(
+            "unknown conversion '%{}' at position {}",
+            other, position
+        )),
+    }
+}
+
+fn format_output(args: &[Object]) -> Result<String, String> {
     if args.is_empty() {
         return Ok(String::new());
     }
@@ -14,529 +117,2759 @@ fn format_output(args: Vec<Object>) -> Result<String, String> {
         _ => return Ok(args.iter().map(|a| a.inspect()).collect::<String>()), // Fallback to join if not string fmt
     };
 
-    let fmt_args = &args[1..];
+    let mut fmt_args = args[1..].iter();
     let mut out = String::new();
-    let mut arg_idx = 0;
-    let mut chars = fmt_str.chars().peekable();
+    let mut chars = fmt_str.chars().enumerate().peekable();
 
-    while let Some(c) = chars.next() {
-        if c == '%' {
-            if let Some(&next_c) = chars.peek() {
-                match next_c {
-                    's' | 'd' => {
-                        chars.next(); // consume specifier
-                        if arg_idx < fmt_args.len() {
-                            out.push_str(&fmt_args[arg_idx].inspect());
-                            arg_idx += 1;
-                        } else {
-                            out.push('%');
-                            out.push(next_c);
-                        }
-                    }
-                    '%' => {
-                        chars.next();
-                        out.push('%');
-                    }
-                    _ => {
-                        out.push('%');
-                    }
-                }
-            } else {
-                out.push('%');
-            }
-        } else {
+    while let Some((position, c)) = chars.next() {
+        if c != '%' {
             out.push(c);
+            continue;
         }
-    }
-    Ok(out)
-}
 
-pub fn register_stdlib(env: Rc<RefCell<Environment>>) {
-    let mut env_mut = env.borrow_mut();
+        let mut spec = FormatSpec {
+            left_align: false,
+            zero_pad: false,
+            width: 0,
+            precision: None,
+            conversion: '%',
+        };
 
-    // puts(str)
-    env_mut.set(
-        "puts".to_string(),
-        Object::Builtin(|args| {
-            if args.len() != 1 {
-                return Object::Error(format!("puts expected 1 argument, got {}", args.len()));
+        while let Some(&(_, flag)) = chars.peek() {
+            match flag {
+                '-' => spec.left_align = true,
+                '0' => spec.zero_pad = true,
+                _ => break,
             }
-            println!("{}", args[0].inspect());
-            Object::Null
-        }),
-    );
+            chars.next();
+        }
 
-    // putchar(char)
-    env_mut.set(
-        "putchar".to_string(),
-        Object::Builtin(|args| {
-            if args.len() != 1 {
-                return Object::Error("putchar expected 1 argument".to_string());
+        while let Some(&(_, digit)) = chars.peek() {
+            match digit.to_digit(10) {
+                Some(d) => spec.width = spec.width * 10 + d as usize,
+                None => break,
             }
-            let s = args[0].inspect();
-            if let Some(c) = s.chars().next() {
-                print!("{}", c);
+            chars.next();
+        }
+
+        if let Some(&(_, '.')) = chars.peek() {
+            chars.next();
+            let mut precision = 0;
+            while let Some(&(_, digit)) = chars.peek() {
+                match digit.to_digit(10) {
+                    Some(d) => precision = precision * 10 + d as usize,
+                    None => break,
+                }
+                chars.next();
             }
-            Object::Null
-        }),
-    );
+            spec.precision = Some(precision);
+        }
 
-    // printf(fmt, ...)
-    env_mut.set(
-        "printf".to_string(),
-        Object::Builtin(|args| match format_output(args) {
-            Ok(s) => {
-                print!("{}", s);
-                Object::Null
+        spec.conversion = match chars.next() {
+            Some((_, conversion)) => conversion,
+            None => {
+                return Err(format!(
+                    "incomplete format specifier at position {}",
+                    position
+                ));
             }
-            Err(e) => Object::Error(e),
-        }),
-    );
+        };
 
-    // sprintf(fmt, ...) -> String
-    env_mut.set(
-        "sprintf".to_string(),
-        Object::Builtin(|args| match format_output(args) {
-            Ok(s) => Object::String(s),
-            Err(e) => Object::Error(e),
-        }),
-    );
+        if spec.conversion == '%' {
+            out.push('%');
+            continue;
+        }
 
-    // fopen(path, mode)
-    env_mut.set(
-        "fopen".to_string(),
-        Object::Builtin(|args| {
-            if args.len() != 2 {
-                return Object::Error(format!("fopen expected 2 arguments, got {}", args.len()));
+        match fmt_args.next() {
+            Some(arg) => out.push_str(&format_value(&spec, arg, position)?),
+            None => {
+                return Err(format!(
+                    "missing argument for %{} at position {}",
+                    spec.conversion, position
+                ));
             }
-            let path = match &args[0] {
-                Object::String(s) => s,
-                _ => {
-                    return Object::Error("fopen first argument must be a string path".to_string());
-                }
-            };
-            let mode = match &args[1] {
-                Object::String(s) => s,
-                _ => {
-                    return Object::Error(
-                        "fopen second argument must be a string mode".to_string(),
-                    );
-                }
-            };
+        }
+    }
+    Ok(out)
+}
 
-            let file = if mode == "r" {
-                File::open(path)
-            } else if mode == "w" {
-                File::create(path)
-            } else {
-                File::open(path) // Default read
-            };
+// Maps a C fopen mode string to OpenOptions; a single "b" flag, as in "rb",
+// "r+b" or "rb+", is accepted and ignored
+fn open_options(mode: &str) -> Option<OpenOptions> {
+    let mode = match mode.strip_suffix("b+") {
+        Some(kind) => format!("{}+", kind),
+        None => mode.strip_suffix('b').unwrap_or(mode).to_string(),
+    };
+    let mut options = OpenOptions::new();
+    match mode.as_str() {
+        "r" => options.read(true),
+        "w" => options.write(true).create(true).truncate(true),
+        "a" => options.append(true).create(true),
+        "r+" => options.read(true).write(true),
+        "w+" => options.read(true).write(true).create(true).truncate(true),
+        "a+" => options.read(true).append(true).create(true),
+        _ => return None,
+    };
+    Some(options)
+}
 
-            match file {
-                Ok(f) => Object::File(Rc::new(RefCell::new(FileHandle {
-                    file: f,
-                    eof: false,
-                    error: false,
-                }))),
-                Err(e) => Object::Error(format!("fopen failed: {}", e)),
-            }
-        }),
-    );
+const CLOSED_FILE: &str = "operation on closed file";
 
-    // fclose(file)
-    env_mut.set("fclose".to_string(), Object::Builtin(|_args| Object::Null));
+// The errno codes file builtins record when they fail without stopping the
+// program, with Linux's numbers and strerror messages so ported C code reads
+// the same. Each is also a global constant, so scripts can test errno() == ENOENT.
+const ERRNOS: &[(&str, i64, &str)] = &[
+    ("ENOENT", 2, "No such file or directory"),
+    ("EIO", 5, "Input/output error"),
+    ("EACCES", 13, "Permission denied"),
+    ("EEXIST", 17, "File exists"),
+    ("ENOTDIR", 20, "Not a directory"),
+    ("EISDIR", 21, "Is a directory"),
+    ("EINVAL", 22, "Invalid argument"),
+    ("ENOTEMPTY", 39, "Directory not empty"),
+];
 
-    // fputs(str, file)
-    env_mut.set(
-        "fputs".to_string(),
-        Object::Builtin(|args| {
-            if args.len() != 2 {
-                return Object::Error("fputs expected 2 arguments".to_string());
-            }
-            let content = match &args[0] {
-                Object::String(s) => s,
-                _ => return Object::Error("fputs first arg must be string".to_string()),
-            };
+// The errno for an I/O error; kinds without a closer match are EIO
+fn errno_code(error: &std::io::Error) -> i64 {
+    use std::io::ErrorKind;
+    let name = match error.kind() {
+        ErrorKind::NotFound => "ENOENT",
+        ErrorKind::PermissionDenied => "EACCES",
+        ErrorKind::AlreadyExists => "EEXIST",
+        ErrorKind::NotADirectory => "ENOTDIR",
+        ErrorKind::IsADirectory => "EISDIR",
+        ErrorKind::InvalidInput | ErrorKind::InvalidFilename => "EINVAL",
+        ErrorKind::DirectoryNotEmpty => "ENOTEMPTY",
+        _ => "EIO",
+    };
+    ERRNOS.iter().find(|(n, _, _)| *n == name).unwrap().1
+}
 
-            match &args[1] {
-                Object::File(handle) => {
-                    let mut fh = handle.borrow_mut();
-                    if let Err(e) = write!(fh.file, "{}", content) {
-                        fh.error = true;
-                        Object::Error(format!("fputs failed: {}", e))
-                    } else {
-                        Object::Null
-                    }
-                }
-                _ => Object::Error("fputs second arg must be file".to_string()),
-            }
-        }),
-    );
+// Creates a file no other process or tmpfile() call has, in the OS temp directory
+fn create_temp_file() -> std::io::Result<(std::fs::File, std::path::PathBuf)> {
+    static CREATED: AtomicU64 = AtomicU64::new(0);
+    loop {
+        let name = format!(
+            "tinyc-tmp-{}-{}",
+            std::process::id(),
+            CREATED.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = std::env::temp_dir().join(name);
+        // A file left by an earlier process with the same id is skipped
+        match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(file) => return Ok((file, path)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
 
-    // fputc(char, file)
-    env_mut.set(
-        "fputc".to_string(),
-        Object::Builtin(|args| {
-            if args.len() != 2 {
-                return Object::Error("fputc expected 2 arguments".to_string());
-            }
-            let c_str = args[0].inspect();
-            let c = if let Some(ch) = c_str.chars().next() {
-                ch
-            } else {
-                return Object::Null;
-            };
+// A C-style status for `result`: 0 on success, or -1 with `errno` set
+fn io_status(result: std::io::Result<()>, errno: &Cell<i64>) -> Object {
+    match result {
+        Ok(()) => Object::Integer(0),
+        Err(e) => {
+            errno.set(errno_code(&e));
+            Object::Integer(-1)
+        }
+    }
+}
 
-            match &args[1] {
-                Object::File(handle) => {
-                    let mut fh = handle.borrow_mut();
-                    if let Err(e) = write!(fh.file, "{}", c) {
-                        fh.error = true;
-                        Object::Error(format!("fputc failed: {}", e))
-                    } else {
-                        Object::Null
-                    }
-                }
-                _ => Object::Error("fputc arg must be file".to_string()),
-            }
-        }),
-    );
+// Like C's strerror, with glibc's wording for 0 and unknown codes
+fn strerror(code: i64) -> String {
+    match ERRNOS.iter().find(|(_, c, _)| *c == code) {
+        Some((_, _, message)) => message.to_string(),
+        None if code == 0 => "Success".to_string(),
+        None => format!("Unknown error {}", code),
+    }
+}
 
-    // fprintf(file, fmt, ...)
-    env_mut.set(
-        "fprintf".to_string(),
-        Object::Builtin(|args| {
-            if args.len() < 2 {
-                return Object::Error("fprintf expected at least file and fmt".to_string());
-            }
+// Parses an optionally signed decimal prefix after leading whitespace, like C's atoi.
+// Returns None when there are no digits, plus whether the whole input was consumed.
+fn parse_leading_int(s: &str) -> Option<(i64, bool)> {
+    let trimmed = s.trim_start();
+    let (negative, rest) = match trimmed.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+    };
 
-            let file_obj = &args[0];
-            // Need to extract other args for formatting
-            // args[1] is fmt.
-
-            if let Object::File(handle) = file_obj {
-                let fmt_args = args[1..].to_vec(); // clone args
-                match format_output(fmt_args) {
-                    Ok(s) => {
-                        let mut fh = handle.borrow_mut();
-                        if let Err(_) = write!(fh.file, "{}", s) {
-                            fh.error = true;
-                            Object::Error("write error".to_string())
-                        } else {
-                            Object::Null
-                        }
-                    }
-                    Err(e) => Object::Error(e),
-                }
-            } else {
-                Object::Error("fprintf first arg must be file".to_string())
-            }
-        }),
-    );
+    let digits: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+    if digits.is_empty() {
+        return None;
+    }
 
-    // fgets(file)
-    env_mut.set(
-        "fgets".to_string(),
-        Object::Builtin(|args| {
-            if args.len() != 1 {
-                return Object::Error("fgets expected 1 argument".to_string());
-            }
-            match &args[0] {
-                Object::File(handle) => {
-                    let mut fh = handle.borrow_mut();
-                    let mut line = String::new();
-                    let mut buf = [0; 1];
-                    loop {
-                        match fh.file.read(&mut buf) {
-                            Ok(0) => {
-                                fh.eof = true;
-                                break;
-                            }
-                            Ok(_) => {
-                                let c = buf[0] as char;
-                                line.push(c);
-                                if c == '\n' {
-                                    break;
-                                }
-                            }
-                            Err(e) => {
-                                fh.error = true;
-                                return Object::Error(format!("fgets error: {}", e));
-                            }
-                        }
-                    }
-                    if line.is_empty() && fh.eof {
-                        Object::Null
-                    } else {
-                        Object::String(line)
+    // Out-of-range values saturate like strtol
+    let value = match digits.parse::<i64>() {
+        Ok(v) if negative => -v,
+        Ok(v) => v,
+        Err(_) if negative => i64::MIN,
+        Err(_) => i64::MAX,
+    };
+    let consumed_all = rest[digits.len()..].trim().is_empty();
+    Some((value, consumed_all))
+}
+
+// Reads one line without its trailing "\n" or "\r\n", or None at EOF. Reading stops
+// at the newline, so characters after it stay available to getchar.
+fn read_line(reader: &mut dyn BufRead) -> std::io::Result<Option<String>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+    Ok(Some(line))
+}
+
+fn peek_byte(reader: &mut dyn BufRead) -> std::io::Result<Option<u8>> {
+    Ok(reader.fill_buf()?.first().copied())
+}
+
+fn skip_whitespace(reader: &mut dyn BufRead) -> std::io::Result<()> {
+    while let Some(b) = peek_byte(reader)? {
+        if !b.is_ascii_whitespace() {
+            break;
+        }
+        reader.consume(1);
+    }
+    Ok(())
+}
+
+// Reads an optionally signed decimal integer after leading whitespace, consuming
+// nothing past its last digit. None at EOF or when no digits follow; a sign
+// with no digit after it is left unread, unless it ends the buffered input.
+fn read_int(reader: &mut dyn BufRead) -> std::io::Result<Option<i64>> {
+    skip_whitespace(reader)?;
+    let mut text = String::new();
+    if let [sign @ (b'-' | b'+'), rest @ ..] = reader.fill_buf()? {
+        if rest.first().is_some_and(|b| !b.is_ascii_digit()) {
+            return Ok(None);
+        }
+        text.push(*sign as char);
+        reader.consume(1);
+    }
+    while let Some(b) = peek_byte(reader)? {
+        if !b.is_ascii_digit() {
+            break;
+        }
+        text.push(b as char);
+        reader.consume(1);
+    }
+    Ok(parse_leading_int(&text).map(|(n, _)| n))
+}
+
+// Reads a whitespace-delimited word after leading whitespace, or None at EOF
+fn read_word(reader: &mut dyn BufRead) -> std::io::Result<Option<String>> {
+    skip_whitespace(reader)?;
+    let mut bytes = vec![];
+    while let Some(b) = peek_byte(reader)? {
+        if b.is_ascii_whitespace() {
+            break;
+        }
+        bytes.push(b);
+        reader.consume(1);
+    }
+    if bytes.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(String::from_utf8_lossy(&bytes).into_owned()))
+}
+
+// A limited scanf: whitespace in `fmt` skips any amount of input whitespace,
+// other characters must match exactly, and the single %d or %s conversion is
+// returned. Null when the input runs out or doesn't match.
+fn scan(reader: &mut dyn BufRead, fmt: &str) -> Result<Object, String> {
+    let conversions = fmt.matches('%').count() - 2 * fmt.matches("%%").count();
+    if conversions != 1 {
+        return Err(format!(
+            "scanf supports exactly one %d or %s conversion, got {}",
+            conversions
+        ));
+    }
+
+    let io_error = |e: std::io::Error| format!("scanf failed: {}", e);
+    let mut result = Object::Null;
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        let expected = match c {
+            '%' => match chars.next() {
+                Some('d') => {
+                    match read_int(reader).map_err(io_error)? {
+                        Some(n) => result = Object::Integer(n),
+                        None => return Ok(Object::Null),
                     }
+                    continue;
                 }
-                _ => Object::Error("fgets arg must be file".to_string()),
-            }
-        }),
-    );
-
-    // fgetc(file)
-    env_mut.set(
-        "fgetc".to_string(),
-        Object::Builtin(|args| {
-            if args.len() != 1 {
-                return Object::Error("fgetc expected 1 arg".to_string());
-            }
-            match &args[0] {
-                Object::File(handle) => {
-                    let mut fh = handle.borrow_mut();
-                    let mut buf = [0; 1];
-                    match fh.file.read(&mut buf) {
-                        Ok(0) => {
-                            fh.eof = true;
-                            Object::Null
-                        }
-                        Ok(_) => Object::String((buf[0] as char).to_string()),
-                        Err(_) => {
-                            fh.error = true;
-                            Object::Null
-                        }
+                Some('s') => {
+                    match read_word(reader).map_err(io_error)? {
+                        Some(word) => result = Object::String(word),
+                        None => return Ok(Object::Null),
                     }
+                    continue;
                 }
-                _ => Object::Error("fgetc arg must be file".to_string()),
+                Some('%') => '%',
+                Some(other) => return Err(format!("scanf: unsupported conversion '%{}'", other)),
+                None => return Err("scanf: incomplete format specifier".to_string()),
+            },
+            c if c.is_whitespace() => {
+                skip_whitespace(reader).map_err(io_error)?;
+                continue;
             }
-        }),
-    );
+            c => c,
+        };
 
-    // feof(file)
-    env_mut.set(
-        "feof".to_string(),
-        Object::Builtin(|args| {
-            if args.len() != 1 {
-                return Object::Error("feof expected 1 arg".to_string());
+        // Matched byte by byte, so a character outside ASCII needs all of its
+        // UTF-8 encoding to be there
+        for &byte in expected.encode_utf8(&mut [0; 4]).as_bytes() {
+            if peek_byte(reader).map_err(io_error)? != Some(byte) {
+                return Ok(Object::Null);
             }
-            match &args[0] {
-                Object::File(handle) => Object::Boolean(handle.borrow().eof),
-                _ => Object::Error("feof arg must be file".to_string()),
-            }
-        }),
-    );
+            reader.consume(1);
+        }
+    }
+    Ok(result)
+}
 
-    // ferror(file)
-    env_mut.set(
-        "ferror".to_string(),
-        Object::Builtin(|args| {
-            if args.len() != 1 {
-                return Object::Error("ferror expected 1 arg".to_string());
-            }
-            match &args[0] {
-                Object::File(handle) => Object::Boolean(handle.borrow().error),
-                _ => Object::Error("ferror arg must be file".to_string()),
-            }
-        }),
-    );
+// A small deterministic PRNG (xorshift64*) shared by rand, srand and rand_range
+struct Rng(Cell<u64>);
 
-    // ftell(file)
-    env_mut.set(
-        "ftell".to_string(),
-        Object::Builtin(|args| {
-            if args.len() != 1 {
-                return Object::Error("ftell expected 1 arg".to_string());
-            }
-            match &args[0] {
-                Object::File(handle) => match handle.borrow_mut().file.stream_position() {
-                    Ok(pos) => Object::Integer(pos as i64),
-                    Err(_) => Object::Integer(-1),
-                },
-                _ => Object::Error("ftell arg must be file".to_string()),
-            }
-        }),
-    );
+impl Rng {
+    // Like C, a program that never calls srand behaves as if it called srand(1)
+    fn new() -> Self {
+        let rng = Rng(Cell::new(0));
+        rng.seed(1);
+        rng
+    }
 
-    // fseek(file, offset, whence) (whence: 0=Start, 1=Current, 2=End)
-    env_mut.set(
-        "fseek".to_string(),
-        Object::Builtin(|args| {
-            if args.len() != 3 {
-                return Object::Error("fseek expected 3 args".to_string());
-            }
-            match &args[0] {
-                Object::File(handle) => {
-                    let offset = match args[1] {
-                        Object::Integer(i) => i,
-                        _ => return Object::Error("fseek offset must be int".to_string()),
-                    };
-                    let whence = match args[2] {
-                        Object::Integer(i) => i,
-                        _ => return Object::Error("fseek whence must be int".to_string()),
-                    };
-
-                    let pos = match whence {
-                        0 => SeekFrom::Start(offset as u64),
-                        1 => SeekFrom::Current(offset),
-                        2 => SeekFrom::End(offset),
-                        _ => return Object::Error("invalid whence".to_string()),
-                    };
-
-                    let mut fh = handle.borrow_mut();
-                    match fh.file.seek(pos) {
-                        Ok(_) => {
-                            fh.eof = false;
-                            Object::Integer(0)
-                        }
-                        Err(_) => {
-                            fh.error = true;
-                            Object::Integer(-1)
-                        }
-                    }
-                }
-                _ => Object::Error("fseek arg must be file".to_string()),
-            }
-        }),
-    );
+    fn seed(&self, seed: i64) {
+        // Scramble with splitmix64 so nearby seeds give unrelated sequences and
+        // the state is never the zero that xorshift can't leave
+        let mut z = (seed as u64).wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        self.0.set(if z == 0 { 1 } else { z });
+    }
 
-    // rewind(file)
-    env_mut.set(
-        "rewind".to_string(),
-        Object::Builtin(|args| {
-            if args.len() != 1 {
-                return Object::Error("rewind expected 1 arg".to_string());
-            }
-            match &args[0] {
-                Object::File(handle) => {
-                    let mut fh = handle.borrow_mut();
-                    let _ = fh.file.seek(SeekFrom::Start(0));
-                    fh.eof = false;
-                    fh.error = false;
-                    Object::Null
-                }
-                _ => Object::Error("rewind arg must be file".to_string()),
-            }
-        }),
-    );
+    fn next_u64(&self) -> u64 {
+        let mut x = self.0.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0.set(x);
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
 
-    // remove(path)
-    env_mut.set(
-        "remove".to_string(),
-        Object::Builtin(|args| {
-            if args.len() != 1 {
-                return Object::Error("remove expected 1 arg".to_string());
-            }
-            let path = match &args[0] {
-                Object::String(s) => s,
-                _ => return Object::Error("remove arg must be string".to_string()),
-            };
-            if let Err(e) = std::fs::remove_file(path) {
-                Object::Error(format!("remove failed: {}", e))
-            } else {
-                Object::Null
+    // Uniform in lo..=hi, rejecting the biased tail instead of using a plain modulo
+    fn range(&self, lo: i64, hi: i64) -> i64 {
+        let span = (hi as i128 - lo as i128 + 1) as u128;
+        let limit = (1u128 << 64) - (1u128 << 64) % span;
+        loop {
+            let x = self.next_u64() as u128;
+            if x < limit {
+                return (lo as i128 + (x % span) as i128) as i64;
             }
-        }),
-    );
+        }
+    }
+}
 
-    // rename(old, new)
-    env_mut.set(
-        "rename".to_string(),
-        Object::Builtin(|args| {
-            if args.len() != 2 {
-                return Object::Error("rename expected 2 args".to_string());
-            }
-            let old = match &args[0] {
-                Object::String(s) => s,
-                _ => return Object::Error("rename old must be string".to_string()),
-            };
-            let new = match &args[1] {
-                Object::String(s) => s,
-                _ => return Object::Error("rename new must be string".to_string()),
-            };
+// The upper bound of the arity of a builtin taking any number of arguments
+const VARIADIC: usize = usize::MAX;
 
-            if let Err(e) = std::fs::rename(old, new) {
-                Object::Error(format!("rename failed: {}", e))
-            } else {
-                Object::Null
-            }
-        }),
-    );
+// What a builtin's body returns. An Err message reaches the program as an
+// error prefixed with the builtin's name.
+type BuiltinResult = Result<Object, String>;
 
-    // getchar()
-    env_mut.set(
-        "getchar".to_string(),
-        Object::Builtin(|args| {
-            if !args.is_empty() {
-                return Object::Error("getchar expected 0 args".to_string());
-            }
-            let mut buf = [0; 1];
-            let mut handle = std::io::stdin();
-            match handle.read(&mut buf) {
-                Ok(0) => Object::Null, // EOF
-                Ok(_) => Object::String((buf[0] as char).to_string()),
-                Err(_) => Object::Error("getchar read error".to_string()),
-            }
-        }),
-    );
+// Registers `f` as the builtin `name`. Calls with an argument count outside
+// `arity` fail before `f` runs, and every error reads "<name>: <message>".
+fn builtin(
+    env: &mut Environment,
+    name: &'static str,
+    arity: RangeInclusive<usize>,
+    f: impl Fn(&[Object]) -> BuiltinResult + 'static,
+) {
+    env.register_builtin(name, move |args| {
+        match check_arity(&arity, args.len()).and_then(|()| f(&args)) {
+            Ok(result) => result,
+            Err(message) => Object::Error(format!("{}: {}", name, message)),
+        }
+    });
+}
 
-    // Aliases
+fn check_arity(arity: &RangeInclusive<usize>, got: usize) -> Result<(), String> {
+    if arity.contains(&got) {
+        return Ok(());
+    }
+    let (min, max) = (*arity.start(), *arity.end());
+    let expected = if min == max {
+        arguments(min)
+    } else if max == VARIADIC {
+        format!("at least {}", arguments(min))
+    } else if min == 0 {
+        format!("at most {}", arguments(max))
+    } else {
+        format!("{} to {} arguments", min, max)
+    };
+    Err(format!("expected {}, got {}", expected, got))
+}
 
-    // getc = fgetc (technically getc(stream), getchar() is stdin)
-    // For now, I'll copy the logic of fgetc for getc.
-    env_mut.set(
-        "getc".to_string(),
-        Object::Builtin(|args| {
-            // Same logic as fgetc
-            if args.len() != 1 {
-                return Object::Error("getc expected 1 arg".to_string());
-            }
-            match &args[0] {
-                Object::File(handle) => {
-                    let mut fh = handle.borrow_mut();
-                    let mut buf = [0; 1];
-                    match fh.file.read(&mut buf) {
-                        Ok(0) => {
-                            fh.eof = true;
-                            Object::Null
-                        }
-                        Ok(_) => Object::String((buf[0] as char).to_string()),
-                        Err(_) => {
-                            fh.error = true;
-                            Object::Null
-                        }
-                    }
-                }
-                _ => Object::Error("getc arg must be file".to_string()),
-            }
-        }),
-    );
+fn arguments(count: usize) -> String {
+    match count {
+        1 => "1 argument".to_string(),
+        _ => format!("{} arguments", count),
+    }
+}
 
-    // putc = fputc
-    env_mut.set(
-        "putc".to_string(),
-        Object::Builtin(|args| {
-            if args.len() != 2 {
-                return Object::Error("putc expected 2 arguments".to_string());
-            }
-            let c_str = args[0].inspect();
-            let c = if let Some(ch) = c_str.chars().next() {
-                ch
-            } else {
-                return Object::Null;
-            };
+// The expect_* helpers take argument `i` of a call whose arity was already
+// checked; their errors count arguments from 1 like the program does
+fn expect_string(args: &[Object], i: usize) -> Result<&str, String> {
+    match &args[i] {
+        Object::String(s) => Ok(s),
+        other => Err(wrong_type(i, "a string", other)),
+    }
+}
 
-            match &args[1] {
-                Object::File(handle) => {
-                    let mut fh = handle.borrow_mut();
-                    if let Err(e) = write!(fh.file, "{}", c) {
-                        fh.error = true;
-                        Object::Error(format!("putc failed: {}", e))
-                    } else {
-                        Object::Null
-                    }
-                }
-                _ => Object::Error("putc arg must be file".to_string()),
+fn expect_integer(args: &[Object], i: usize) -> Result<i64, String> {
+    match &args[i] {
+        Object::Integer(n) => Ok(*n),
+        other => Err(wrong_type(i, "an integer", other)),
+    }
+}
+
+fn expect_array(args: &[Object], i: usize) -> Result<&Rc<RefCell<Vec<Object>>>, String> {
+    match &args[i] {
+        Object::Array(elements) => Ok(elements),
+        other => Err(wrong_type(i, "an array", other)),
+    }
+}
+
+fn expect_file(args: &[Object], i: usize) -> Result<&Rc<RefCell<FileHandle>>, String> {
+    match &args[i] {
+        Object::File(handle) => Ok(handle),
+        other => Err(wrong_type(i, "a file", other)),
+    }
+}
+
+// A file argument that hasn't been closed, borrowed for reading or writing
+fn expect_open_file(args: &[Object], i: usize) -> Result<RefMut<'_, FileHandle>, String> {
+    let fh = expect_file(args, i)?.borrow_mut();
+    if fh.is_closed() {
+        return Err(CLOSED_FILE.to_string());
+    }
+    Ok(fh)
+}
+
+// A one-character string or an integer code point
+fn expect_char(args: &[Object], i: usize) -> Result<char, String> {
+    match &args[i] {
+        Object::String(s) => {
+            let mut chars = s.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Ok(c),
+                _ => Err(format!("expected a single character, got {:?}", s)),
             }
-        }),
-    );
+        }
+        Object::Integer(code) => code_point(*code),
+        other => Err(wrong_type(i, "a character or code point", other)),
+    }
+}
+
+fn wrong_type(i: usize, expected: &str, got: &Object) -> String {
+    format!(
+        "argument {} must be {}, got {}",
+        i + 1,
+        expected,
+        got.repr()
+    )
+}
+
+type CharClass = fn(char) -> bool;
+
+// Rejects negative numbers, surrogates and anything past U+10FFFF
+fn code_point(code: i64) -> Result<char, String> {
+    u32::try_from(code)
+        .ok()
+        .and_then(char::from_u32)
+        .ok_or_else(|| format!("invalid code point {}", code))
+}
+
+// fgetc(file) and getc(file): the next byte as a one-character string, or null
+// at the end of the file or on a read error
+fn read_char(args: &[Object]) -> BuiltinResult {
+    let mut fh = expect_open_file(args, 0)?;
+    let mut buf = [0; 1];
+    match fh.read(&mut buf) {
+        Ok(0) => {
+            fh.eof = true;
+            Ok(Object::Null)
+        }
+        Ok(_) => Ok(Object::String((buf[0] as char).to_string())),
+        Err(_) => {
+            fh.error = true;
+            Ok(Object::Null)
+        }
+    }
+}
+
+// fputc(c, file) and putc(c, file): writes the first character of c
+fn write_char(args: &[Object]) -> BuiltinResult {
+    let mut fh = expect_open_file(args, 1)?;
+    let Some(c) = args[0].inspect().chars().next() else {
+        return Ok(Object::Null);
+    };
+    if let Err(e) = write!(fh, "{}", c) {
+        fh.error = true;
+        return Err(e.to_string());
+    }
+    Ok(Object::Null)
+}
+
+// Runs `cmd` through the platform shell, like C's system
+fn shell_command(cmd: &str) -> std::process::Command {
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let mut command = std::process::Command::new(shell);
+    command.arg(flag).arg(cmd);
+    command
+}
+
+fn read_stdin_line(output: &Output) -> BuiltinResult {
+    match read_stdin(output, read_line)? {
+        Ok(Some(line)) => Ok(Object::String(line)),
+        Ok(None) => Ok(Object::Null),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// Runs `read` on stdin after flushing the output, so a prompt printed without a
+// newline is visible before the program blocks waiting for input
+fn read_stdin<T>(output: &Output, read: impl FnOnce(&mut dyn BufRead) -> T) -> Result<T, String> {
+    output.borrow_mut().flush().map_err(|e| e.to_string())?;
+    Ok(read(&mut std::io::stdin().lock()))
+}
+
+// Where the printing builtins write; stdout unless an embedder injects another writer
+pub type Output = Rc<RefCell<dyn Write>>;
+
+pub fn stdout_output() -> Output {
+    Rc::new(RefCell::new(std::io::stdout()))
+}
+
+// The files tmpfile() has created and the program hasn't closed, so any still
+// open (say, kept alive by a reference cycle) can be removed once it finishes
+pub type TempFiles = Rc<RefCell<Vec<std::path::PathBuf>>>;
+
+// Removes every file in `files` that is still there
+pub fn remove_temp_files(files: &TempFiles) {
+    for path in files.borrow_mut().drain(..) {
+        // One whose handle was dropped is already gone
+        let _ = std::fs::remove_file(path);
+    }
+}
 
-    // getc -> fgetc
-    // putc -> fputc
-    // rewind is already there. Wait, I added rewind.
+fn write_output(output: &Output, s: &str) -> BuiltinResult {
+    match output.borrow_mut().write_all(s.as_bytes()) {
+        Ok(()) => Ok(Object::Null),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn join_inspected(args: &[Object]) -> String {
+    let values: Vec<String> = args.iter().map(Object::inspect).collect();
+    values.join(" ")
+}
+
+// Which groups of potentially dangerous builtins a program may use. Disabled
+// builtins stay defined but fail with "<name> is disabled in sandbox mode", or
+// for stdin "<name> is disabled because stdin is not available to the program"
+// (it may hold the program itself), and the `stdin` file object is closed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StdlibConfig {
+    pub allow_fs: bool,      // Opening, removing and renaming files
+    pub allow_process: bool, // Running commands and reading environment variables
+    pub allow_stdin: bool,   // Reading from stdin
+}
+
+impl Default for StdlibConfig {
+    // Everything allowed
+    fn default() -> Self {
+        StdlibConfig {
+            allow_fs: true,
+            allow_process: true,
+            allow_stdin: true,
+        }
+    }
+}
+
+impl StdlibConfig {
+    // For untrusted programs: no filesystem or process access, stdin still allowed
+    pub fn sandbox() -> Self {
+        StdlibConfig {
+            allow_fs: false,
+            allow_process: false,
+            allow_stdin: true,
+        }
+    }
+}
 
-    // We can just reuse the function pointers if we had them or just redefine.
-    // simpler to just call the other builtin if I could look it up, but I can't.
-    // Redefining is fine.
+const FS_BUILTINS: &[&str] = &[
+    "fopen",
+    "remove",
+    "rename",
+    "file_exists",
+    "file_size",
+    "is_dir",
+    "mkdir",
+    "rmdir",
+    "listdir",
+    "tmpfile",
+    "tempdir",
+    "read_file",
+    "write_file",
+    "append_file",
+];
+const PROCESS_BUILTINS: &[&str] = &["system", "system_output", "getenv"];
+const STDIN_BUILTINS: &[&str] = &["getchar", "gets", "input", "read_int", "scanf"];
+
+pub fn register_stdlib(
+    env: Rc<RefCell<Environment>>,
+    output: Output,
+    config: StdlibConfig,
+    temp_files: TempFiles,
+) {
+    let mut env_mut = env.borrow_mut();
+    let env_mut = &mut *env_mut;
+
+    // puts(...) and print(...): the arguments separated by spaces, with or
+    // without a trailing newline. puts() prints just the newline.
+    let out = Rc::clone(&output);
+    builtin(env_mut, "puts", 0..=VARIADIC, move |args| {
+        write_output(&out, &format!("{}\n", join_inspected(args)))
+    });
+    let out = Rc::clone(&output);
+    builtin(env_mut, "print", 0..=VARIADIC, move |args| {
+        write_output(&out, &join_inspected(args))
+    });
+
+    // putchar(char)
+    let out = Rc::clone(&output);
+    builtin(env_mut, "putchar", 1..=1, move |args| {
+        match args[0].inspect().chars().next() {
+            Some(c) => write_output(&out, &c.to_string()),
+            None => Ok(Object::Null),
+        }
+    });
+
+    // printf(fmt, ...)
+    let out = Rc::clone(&output);
+    builtin(env_mut, "printf", 1..=VARIADIC, move |args| {
+        write_output(&out, &format_output(args)?)
+    });
+
+    // sprintf(fmt, ...) -> String
+    builtin(env_mut, "sprintf", 1..=VARIADIC, |args| {
+        Ok(Object::String(format_output(args)?))
+    });
+
+    // strlen(s) -> Integer, counted in chars like fgetc produces them
+    builtin(env_mut, "strlen", 1..=1, |args| {
+        Ok(Object::Integer(
+            expect_string(args, 0)?.chars().count() as i64
+        ))
+    });
+
+    // strcmp(a, b) -> negative, zero or positive Integer
+    builtin(env_mut, "strcmp", 2..=2, |args| {
+        let (a, b) = (expect_string(args, 0)?, expect_string(args, 1)?);
+        Ok(Object::Integer(a.cmp(b) as i64))
+    });
+
+    // strcat(a, b) -> String; a is left as it was, unlike C
+    builtin(env_mut, "strcat", 2..=2, |args| {
+        let (a, b) = (expect_string(args, 0)?, expect_string(args, 1)?);
+        Ok(Object::String(format!("{}{}", a, b)))
+    });
+
+    // atoi(s) -> Integer, 0 when there are no leading digits
+    builtin(env_mut, "atoi", 1..=1, |args| {
+        match parse_leading_int(expect_string(args, 0)?) {
+            Some((n, _)) => Ok(Object::Integer(n)),
+            None => Ok(Object::Integer(0)),
+        }
+    });
+
+    // atoi_checked(s) -> Integer, or null unless the whole string is a number
+    builtin(
+        env_mut,
+        "atoi_checked",
+        1..=1,
+        |args| match parse_leading_int(expect_string(args, 0)?) {
+            Some((n, true)) => Ok(Object::Integer(n)),
+            _ => Ok(Object::Null),
+        },
+    );
+
+    // itoa(n) -> String
+    builtin(env_mut, "itoa", 1..=1, |args| {
+        Ok(Object::String(expect_integer(args, 0)?.to_string()))
+    });
+
+    // substr(s, start, len) -> String of up to len chars from start
+    builtin(env_mut, "substr", 3..=3, |args| {
+        let s = expect_string(args, 0)?;
+        let (start, len) = (expect_integer(args, 1)?, expect_integer(args, 2)?);
+        if start < 0 {
+            return Err(format!("start must be non-negative, got {}", start));
+        }
+        if len < 0 {
+            return Err(format!("length must be non-negative, got {}", len));
+        }
+        // Past the end of the string the result is cut short rather than an error
+        Ok(Object::String(
+            s.chars().skip(start as usize).take(len as usize).collect(),
+        ))
+    });
+
+    // char_at(s, i) -> one-character String, the same as s[i]
+    builtin(env_mut, "char_at", 2..=2, |args| {
+        let (s, i) = (expect_string(args, 0)?, expect_integer(args, 1)?);
+        match usize::try_from(i).ok().and_then(|i| s.chars().nth(i)) {
+            Some(c) => Ok(Object::String(c.to_string())),
+            None => Err(format!(
+                "index {} out of bounds for length {}",
+                i,
+                s.chars().count()
+            )),
+        }
+    });
+
+    // index_of(haystack, needle) -> Integer char position of the first match, or -1
+    builtin(env_mut, "index_of", 2..=2, |args| {
+        let (haystack, needle) = (expect_string(args, 0)?, expect_string(args, 1)?);
+        match haystack.find(needle) {
+            Some(byte) => Ok(Object::Integer(haystack[..byte].chars().count() as i64)),
+            None => Ok(Object::Integer(-1)),
+        }
+    });
+
+    // contains(s, sub) -> Boolean
+    builtin(env_mut, "contains", 2..=2, |args| {
+        let (s, sub) = (expect_string(args, 0)?, expect_string(args, 1)?);
+        Ok(Object::Boolean(s.contains(sub)))
+    });
+
+    // replace(s, from, to) -> String with every occurrence of from replaced
+    builtin(env_mut, "replace", 3..=3, |args| {
+        let s = expect_string(args, 0)?;
+        let (from, to) = (expect_string(args, 1)?, expect_string(args, 2)?);
+        if from.is_empty() {
+            return Err("from must not be empty".to_string());
+        }
+        Ok(Object::String(s.replace(from, to)))
+    });
+
+    // split(s, sep) -> Array of Strings; an empty sep splits into characters
+    builtin(env_mut, "split", 2..=2, |args| {
+        let (s, sep) = (expect_string(args, 0)?, expect_string(args, 1)?);
+        let parts: Vec<Object> = if sep.is_empty() {
+            s.chars().map(|c| Object::String(c.to_string())).collect()
+        } else {
+            s.split(sep)
+                .map(|part| Object::String(part.to_string()))
+                .collect()
+        };
+        Ok(Object::Array(Rc::new(RefCell::new(parts))))
+    });
+
+    // trim(s) -> String without leading and trailing whitespace
+    builtin(env_mut, "trim", 1..=1, |args| {
+        Ok(Object::String(expect_string(args, 0)?.trim().to_string()))
+    });
+
+    // toupper(s) -> String
+    builtin(env_mut, "toupper", 1..=1, |args| {
+        Ok(Object::String(expect_string(args, 0)?.to_uppercase()))
+    });
+
+    // tolower(s) -> String
+    builtin(env_mut, "tolower", 1..=1, |args| {
+        Ok(Object::String(expect_string(args, 0)?.to_lowercase()))
+    });
+
+    // isdigit(c), isalpha(c), isspace(c), isalnum(c) -> Boolean; false for
+    // -1, C's EOF, so ported character loops don't stop with an error
+    let classes: [(&str, CharClass); 4] = [
+        ("isdigit", |c| c.is_ascii_digit()),
+        ("isalpha", char::is_alphabetic),
+        ("isspace", char::is_whitespace),
+        ("isalnum", |c| c.is_ascii_digit() || c.is_alphabetic()),
+    ];
+    for (name, class) in classes {
+        builtin(env_mut, name, 1..=1, move |args| {
+            if args[0] == Object::Integer(-1) {
+                return Ok(Object::Boolean(false));
+            }
+            Ok(Object::Boolean(class(expect_char(args, 0)?)))
+        });
+    }
+
+    // ord(c) -> Integer code point of a one-character string
+    builtin(env_mut, "ord", 1..=1, |args| {
+        expect_string(args, 0)?;
+        Ok(Object::Integer(expect_char(args, 0)? as i64))
+    });
+
+    // chr(n) -> one-character String for a code point
+    builtin(env_mut, "chr", 1..=1, |args| {
+        let c = code_point(expect_integer(args, 0)?)?;
+        Ok(Object::String(c.to_string()))
+    });
+
+    // typeof(x) -> String naming the type of x, such as "integer" or "string"
+    builtin(env_mut, "typeof", 1..=1, |args| {
+        Ok(Object::String(args[0].type_name().to_lowercase()))
+    });
+
+    // repr(x) -> String like puts prints, but with strings quoted and escaped
+    builtin(env_mut, "repr", 1..=1, |args| {
+        Ok(Object::String(args[0].repr()))
+    });
+
+    // len(a) -> Integer, the number of elements of an array or chars of a string
+    builtin(env_mut, "len", 1..=1, |args| match &args[0] {
+        Object::Array(elements) => Ok(Object::Integer(elements.borrow().len() as i64)),
+        Object::String(s) => Ok(Object::Integer(s.chars().count() as i64)),
+        other => Err(wrong_type(0, "an array or string", other)),
+    });
+
+    // push(a, v) -> Integer, the new length
+    builtin(env_mut, "push", 2..=2, |args| {
+        let mut elements = expect_array(args, 0)?.borrow_mut();
+        elements.push(args[1].clone());
+        Ok(Object::Integer(elements.len() as i64))
+    });
+
+    // pop(a) -> the removed last element
+    builtin(env_mut, "pop", 1..=1, |args| {
+        match expect_array(args, 0)?.borrow_mut().pop() {
+            Some(last) => Ok(last),
+            None => Err("array is empty".to_string()),
+        }
+    });
+
+    // abs(n) -> Integer
+    builtin(env_mut, "abs", 1..=1, |args| {
+        let n = expect_integer(args, 0)?;
+        match n.checked_abs() {
+            Some(abs) => Ok(Object::Integer(abs)),
+            None => Err(format!("{} has no positive counterpart", n)),
+        }
+    });
+
+    // min(a, b) -> Integer
+    builtin(env_mut, "min", 2..=2, |args| {
+        let (a, b) = (expect_integer(args, 0)?, expect_integer(args, 1)?);
+        Ok(Object::Integer(a.min(b)))
+    });
+
+    // max(a, b) -> Integer
+    builtin(env_mut, "max", 2..=2, |args| {
+        let (a, b) = (expect_integer(args, 0)?, expect_integer(args, 1)?);
+        Ok(Object::Integer(a.max(b)))
+    });
+
+    // pow(base, exp) -> Integer; negative exponents and overflow are errors
+    builtin(env_mut, "pow", 2..=2, |args| {
+        let (base, exp) = (expect_integer(args, 0)?, expect_integer(args, 1)?);
+        let Ok(exp) = u32::try_from(exp) else {
+            return Err(format!("exponent must be non-negative, got {}", exp));
+        };
+        match base.checked_pow(exp) {
+            Some(result) => Ok(Object::Integer(result)),
+            None => Err(format!("{} to the power {} overflows", base, exp)),
+        }
+    });
+
+    // sqrt(n) -> Integer, the floor of the square root
+    builtin(env_mut, "sqrt", 1..=1, |args| {
+        let n = expect_integer(args, 0)?;
+        if n < 0 {
+            return Err(format!("negative number {}", n));
+        }
+        Ok(Object::Integer(n.isqrt()))
+    });
+
+    let rng = Rc::new(Rng::new());
+
+    // srand(seed): restarts the sequence rand and rand_range produce
+    let state = Rc::clone(&rng);
+    builtin(env_mut, "srand", 1..=1, move |args| {
+        state.seed(expect_integer(args, 0)?);
+        Ok(Object::Null)
+    });
+
+    // rand() -> Integer in 0..=2147483647, like C's rand with RAND_MAX = 2^31 - 1
+    let state = Rc::clone(&rng);
+    builtin(env_mut, "rand", 0..=0, move |_| {
+        Ok(Object::Integer((state.next_u64() >> 33) as i64))
+    });
+
+    // rand_range(lo, hi) -> Integer in lo..=hi
+    let state = Rc::clone(&rng);
+    builtin(env_mut, "rand_range", 2..=2, move |args| {
+        let (lo, hi) = (expect_integer(args, 0)?, expect_integer(args, 1)?);
+        if lo > hi {
+            return Err(format!("lo ({}) must not be greater than hi ({})", lo, hi));
+        }
+        Ok(Object::Integer(state.range(lo, hi)))
+    });
+
+    // time() -> Integer, seconds since the Unix epoch
+    builtin(env_mut, "time", 0..=0, |_| {
+        match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(elapsed) => Ok(Object::Integer(elapsed.as_secs() as i64)),
+            Err(_) => Err("system clock is before 1970".to_string()),
+        }
+    });
+
+    // clock_ms() -> Integer, milliseconds since the stdlib was registered
+    let start = Instant::now();
+    builtin(env_mut, "clock_ms", 0..=0, move |_| {
+        Ok(Object::Integer(start.elapsed().as_millis() as i64))
+    });
+
+    // sleep_ms(n)
+    builtin(env_mut, "sleep_ms", 1..=1, |args| {
+        let ms = expect_integer(args, 0)?;
+        if ms < 0 {
+            return Err(format!("duration must be non-negative, got {}", ms));
+        }
+        std::thread::sleep(Duration::from_millis(ms as u64));
+        Ok(Object::Null)
+    });
+
+    // system(cmd) -> Integer exit status, or -1 if the command couldn't be run
+    let out = Rc::clone(&output);
+    builtin(env_mut, "system", 1..=1, move |args| {
+        let cmd = expect_string(args, 0)?;
+        // The command writes straight to the terminal, so earlier output goes first
+        let _ = out.borrow_mut().flush();
+        match shell_command(cmd).status() {
+            Ok(status) => Ok(Object::Integer(status.code().unwrap_or(-1) as i64)),
+            Err(_) => Ok(Object::Integer(-1)),
+        }
+    });
+
+    // system_output(cmd) -> String of everything cmd printed to stdout, or null
+    // if it couldn't be run
+    builtin(
+        env_mut,
+        "system_output",
+        1..=1,
+        |args| match shell_command(expect_string(args, 0)?).output() {
+            Ok(output) => Ok(Object::String(
+                String::from_utf8_lossy(&output.stdout).into_owned(),
+            )),
+            Err(_) => Ok(Object::Null),
+        },
+    );
+
+    // getenv(name) -> String, or null if unset
+    builtin(env_mut, "getenv", 1..=1, |args| {
+        match std::env::var(expect_string(args, 0)?) {
+            Ok(value) => Ok(Object::String(value)),
+            Err(_) => Ok(Object::Null),
+        }
+    });
+
+    // The errno of the last file builtin that failed, 0 if none has
+    let last_errno = Rc::new(Cell::new(0));
+
+    // errno() -> Integer
+    let errno = Rc::clone(&last_errno);
+    builtin(env_mut, "errno", 0..=0, move |_| {
+        Ok(Object::Integer(errno.get()))
+    });
+
+    // strerror(code) -> String
+    builtin(env_mut, "strerror", 1..=1, |args| {
+        Ok(Object::String(strerror(expect_integer(args, 0)?)))
+    });
+
+    // perror(prefix): prints "prefix: <strerror(errno())>" to stderr, or just
+    // the message when the prefix is empty
+    let (errno, out) = (Rc::clone(&last_errno), Rc::clone(&output));
+    builtin(env_mut, "perror", 1..=1, move |args| {
+        let prefix = expect_string(args, 0)?;
+        let message = strerror(errno.get());
+        // Flushed first so the message lands after anything already printed
+        out.borrow_mut().flush().map_err(|e| e.to_string())?;
+        let line = if prefix.is_empty() {
+            format!("{}\n", message)
+        } else {
+            format!("{}: {}\n", prefix, message)
+        };
+        std::io::stderr()
+            .write_all(line.as_bytes())
+            .map_err(|e| e.to_string())?;
+        Ok(Object::Null)
+    });
+
+    // fopen(path, mode) -> a file, or null with errno set if it can't be opened
+    let errno = Rc::clone(&last_errno);
+    builtin(env_mut, "fopen", 2..=2, move |args| {
+        let (path, mode) = (expect_string(args, 0)?, expect_string(args, 1)?);
+        let Some(options) = open_options(mode) else {
+            return Err(format!("invalid mode {:?}", mode));
+        };
+        match options.open(path) {
+            Ok(f) => Ok(Object::File(Rc::new(RefCell::new(FileHandle::new(f))))),
+            Err(e) => {
+                errno.set(errno_code(&e));
+                Ok(Object::Null)
+            }
+        }
+    });
+
+    // tmpfile() -> a new, empty file open for reading and writing, or null
+    // with errno set. It is deleted once closed, or when the program ends.
+    let (errno, created) = (Rc::clone(&last_errno), Rc::clone(&temp_files));
+    builtin(env_mut, "tmpfile", 0..=0, move |_| {
+        match create_temp_file() {
+            Ok((file, path)) => {
+                let mut created = created.borrow_mut();
+                // Files whose handles were dropped unclosed are already gone
+                created.retain(|path| path.exists());
+                created.push(path.clone());
+                Ok(Object::File(Rc::new(RefCell::new(FileHandle::temporary(
+                    file, path,
+                )))))
+            }
+            Err(e) => {
+                errno.set(errno_code(&e));
+                Ok(Object::Null)
+            }
+        }
+    });
+
+    // tempdir() -> String, the OS directory for temporary files
+    builtin(env_mut, "tempdir", 0..=0, |_| {
+        Ok(Object::String(
+            std::env::temp_dir().to_string_lossy().into_owned(),
+        ))
+    });
+
+    // read_file(path) -> String of the whole file; invalid UTF-8 becomes U+FFFD
+    builtin(env_mut, "read_file", 1..=1, |args| {
+        match std::fs::read(expect_string(args, 0)?) {
+            Ok(bytes) => Ok(Object::String(String::from_utf8_lossy(&bytes).into_owned())),
+            Err(e) => Err(e.to_string()),
+        }
+    });
+
+    // write_file(path, content): creates or truncates the file
+    builtin(env_mut, "write_file", 2..=2, |args| {
+        let (path, content) = (expect_string(args, 0)?, expect_string(args, 1)?);
+        match std::fs::write(path, content) {
+            Ok(()) => Ok(Object::Null),
+            Err(e) => Err(e.to_string()),
+        }
+    });
+
+    // append_file(path, content): creates the file if needed
+    builtin(env_mut, "append_file", 2..=2, |args| {
+        let (path, content) = (expect_string(args, 0)?, expect_string(args, 1)?);
+        let result = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(content.as_bytes()));
+        match result {
+            Ok(()) => Ok(Object::Null),
+            Err(e) => Err(e.to_string()),
+        }
+    });
+
+    // fclose(file); a tmpfile() file is deleted
+    builtin(env_mut, "fclose", 1..=1, move |args| {
+        let mut handle = expect_file(args, 0)?.borrow_mut();
+        if let Some(path) = handle.temp_path() {
+            temp_files.borrow_mut().retain(|created| created != path);
+        }
+        match handle.close() {
+            Ok(()) => Ok(Object::Integer(0)),
+            Err(_) => Ok(Object::Integer(-1)),
+        }
+    });
+
+    // fputs(str, file)
+    builtin(env_mut, "fputs", 2..=2, |args| {
+        let content = expect_string(args, 0)?;
+        let mut fh = expect_open_file(args, 1)?;
+        if let Err(e) = write!(fh, "{}", content) {
+            fh.error = true;
+            return Err(e.to_string());
+        }
+        Ok(Object::Null)
+    });
+
+    // fputc(char, file)
+    builtin(env_mut, "fputc", 2..=2, write_char);
+
+    // fprintf(file, fmt, ...)
+    builtin(env_mut, "fprintf", 2..=VARIADIC, |args| {
+        expect_file(args, 0)?;
+        let s = format_output(&args[1..])?;
+        let mut fh = expect_open_file(args, 0)?;
+        if let Err(e) = write!(fh, "{}", s) {
+            fh.error = true;
+            return Err(e.to_string());
+        }
+        Ok(Object::Null)
+    });
+
+    // fgets(file)
+    builtin(env_mut, "fgets", 1..=1, |args| {
+        let mut fh = expect_open_file(args, 0)?;
+        let mut bytes = vec![];
+        if let Err(e) = fh.read_until(b'\n', &mut bytes) {
+            fh.error = true;
+            return Err(e.to_string());
+        }
+        // Only a line cut short by the end of the file lacks its newline
+        if bytes.last() != Some(&b'\n') {
+            fh.eof = true;
+        }
+        let line: String = bytes.iter().map(|&b| b as char).collect();
+        if line.is_empty() && fh.eof {
+            Ok(Object::Null)
+        } else {
+            Ok(Object::String(line))
+        }
+    });
+
+    // fgetc(file)
+    builtin(env_mut, "fgetc", 1..=1, read_char);
+
+    // feof(file)
+    builtin(env_mut, "feof", 1..=1, |args| {
+        Ok(Object::Boolean(expect_open_file(args, 0)?.eof))
+    });
+
+    // ferror(file)
+    builtin(env_mut, "ferror", 1..=1, |args| {
+        Ok(Object::Boolean(expect_open_file(args, 0)?.error))
+    });
+
+    // ftell(file)
+    builtin(env_mut, "ftell", 1..=1, |args| {
+        match expect_open_file(args, 0)?.stream_position() {
+            Ok(pos) => Ok(Object::Integer(pos as i64)),
+            Err(_) => Ok(Object::Integer(-1)),
+        }
+    });
+
+    // fseek(file, offset, whence) (whence: 0=Start, 1=Current, 2=End)
+    builtin(env_mut, "fseek", 3..=3, |args| {
+        expect_file(args, 0)?;
+        let (offset, whence) = (expect_integer(args, 1)?, expect_integer(args, 2)?);
+        let pos = match whence {
+            0 => SeekFrom::Start(offset as u64),
+            1 => SeekFrom::Current(offset),
+            2 => SeekFrom::End(offset),
+            _ => return Err(format!("invalid whence {}", whence)),
+        };
+        let mut fh = expect_open_file(args, 0)?;
+        match fh.seek(pos) {
+            Ok(_) => {
+                fh.eof = false;
+                Ok(Object::Integer(0))
+            }
+            Err(_) => {
+                fh.error = true;
+                Ok(Object::Integer(-1))
+            }
+        }
+    });
+
+    // rewind(file)
+    builtin(env_mut, "rewind", 1..=1, |args| {
+        let mut fh = expect_open_file(args, 0)?;
+        let _ = fh.seek(SeekFrom::Start(0));
+        fh.eof = false;
+        fh.error = false;
+        Ok(Object::Null)
+    });
+
+    // remove(path) -> 0, or -1 with errno set
+    let errno = Rc::clone(&last_errno);
+    builtin(env_mut, "remove", 1..=1, move |args| {
+        let result = std::fs::remove_file(expect_string(args, 0)?);
+        Ok(io_status(result, &errno))
+    });
+
+    // rename(old, new) -> 0, or -1 with errno set
+    let errno = Rc::clone(&last_errno);
+    builtin(env_mut, "rename", 2..=2, move |args| {
+        let (old, new) = (expect_string(args, 0)?, expect_string(args, 1)?);
+        Ok(io_status(std::fs::rename(old, new), &errno))
+    });
+
+    // file_exists(path) -> Boolean; like the two below, follows symlinks, so
+    // a link to nothing doesn't exist
+    builtin(env_mut, "file_exists", 1..=1, |args| {
+        let path = std::path::Path::new(expect_string(args, 0)?);
+        Ok(Object::Boolean(path.exists()))
+    });
+
+    // file_size(path) -> size in bytes, or -1 with errno set
+    let errno = Rc::clone(&last_errno);
+    builtin(
+        env_mut,
+        "file_size",
+        1..=1,
+        move |args| match std::fs::metadata(expect_string(args, 0)?) {
+            Ok(metadata) => Ok(Object::Integer(metadata.len() as i64)),
+            Err(e) => {
+                errno.set(errno_code(&e));
+                Ok(Object::Integer(-1))
+            }
+        },
+    );
+
+    // is_dir(path) -> Boolean, false if it doesn't exist
+    builtin(env_mut, "is_dir", 1..=1, |args| {
+        let path = std::path::Path::new(expect_string(args, 0)?);
+        Ok(Object::Boolean(path.is_dir()))
+    });
+
+    // mkdir(path, recursive = false) -> 0, or -1 with errno set. A truthy
+    // `recursive` creates missing parents and accepts an existing directory.
+    let errno = Rc::clone(&last_errno);
+    builtin(env_mut, "mkdir", 1..=2, move |args| {
+        let path = expect_string(args, 0)?;
+        let result = if args.get(1).is_some_and(Object::is_truthy) {
+            std::fs::create_dir_all(path)
+        } else {
+            std::fs::create_dir(path)
+        };
+        Ok(io_status(result, &errno))
+    });
+
+    // rmdir(path) -> 0, or -1 with errno set; the directory must be empty
+    let errno = Rc::clone(&last_errno);
+    builtin(env_mut, "rmdir", 1..=1, move |args| {
+        let result = std::fs::remove_dir(expect_string(args, 0)?);
+        Ok(io_status(result, &errno))
+    });
+
+    // listdir(path) -> Array of entry names, sorted, or null with errno set.
+    // Hidden entries are included; "." and ".." never are.
+    let errno = Rc::clone(&last_errno);
+    builtin(env_mut, "listdir", 1..=1, move |args| {
+        let entries = std::fs::read_dir(expect_string(args, 0)?).and_then(|entries| {
+            entries
+                .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+                .collect::<std::io::Result<Vec<String>>>()
+        });
+        match entries {
+            Ok(mut names) => {
+                names.sort();
+                let names = names.into_iter().map(Object::String).collect();
+                Ok(Object::Array(Rc::new(RefCell::new(names))))
+            }
+            Err(e) => {
+                errno.set(errno_code(&e));
+                Ok(Object::Null)
+            }
+        }
+    });
+
+    // exit(code): unwinds the interpreter instead of killing the process, so open
+    // files are dropped and embedders keep control
+    builtin(env_mut, "exit", 1..=1, |args| {
+        Ok(Object::Exit(expect_integer(args, 0)?))
+    });
+
+    // assert(cond, message): a runtime error unless cond is truthy. Failures are
+    // returned as values so they read "assertion failed" without the name prefix
+    builtin(env_mut, "assert", 1..=2, |args| {
+        if args[0].is_truthy() {
+            return Ok(Object::Null);
+        }
+        match args.get(1) {
+            Some(message) => Ok(Object::Error(format!(
+                "assertion failed: {}",
+                message.inspect()
+            ))),
+            None => Ok(Object::Error("assertion failed".to_string())),
+        }
+    });
+
+    // assert_eq(actual, expected): a runtime error showing both unless they're equal
+    builtin(env_mut, "assert_eq", 2..=2, |args| {
+        if args[0] == args[1] {
+            return Ok(Object::Null);
+        }
+        Ok(Object::Error(format!(
+            "assertion failed: expected {}, got {}",
+            args[1].repr(),
+            args[0].repr()
+        )))
+    });
+
+    // getchar()
+    let out = Rc::clone(&output);
+    builtin(env_mut, "getchar", 0..=0, move |_| {
+        let mut buf = [0; 1];
+        match read_stdin(&out, |stdin| stdin.read(&mut buf))? {
+            Ok(0) => Ok(Object::Null), // EOF
+            Ok(_) => Ok(Object::String((buf[0] as char).to_string())),
+            Err(e) => Err(e.to_string()),
+        }
+    });
+
+    // gets() -> the next line of stdin without its newline, or null at EOF
+    let out = Rc::clone(&output);
+    builtin(env_mut, "gets", 0..=0, move |_| read_stdin_line(&out));
+
+    // input(prompt) -> like gets, after printing the optional prompt
+    let out = Rc::clone(&output);
+    builtin(env_mut, "input", 0..=1, move |args| {
+        if let Some(prompt) = args.first() {
+            write_output(&out, &prompt.inspect())?;
+        }
+        read_stdin_line(&out)
+    });
+
+    // read_int() -> the next integer on stdin, or null at EOF or on a non-number
+    let out = Rc::clone(&output);
+    builtin(env_mut, "read_int", 0..=0, move |_| {
+        match read_stdin(&out, read_int)? {
+            Ok(Some(n)) => Ok(Object::Integer(n)),
+            Ok(None) => Ok(Object::Null),
+            Err(e) => Err(e.to_string()),
+        }
+    });
+
+    // scanf(fmt) -> the value of its one %d or %s conversion, or null
+    let out = Rc::clone(&output);
+    builtin(env_mut, "scanf", 1..=1, move |args| {
+        let fmt = expect_string(args, 0)?;
+        read_stdin(&out, |stdin| scan(stdin, fmt))?
+    });
+
+    // fflush(file) -> 0, or -1 on failure; fflush() flushes stdout
+    let out = Rc::clone(&output);
+    builtin(env_mut, "fflush", 0..=1, move |args| {
+        let result = if args.is_empty() {
+            out.borrow_mut().flush()
+        } else {
+            let mut fh = expect_open_file(args, 0)?;
+            let result = fh.flush();
+            fh.error |= result.is_err();
+            result
+        };
+        match result {
+            Ok(()) => Ok(Object::Integer(0)),
+            Err(_) => Ok(Object::Integer(-1)),
+        }
+    });
+
+    // getc(file) and putc(c, file), the same as fgetc and fputc
+    builtin(env_mut, "getc", 1..=1, read_char);
+    builtin(env_mut, "putc", 2..=2, write_char);
+
+    let disabled = [
+        (config.allow_fs, FS_BUILTINS, "in sandbox mode"),
+        (config.allow_process, PROCESS_BUILTINS, "in sandbox mode"),
+        (
+            config.allow_stdin,
+            STDIN_BUILTINS,
+            "because stdin is not available to the program",
+        ),
+    ];
+    for (_, names, reason) in disabled.iter().filter(|(allowed, _, _)| !allowed) {
+        for name in names.iter() {
+            let message = format!("{} is disabled {}", name, reason);
+            env_mut.register_builtin(name, move |_| Object::Error(message.clone()));
+        }
+    }
+
+    // The standard streams as file objects. stdout shares the interpreter's
+    // output so fprintf(stdout, ...) stays in order with printf.
+    let mut stdin = FileHandle::from_stream(Stream::Stdin(Rc::clone(&output)));
+    if !config.allow_stdin {
+        let _ = stdin.close();
+    }
+    let streams = [
+        ("stdin", stdin),
+        ("stdout", FileHandle::from_stream(Stream::Stdout(output))),
+        ("stderr", FileHandle::from_stream(Stream::Stderr)),
+    ];
+    for (name, handle) in streams {
+        env_mut.set_builtin(name, Object::File(Rc::new(RefCell::new(handle))));
+    }
+
+    for (name, code, _) in ERRNOS {
+        env_mut.set_builtin(name, Object::Integer(*code));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A fresh environment with the stdlib registered
+    fn stdlib_env(output: Output, config: StdlibConfig) -> Rc<RefCell<Environment>> {
+        let env = Rc::new(RefCell::new(Environment::new()));
+        register_stdlib(Rc::clone(&env), output, config, TempFiles::default());
+        env
+    }
+
+    fn call(name: &str, args: Vec<Object>) -> Object {
+        let env = stdlib_env(stdout_output(), StdlibConfig::default());
+        let builtin = env.borrow().get(name);
+        match builtin {
+            Some(Object::Builtin(f)) => f.call(args),
+            _ => panic!("{} is not a builtin", name),
+        }
+    }
+
+    fn string(s: &str) -> Object {
+        Object::String(s.to_string())
+    }
+
+    #[test]
+    fn test_misuse_names_the_builtin() {
+        let file = call("fopen", vec![string(&temp_path("misuse.txt")), string("w")]);
+        let cases = [
+            ("strlen", vec![], "strlen: expected 1 argument, got 0"),
+            (
+                "rand",
+                vec![Object::Integer(1)],
+                "rand: expected 0 arguments, got 1",
+            ),
+            (
+                "printf",
+                vec![],
+                "printf: expected at least 1 argument, got 0",
+            ),
+            (
+                "fprintf",
+                vec![file.clone()],
+                "fprintf: expected at least 2 arguments, got 1",
+            ),
+            (
+                "input",
+                vec![string("a"), string("b")],
+                "input: expected at most 1 argument, got 2",
+            ),
+            (
+                "fopen",
+                vec![Object::Integer(1), string("r")],
+                "fopen: argument 1 must be a string, got 1",
+            ),
+            (
+                "fputs",
+                vec![string("x"), string("out.txt")],
+                "fputs: argument 2 must be a file, got \"out.txt\"",
+            ),
+            (
+                "fseek",
+                vec![file, Object::Null, Object::Integer(0)],
+                "fseek: argument 2 must be an integer, got null",
+            ),
+            (
+                "push",
+                vec![Object::Integer(1), Object::Integer(2)],
+                "push: argument 1 must be an array, got 1",
+            ),
+            (
+                "len",
+                vec![Object::Boolean(true)],
+                "len: argument 1 must be an array or string, got true",
+            ),
+            (
+                "isdigit",
+                vec![Object::Null],
+                "isdigit: argument 1 must be a character or code point, got null",
+            ),
+            (
+                "exit",
+                vec![string("1")],
+                "exit: argument 1 must be an integer, got \"1\"",
+            ),
+        ];
+        for (name, args, message) in cases {
+            assert_eq!(call(name, args), Object::Error(message.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_assert() {
+        assert_eq!(call("assert", vec![Object::Integer(1)]), Object::Null);
+        assert_eq!(
+            call("assert", vec![Object::Integer(0)]),
+            Object::Error("assertion failed".to_string())
+        );
+        assert_eq!(
+            call("assert", vec![Object::Null, string("no input")]),
+            Object::Error("assertion failed: no input".to_string())
+        );
+        assert_eq!(
+            call("assert_eq", vec![string("a"), string("a")]),
+            Object::Null
+        );
+        assert_eq!(
+            call("assert_eq", vec![Object::Integer(7), Object::Integer(5)]),
+            Object::Error("assertion failed: expected 5, got 7".to_string())
+        );
+        assert_eq!(
+            call("assert_eq", vec![string("5"), Object::Integer(5)]),
+            Object::Error("assertion failed: expected 5, got \"5\"".to_string())
+        );
+    }
+
+    #[test]
+    fn test_typeof_and_repr() {
+        let file = call("fopen", vec![string(&temp_path("typeof.txt")), string("w")]);
+        let values = [
+            (Object::Integer(5), "integer", "5"),
+            (Object::Float(2.0), "float", "2.0"),
+            (string("5"), "string", "\"5\""),
+            (string("line\n\t\"q\""), "string", "\"line\\n\\t\\\"q\\\"\""),
+            (Object::Boolean(true), "boolean", "true"),
+            (Object::Null, "null", "null"),
+            (file, "file", "file"),
+            (
+                Object::Array(Rc::new(RefCell::new(vec![Object::Integer(1), string("a")]))),
+                "array",
+                "[1, \"a\"]",
+            ),
+        ];
+        for (value, type_name, repr) in values {
+            assert_eq!(call("typeof", vec![value.clone()]), string(type_name));
+            assert_eq!(call("repr", vec![value]), string(repr));
+        }
+    }
+
+    #[test]
+    fn test_strlen() {
+        assert_eq!(call("strlen", vec![string("hello")]), Object::Integer(5));
+        assert_eq!(call("strlen", vec![string("")]), Object::Integer(0));
+        assert_eq!(call("strlen", vec![string("héllo")]), Object::Integer(5));
+        assert!(matches!(
+            call("strlen", vec![Object::Integer(1)]),
+            Object::Error(_)
+        ));
+    }
+
+    #[test]
+    fn test_strcmp() {
+        assert_eq!(
+            call("strcmp", vec![string("abc"), string("abc")]),
+            Object::Integer(0)
+        );
+        assert_eq!(
+            call("strcmp", vec![string("abc"), string("abd")]),
+            Object::Integer(-1)
+        );
+        assert_eq!(
+            call("strcmp", vec![string("b"), string("abc")]),
+            Object::Integer(1)
+        );
+        assert!(matches!(
+            call("strcmp", vec![string("a")]),
+            Object::Error(_)
+        ));
+    }
+
+    #[test]
+    fn test_strcat() {
+        assert_eq!(
+            call("strcat", vec![string("foo"), string("bar")]),
+            string("foobar")
+        );
+        assert!(matches!(
+            call("strcat", vec![string("foo"), Object::Integer(1)]),
+            Object::Error(_)
+        ));
+    }
+
+    #[test]
+    fn test_substr() {
+        let substr = |s: &str, start, len| {
+            call(
+                "substr",
+                vec![string(s), Object::Integer(start), Object::Integer(len)],
+            )
+        };
+        assert_eq!(substr("hello world", 6, 5), string("world"));
+        assert_eq!(substr("hello", 1, 100), string("ello"));
+        assert_eq!(substr("hello", 9, 2), string(""));
+        assert_eq!(substr("héllo", 1, 2), string("él"));
+        assert_eq!(
+            substr("hello", -1, 2),
+            Object::Error("substr: start must be non-negative, got -1".to_string())
+        );
+        assert_eq!(
+            substr("hello", 0, -2),
+            Object::Error("substr: length must be non-negative, got -2".to_string())
+        );
+    }
+
+    #[test]
+    fn test_char_at() {
+        assert_eq!(
+            call("char_at", vec![string("héllo"), Object::Integer(1)]),
+            string("é")
+        );
+        assert_eq!(
+            call("char_at", vec![string("abc"), Object::Integer(3)]),
+            Object::Error("char_at: index 3 out of bounds for length 3".to_string())
+        );
+        assert_eq!(
+            call("char_at", vec![string("abc"), Object::Integer(-1)]),
+            Object::Error("char_at: index -1 out of bounds for length 3".to_string())
+        );
+    }
+
+    fn strings(values: &[&str]) -> Vec<Object> {
+        values.iter().map(|s| string(s)).collect()
+    }
+
+    #[test]
+    fn test_index_of_contains() {
+        assert_eq!(
+            call("index_of", strings(&["hello", "llo"])),
+            Object::Integer(2)
+        );
+        assert_eq!(
+            call("index_of", strings(&["héllo", "l"])),
+            Object::Integer(2)
+        );
+        assert_eq!(
+            call("index_of", strings(&["hello", "z"])),
+            Object::Integer(-1)
+        );
+        assert_eq!(call("index_of", strings(&["abc", ""])), Object::Integer(0));
+        assert_eq!(
+            call("contains", strings(&["hello", "ell"])),
+            Object::Boolean(true)
+        );
+        assert_eq!(
+            call("contains", strings(&["hello", "L"])),
+            Object::Boolean(false)
+        );
+        assert_eq!(
+            call("contains", vec![string("a1"), Object::Integer(1)]),
+            Object::Error("contains: argument 2 must be a string, got 1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_replace_split() {
+        assert_eq!(
+            call("replace", strings(&["a-b-c", "-", "+"])),
+            string("a+b+c")
+        );
+        assert_eq!(
+            call("replace", strings(&["abc", "", "x"])),
+            Object::Error("replace: from must not be empty".to_string())
+        );
+        assert_eq!(
+            call("split", strings(&["a,b,,c", ","])),
+            Object::Array(Rc::new(RefCell::new(strings(&["a", "b", "", "c"]))))
+        );
+        assert_eq!(
+            call("split", strings(&["hé!", ""])),
+            Object::Array(Rc::new(RefCell::new(strings(&["h", "é", "!"]))))
+        );
+        assert_eq!(
+            call("split", strings(&["abc"])),
+            Object::Error("split: expected 2 arguments, got 1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_trim_and_case() {
+        assert_eq!(call("trim", strings(&["  hi there\n"])), string("hi there"));
+        assert_eq!(call("toupper", strings(&["straße"])), string("STRASSE"));
+        assert_eq!(call("tolower", strings(&["ÀB"])), string("àb"));
+        assert!(matches!(
+            call("toupper", vec![Object::Integer(1)]),
+            Object::Error(_)
+        ));
+    }
+
+    #[test]
+    fn test_char_classes() {
+        assert_eq!(call("isdigit", vec![string("7")]), Object::Boolean(true));
+        assert_eq!(
+            call("isdigit", vec![Object::Integer(55)]),
+            Object::Boolean(true)
+        );
+        assert_eq!(call("isdigit", vec![string("٣")]), Object::Boolean(false));
+        assert_eq!(call("isalpha", vec![string("é")]), Object::Boolean(true));
+        assert_eq!(
+            call("isalpha", vec![Object::Integer(49)]),
+            Object::Boolean(false)
+        );
+        assert_eq!(call("isspace", vec![string("\t")]), Object::Boolean(true));
+        assert_eq!(
+            call("isspace", vec![Object::Integer(32)]),
+            Object::Boolean(true)
+        );
+        assert_eq!(call("isalnum", vec![string("_")]), Object::Boolean(false));
+        assert_eq!(call("isalnum", vec![string("9")]), Object::Boolean(true));
+
+        assert_eq!(
+            call("isdigit", vec![string("12")]),
+            Object::Error("isdigit: expected a single character, got \"12\"".to_string())
+        );
+        assert_eq!(
+            call("isalpha", vec![string("")]),
+            Object::Error("isalpha: expected a single character, got \"\"".to_string())
+        );
+        assert_eq!(
+            call("isspace", vec![Object::Integer(-2)]),
+            Object::Error("isspace: invalid code point -2".to_string())
+        );
+
+        // C's EOF is in no class
+        for name in ["isdigit", "isalpha", "isspace", "isalnum"] {
+            assert_eq!(
+                call(name, vec![Object::Integer(-1)]),
+                Object::Boolean(false)
+            );
+        }
+    }
+
+    #[test]
+    fn test_ord_chr() {
+        assert_eq!(call("ord", vec![string("A")]), Object::Integer(65));
+        assert_eq!(call("ord", vec![string("€")]), Object::Integer(0x20AC));
+        assert_eq!(call("chr", vec![Object::Integer(97)]), string("a"));
+        assert_eq!(call("chr", vec![Object::Integer(0x1F600)]), string("😀"));
+        assert!(matches!(call("ord", vec![string("ab")]), Object::Error(_)));
+        assert!(matches!(
+            call("ord", vec![Object::Integer(65)]),
+            Object::Error(_)
+        ));
+        for code in [-1, 0xD800, 0x110000] {
+            assert_eq!(
+                call("chr", vec![Object::Integer(code)]),
+                Object::Error(format!("chr: invalid code point {}", code))
+            );
+        }
+    }
+
+    #[test]
+    fn test_atoi() {
+        assert_eq!(call("atoi", vec![string("42\n")]), Object::Integer(42));
+        assert_eq!(call("atoi", vec![string(" -17abc")]), Object::Integer(-17));
+        assert_eq!(call("atoi", vec![string("abc")]), Object::Integer(0));
+        assert_eq!(call("atoi", vec![string("+5")]), Object::Integer(5));
+        assert_eq!(
+            call("atoi", vec![string("99999999999999999999")]),
+            Object::Integer(i64::MAX)
+        );
+    }
+
+    #[test]
+    fn test_atoi_checked() {
+        assert_eq!(
+            call("atoi_checked", vec![string(" 0\n")]),
+            Object::Integer(0)
+        );
+        assert_eq!(call("atoi_checked", vec![string("abc")]), Object::Null);
+        assert_eq!(call("atoi_checked", vec![string("12abc")]), Object::Null);
+    }
+
+    #[test]
+    fn test_itoa() {
+        assert_eq!(call("itoa", vec![Object::Integer(-42)]), string("-42"));
+        assert!(matches!(call("itoa", vec![string("1")]), Object::Error(_)));
+    }
+
+    #[test]
+    fn test_read_line() {
+        let mut reader = std::io::Cursor::new("first\r\nsecond\nrest");
+        assert_eq!(read_line(&mut reader).unwrap(), Some("first".to_string()));
+        assert_eq!(read_line(&mut reader).unwrap(), Some("second".to_string()));
+
+        // Nothing past the line is consumed, so a following getchar sees it
+        let mut c = [0; 1];
+        reader.read_exact(&mut c).unwrap();
+        assert_eq!(&c, b"r");
+
+        assert_eq!(read_line(&mut reader).unwrap(), Some("est".to_string()));
+        assert_eq!(read_line(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn test_read_int() {
+        let mut reader = std::io::Cursor::new("  42\n\t-7 +3x 99999999999999999999");
+        assert_eq!(read_int(&mut reader).unwrap(), Some(42));
+        assert_eq!(read_int(&mut reader).unwrap(), Some(-7));
+        assert_eq!(read_int(&mut reader).unwrap(), Some(3));
+
+        // The character after the digits is left for getchar
+        let mut c = [0; 1];
+        reader.read_exact(&mut c).unwrap();
+        assert_eq!(&c, b"x");
+
+        assert_eq!(read_int(&mut reader).unwrap(), Some(i64::MAX));
+        assert_eq!(read_int(&mut reader).unwrap(), None);
+        assert_eq!(read_int(&mut std::io::Cursor::new("abc")).unwrap(), None);
+
+        // A sign with no digit after it stays for the next read
+        let mut reader = std::io::Cursor::new("- 5 +x");
+        assert_eq!(read_int(&mut reader).unwrap(), None);
+        reader.read_exact(&mut c).unwrap();
+        assert_eq!(&c, b"-");
+        assert_eq!(read_int(&mut reader).unwrap(), Some(5));
+        assert_eq!(read_int(&mut reader).unwrap(), None);
+        assert_eq!(read_word(&mut reader).unwrap(), Some("+x".to_string()));
+    }
+
+    #[test]
+    fn test_scan() {
+        let mut reader = std::io::Cursor::new("x=12\n  hello world\n");
+        assert_eq!(scan(&mut reader, "x=%d"), Ok(Object::Integer(12)));
+        assert_eq!(scan(&mut reader, "%s"), Ok(string("hello")));
+        assert_eq!(scan(&mut reader, " %s"), Ok(string("world")));
+        assert_eq!(scan(&mut reader, "%s"), Ok(Object::Null));
+
+        assert_eq!(
+            scan(&mut std::io::Cursor::new("y=1"), "x=%d"),
+            Ok(Object::Null)
+        );
+        assert_eq!(
+            scan(&mut std::io::Cursor::new("50%"), "%d%%"),
+            Ok(Object::Integer(50))
+        );
+        assert_eq!(
+            scan(&mut std::io::Cursor::new("1 2"), "%d %d"),
+            Err("scanf supports exactly one %d or %s conversion, got 2".to_string())
+        );
+        assert_eq!(
+            scan(&mut std::io::Cursor::new("1"), "%x"),
+            Err("scanf: unsupported conversion '%x'".to_string())
+        );
+
+        // Characters outside ASCII match only themselves, not their low byte
+        assert_eq!(
+            scan(&mut std::io::Cursor::new("€5"), "€%d"),
+            Ok(Object::Integer(5))
+        );
+        assert_eq!(
+            scan(&mut std::io::Cursor::new(",5"), "\u{12C}%d"),
+            Ok(Object::Null)
+        );
+    }
+
+    #[test]
+    fn test_line_input_arity() {
+        assert!(matches!(
+            call("gets", vec![string("extra")]),
+            Object::Error(_)
+        ));
+        assert!(matches!(
+            call("input", vec![string("a"), string("b")]),
+            Object::Error(_)
+        ));
+    }
+
+    fn ints(values: &[i64]) -> Vec<Object> {
+        values.iter().map(|n| Object::Integer(*n)).collect()
+    }
+
+    #[test]
+    fn test_abs_min_max() {
+        assert_eq!(call("abs", ints(&[-5])), Object::Integer(5));
+        assert_eq!(call("abs", ints(&[5])), Object::Integer(5));
+        assert_eq!(
+            call("abs", ints(&[i64::MIN])),
+            Object::Error("abs: -9223372036854775808 has no positive counterpart".to_string())
+        );
+        assert_eq!(call("min", ints(&[3, -2])), Object::Integer(-2));
+        assert_eq!(call("max", ints(&[3, -2])), Object::Integer(3));
+        assert_eq!(
+            call("max", ints(&[1])),
+            Object::Error("max: expected 2 arguments, got 1".to_string())
+        );
+        assert_eq!(
+            call("min", vec![Object::Integer(1), string("2")]),
+            Object::Error("min: argument 2 must be an integer, got \"2\"".to_string())
+        );
+    }
+
+    #[test]
+    fn test_pow_sqrt() {
+        assert_eq!(call("pow", ints(&[2, 10])), Object::Integer(1024));
+        assert_eq!(call("pow", ints(&[-3, 3])), Object::Integer(-27));
+        assert_eq!(call("pow", ints(&[7, 0])), Object::Integer(1));
+        assert_eq!(
+            call("pow", ints(&[2, -1])),
+            Object::Error("pow: exponent must be non-negative, got -1".to_string())
+        );
+        assert_eq!(
+            call("pow", ints(&[2, 63])),
+            Object::Error("pow: 2 to the power 63 overflows".to_string())
+        );
+        assert_eq!(call("sqrt", ints(&[17])), Object::Integer(4));
+        assert_eq!(call("sqrt", ints(&[16])), Object::Integer(4));
+        assert_eq!(call("sqrt", ints(&[0])), Object::Integer(0));
+        assert_eq!(
+            call("sqrt", ints(&[-4])),
+            Object::Error("sqrt: negative number -4".to_string())
+        );
+        assert_eq!(
+            call("sqrt", vec![]),
+            Object::Error("sqrt: expected 1 argument, got 0".to_string())
+        );
+    }
+
+    // Calls builtins from one registration, so stateful ones share their state.
+    // Any other global, such as a stream or errno constant, is returned as is.
+    fn stdlib_caller() -> impl Fn(&str, Vec<Object>) -> Object {
+        let env = stdlib_env(stdout_output(), StdlibConfig::default());
+        move |name, args| match env.borrow().get(name) {
+            Some(Object::Builtin(f)) => f.call(args),
+            Some(value) => value,
+            None => panic!("{} is not defined", name),
+        }
+    }
+
+    #[test]
+    fn test_rand_is_deterministic() {
+        let sequence = |seed: Option<i64>| {
+            let call = stdlib_caller();
+            if let Some(seed) = seed {
+                call("srand", ints(&[seed]));
+            }
+            (0..5).map(|_| call("rand", vec![])).collect::<Vec<_>>()
+        };
+
+        assert_eq!(sequence(Some(42)), sequence(Some(42)));
+        assert_ne!(sequence(Some(42)), sequence(Some(43)));
+        // Without srand the sequence is the one seed 1 gives
+        assert_eq!(sequence(None), sequence(Some(1)));
+        for value in sequence(Some(7)) {
+            assert!(matches!(value, Object::Integer(0..=2147483647)));
+        }
+    }
+
+    #[test]
+    fn test_rand_range() {
+        let call = stdlib_caller();
+        call("srand", ints(&[3]));
+        let mut seen = [false; 6];
+        for _ in 0..200 {
+            match call("rand_range", ints(&[1, 6])) {
+                Object::Integer(n @ 1..=6) => seen[n as usize - 1] = true,
+                other => panic!("out of range: {:?}", other),
+            }
+        }
+        assert!(seen.iter().all(|s| *s));
+
+        assert_eq!(call("rand_range", ints(&[5, 5])), Object::Integer(5));
+        assert!(matches!(
+            call("rand_range", ints(&[i64::MIN, i64::MAX])),
+            Object::Integer(_)
+        ));
+        assert_eq!(
+            call("rand_range", ints(&[2, 1])),
+            Object::Error("rand_range: lo (2) must not be greater than hi (1)".to_string())
+        );
+    }
+
+    #[test]
+    fn test_clock_and_sleep() {
+        let call = stdlib_caller();
+        let ms = |value| match value {
+            Object::Integer(n) => n,
+            other => panic!("expected milliseconds, got {:?}", other),
+        };
+
+        let before = ms(call("clock_ms", vec![]));
+        assert_eq!(call("sleep_ms", ints(&[50])), Object::Null);
+        let after = ms(call("clock_ms", vec![]));
+        assert!(after - before >= 50, "{} -> {}", before, after);
+        assert!(ms(call("clock_ms", vec![])) >= after);
+
+        assert_eq!(
+            call("sleep_ms", ints(&[-1])),
+            Object::Error("sleep_ms: duration must be non-negative, got -1".to_string())
+        );
+        assert!(matches!(
+            call("sleep_ms", vec![string("5")]),
+            Object::Error(_)
+        ));
+        // Some time after 2020
+        assert!(matches!(call("time", vec![]), Object::Integer(n) if n > 1_577_836_800));
+    }
+
+    #[test]
+    fn test_puts_and_print() {
+        let buffer = Rc::new(RefCell::new(Vec::new()));
+        let env = stdlib_env(buffer.clone(), StdlibConfig::default());
+        let call = |name: &str, args| match env.borrow().get(name) {
+            Some(Object::Builtin(f)) => f.call(args),
+            _ => panic!("{} is not a builtin", name),
+        };
+
+        call("puts", vec![string("x ="), Object::Integer(3)]);
+        call("puts", vec![]);
+        call("print", vec![string("a"), Object::Float(1.5), Object::Null]);
+        call("print", vec![]);
+        call("print", vec![string("!")]);
+        call("puts", vec![string("one")]);
+        assert_eq!(
+            String::from_utf8(buffer.borrow().clone()).unwrap(),
+            "x = 3\n\na 1.5 null!one\n"
+        );
+    }
+
+    #[test]
+    fn test_sandbox_disables_dangerous_builtins() {
+        let buffer = Rc::new(RefCell::new(Vec::new()));
+        let env = stdlib_env(buffer.clone(), StdlibConfig::sandbox());
+        let call = |name: &str, args| match env.borrow().get(name) {
+            Some(Object::Builtin(f)) => f.call(args),
+            _ => panic!("{} is not a builtin", name),
+        };
+
+        let path = temp_path("sandbox.txt");
+        assert_eq!(
+            call("fopen", vec![string(&path), string("w")]),
+            Object::Error("fopen is disabled in sandbox mode".to_string())
+        );
+        assert!(!std::path::Path::new(&path).exists());
+        assert_eq!(
+            call("tmpfile", vec![]),
+            Object::Error("tmpfile is disabled in sandbox mode".to_string())
+        );
+        assert_eq!(
+            call("system", vec![string("true")]),
+            Object::Error("system is disabled in sandbox mode".to_string())
+        );
+
+        assert_eq!(
+            call("printf", vec![string("%d\n"), Object::Integer(7)]),
+            Object::Null
+        );
+        assert_eq!(*buffer.borrow(), b"7\n");
+    }
+
+    #[test]
+    fn test_sandbox_without_stdin_closes_stdin() {
+        let config = StdlibConfig {
+            allow_stdin: false,
+            ..StdlibConfig::default()
+        };
+        let env = stdlib_env(stdout_output(), config);
+        let stdin = env.borrow().get("stdin").unwrap();
+        let fgets = match env.borrow().get("fgets") {
+            Some(Object::Builtin(f)) => f,
+            _ => panic!("fgets is not a builtin"),
+        };
+        assert_eq!(
+            fgets.call(vec![stdin]),
+            Object::Error("fgets: operation on closed file".to_string())
+        );
+        let gets = match env.borrow().get("gets") {
+            Some(Object::Builtin(f)) => f,
+            _ => panic!("gets is not a builtin"),
+        };
+        assert_eq!(
+            gets.call(vec![]),
+            Object::Error("gets is disabled because stdin is not available to the program".into())
+        );
+    }
+
+    // Keeps written bytes pending until flushed, to check when flushes happen
+    #[derive(Default)]
+    struct FlushTracker {
+        pending: Vec<u8>,
+        flushed: Vec<u8>,
+    }
+
+    impl Write for FlushTracker {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.pending.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.flushed.append(&mut self.pending);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_output_is_flushed_before_reading_stdin() {
+        let tracker = Rc::new(RefCell::new(FlushTracker::default()));
+        let env = stdlib_env(tracker.clone(), StdlibConfig::default());
+        if let Some(Object::Builtin(printf)) = env.borrow().get("printf") {
+            printf.call(vec![string("enter a number: ")]);
+        }
+        assert!(tracker.borrow().flushed.is_empty());
+
+        let output: Output = tracker.clone();
+        let seen = read_stdin(&output, |_| tracker.borrow().flushed.clone());
+        assert_eq!(seen.ok(), Some(b"enter a number: ".to_vec()));
+    }
+
+    #[test]
+    fn test_fflush() {
+        let tracker = Rc::new(RefCell::new(FlushTracker::default()));
+        let env = stdlib_env(tracker.clone(), StdlibConfig::default());
+        let call = |name: &str, args| match env.borrow().get(name) {
+            Some(Object::Builtin(f)) => f.call(args),
+            _ => panic!("{} is not a builtin", name),
+        };
+        let stdout = env.borrow().get("stdout").unwrap();
+
+        call("printf", vec![string("a")]);
+        assert_eq!(call("fflush", vec![]), Object::Integer(0));
+        assert_eq!(tracker.borrow().flushed, b"a");
+        call("fputs", vec![string("b"), stdout.clone()]);
+        assert_eq!(call("fflush", vec![stdout]), Object::Integer(0));
+        assert_eq!(tracker.borrow().flushed, b"ab");
+
+        let path = temp_path("fflush.txt");
+        let file = call("fopen", vec![string(&path), string("w")]);
+        call("fputs", vec![string("x"), file.clone()]);
+        assert_eq!(call("fflush", vec![file.clone()]), Object::Integer(0));
+        call("fclose", vec![file.clone()]);
+        assert_eq!(
+            call("fflush", vec![file]),
+            Object::Error("fflush: operation on closed file".to_string())
+        );
+        assert!(matches!(
+            call("fflush", vec![Object::Integer(1)]),
+            Object::Error(_)
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_getenv() {
+        assert!(matches!(
+            call("getenv", vec![string("PATH")]),
+            Object::String(_)
+        ));
+        assert_eq!(
+            call("getenv", vec![string("TINYC_SURELY_UNSET_VARIABLE")]),
+            Object::Null
+        );
+        assert!(matches!(
+            call("getenv", vec![Object::Integer(1)]),
+            Object::Error(_)
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_system() {
+        assert_eq!(call("system", vec![string("exit 3")]), Object::Integer(3));
+        assert_eq!(call("system", vec![string("true")]), Object::Integer(0));
+        assert_eq!(
+            call("system_output", vec![string("echo hi; echo err >&2")]),
+            string("hi\n")
+        );
+    }
+
+    fn format(fmt: &str, args: Vec<Object>) -> Result<String, String> {
+        let mut all = vec![string(fmt)];
+        all.extend(args);
+        format_output(&all)
+    }
+
+    #[test]
+    fn test_format_basic() {
+        assert_eq!(
+            format("%s is %d%%", vec![string("x"), Object::Integer(5)]),
+            Ok("x is 5%".to_string())
+        );
+        assert_eq!(
+            format("%c%c", vec![string("hi"), Object::Integer(65)]),
+            Ok("hA".to_string())
+        );
+        assert_eq!(
+            format(
+                "%x %X %o",
+                vec![
+                    Object::Integer(255),
+                    Object::Integer(255),
+                    Object::Integer(8)
+                ]
+            ),
+            Ok("ff FF 10".to_string())
+        );
+    }
+
+    #[test]
+    fn test_format_width_and_flags() {
+        assert_eq!(
+            format("[%5d]", vec![Object::Integer(42)]),
+            Ok("[   42]".to_string())
+        );
+        assert_eq!(
+            format("[%-6s]", vec![string("ab")]),
+            Ok("[ab    ]".to_string())
+        );
+        assert_eq!(
+            format("[%05d]", vec![Object::Integer(-42)]),
+            Ok("[-0042]".to_string())
+        );
+        assert_eq!(
+            format("[%04x]", vec![Object::Integer(255)]),
+            Ok("[00ff]".to_string())
+        );
+        assert_eq!(
+            format("[%1s]", vec![string("long")]),
+            Ok("[long]".to_string())
+        );
+    }
+
+    #[test]
+    fn test_format_floats() {
+        assert_eq!(
+            format("%f", vec![Object::Float(1.23456)]),
+            Ok("1.234560".to_string())
+        );
+        assert_eq!(
+            format(
+                "%.2f|%8.3f|%-6.1f|%.0f",
+                vec![
+                    Object::Float(2.005),
+                    Object::Float(-1.5),
+                    Object::Float(0.25),
+                    Object::Float(2.5)
+                ]
+            ),
+            Ok("2.00|  -1.500|0.2   |2".to_string())
+        );
+        assert_eq!(
+            format(
+                "%07.2f %.1f",
+                vec![Object::Float(-1.23456), Object::Integer(4)]
+            ),
+            Ok("-001.23 4.0".to_string())
+        );
+        assert_eq!(
+            format("%s %.3s", vec![Object::Float(2.0), string("abcdef")]),
+            Ok("2.0 abc".to_string())
+        );
+        assert_eq!(
+            format("%f", vec![string("x")]),
+            Err("%f at position 0 expects a number, got x".to_string())
+        );
+        assert_eq!(
+            format("%d", vec![Object::Float(1.5)]),
+            Err("%d at position 0 expects an integer, got 1.5".to_string())
+        );
+    }
+
+    #[test]
+    fn test_format_errors() {
+        assert_eq!(
+            format("abc %q", vec![Object::Integer(1)]),
+            Err("unknown conversion '%q' at position 4".to_string())
+        );
+        assert_eq!(
+            format("%d", vec![string("5")]),
+            Err("%d at position 0 expects an integer, got 5".to_string())
+        );
+        assert_eq!(
+            format("%s %s", vec![string("a")]),
+            Err("missing argument for %s at position 3".to_string())
+        );
+        assert_eq!(
+            format("100%", vec![]),
+            Err("incomplete format specifier at position 3".to_string())
+        );
+    }
+
+    fn temp_path(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("tinyc-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join(name).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_fclose() {
+        let path = temp_path("fclose.txt");
+        let file = call("fopen", vec![string(&path), string("w")]);
+        assert_eq!(
+            call("fputs", vec![string("data"), file.clone()]),
+            Object::Null
+        );
+        assert_eq!(call("fclose", vec![file.clone()]), Object::Integer(0));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "data");
+
+        let closed = |name: &str| Object::Error(format!("{}: operation on closed file", name));
+        assert_eq!(
+            call("fputs", vec![string("more"), file.clone()]),
+            closed("fputs")
+        );
+        assert_eq!(call("fgetc", vec![file.clone()]), closed("fgetc"));
+        assert_eq!(call("ftell", vec![file.clone()]), closed("ftell"));
+        assert_eq!(
+            call(
+                "fseek",
+                vec![file.clone(), Object::Integer(0), Object::Integer(0)]
+            ),
+            closed("fseek")
+        );
+
+        // Closing twice is harmless
+        assert_eq!(call("fclose", vec![file]), Object::Integer(0));
+    }
+
+    #[test]
+    fn test_whole_file_helpers() {
+        let path = temp_path("whole.txt");
+        assert_eq!(
+            call("write_file", vec![string(&path), string("one\n")]),
+            Object::Null
+        );
+        assert_eq!(
+            call("append_file", vec![string(&path), string("two\n")]),
+            Object::Null
+        );
+        assert_eq!(call("read_file", vec![string(&path)]), string("one\ntwo\n"));
+
+        // write_file replaces what was there
+        call("write_file", vec![string(&path), string("three")]);
+        assert_eq!(call("read_file", vec![string(&path)]), string("three"));
+
+        std::fs::write(&path, b"ok\xFF").unwrap();
+        assert_eq!(call("read_file", vec![string(&path)]), string("ok\u{FFFD}"));
+
+        assert!(matches!(
+            call("read_file", vec![string(&temp_path("missing.txt"))]),
+            Object::Error(msg) if msg.starts_with("read_file: ")
+        ));
+        assert!(matches!(
+            call("write_file", vec![string(&path), Object::Integer(1)]),
+            Object::Error(_)
+        ));
+    }
+
+    #[test]
+    fn test_buffered_reads_track_position() {
+        let path = temp_path("buffered.txt");
+        std::fs::write(&path, "line1\nline2\nabc").unwrap();
+        let file = call("fopen", vec![string(&path), string("r")]);
+        let tell = || call("ftell", vec![file.clone()]);
+        let seek = |offset, whence| {
+            call(
+                "fseek",
+                vec![
+                    file.clone(),
+                    Object::Integer(offset),
+                    Object::Integer(whence),
+                ],
+            )
+        };
+
+        assert_eq!(call("fgets", vec![file.clone()]), string("line1\n"));
+        assert_eq!(tell(), Object::Integer(6));
+        assert_eq!(call("fgetc", vec![file.clone()]), string("l"));
+        assert_eq!(tell(), Object::Integer(7));
+
+        // Relative seeks start from the script's position, not the read-ahead
+        seek(-1, 1);
+        assert_eq!(tell(), Object::Integer(6));
+        assert_eq!(call("fgets", vec![file.clone()]), string("line2\n"));
+        seek(1, 1);
+        assert_eq!(call("fgets", vec![file.clone()]), string("bc"));
+        assert_eq!(call("feof", vec![file.clone()]), Object::Boolean(true));
+        assert_eq!(tell(), Object::Integer(15));
+
+        call("rewind", vec![file.clone()]);
+        assert_eq!(call("getc", vec![file.clone()]), string("l"));
+        seek(2, 1);
+        assert_eq!(call("fgets", vec![file.clone()]), string("e1\n"));
+        seek(-3, 2);
+        assert_eq!(call("fgets", vec![file.clone()]), string("abc"));
+        assert_eq!(call("fgets", vec![file.clone()]), Object::Null);
+        call("fclose", vec![file]);
+    }
+
+    #[test]
+    fn test_write_after_buffered_read() {
+        let path = temp_path("read-write.txt");
+        std::fs::write(&path, "hello world").unwrap();
+        let file = call("fopen", vec![string(&path), string("r+")]);
+
+        // The write lands right after what the script read, not after the read-ahead
+        assert_eq!(call("fgetc", vec![file.clone()]), string("h"));
+        call("fputs", vec![string("E"), file.clone()]);
+        assert_eq!(call("ftell", vec![file.clone()]), Object::Integer(2));
+        assert_eq!(call("fgetc", vec![file.clone()]), string("l"));
+        call("fclose", vec![file]);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hEllo world");
+    }
+
+    #[test]
+    fn test_fgets_across_buffer_refills() {
+        let path = temp_path("many-lines.txt");
+        let content: String = (0..5000).map(|i| format!("line {}\n", i)).collect();
+        std::fs::write(&path, &content).unwrap();
+
+        let file = call("fopen", vec![string(&path), string("r")]);
+        let mut lines = vec![];
+        while let Object::String(line) = call("fgets", vec![file.clone()]) {
+            lines.push(line);
+        }
+        assert_eq!(lines.concat(), content);
+        assert_eq!(
+            call("ftell", vec![file.clone()]),
+            Object::Integer(content.len() as i64)
+        );
+        call("fclose", vec![file]);
+    }
+
+    // Run with `cargo test --release -- --ignored --nocapture` to compare fgets
+    // with reading a byte per syscall, as it did before reads were buffered
+    #[test]
+    #[ignore]
+    fn bench_fgets_large_file() {
+        let path = temp_path("large.txt");
+        let content = "a line of some log file, about sixty bytes in total\n".repeat(200_000);
+        std::fs::write(&path, &content).unwrap();
+
+        let call = stdlib_caller();
+        let start = std::time::Instant::now();
+        let file = call("fopen", vec![string(&path), string("r")]);
+        let mut buffered = 0;
+        while let Object::String(line) = call("fgets", vec![file.clone()]) {
+            buffered += line.len();
+        }
+        let buffered_time = start.elapsed();
+
+        let start = std::time::Instant::now();
+        let mut raw = std::fs::File::open(&path).unwrap();
+        let mut unbuffered = 0;
+        let mut byte = [0; 1];
+        while raw.read(&mut byte).unwrap() == 1 {
+            unbuffered += 1;
+        }
+        let unbuffered_time = start.elapsed();
+
+        assert_eq!(buffered, unbuffered);
+        println!(
+            "{} bytes: fgets took {:?}, byte-at-a-time reads took {:?}",
+            buffered, buffered_time, unbuffered_time
+        );
+    }
+
+    #[test]
+    fn test_fopen_append() {
+        let path = temp_path("append.txt");
+        std::fs::write(&path, "first\n").unwrap();
+
+        let file = call("fopen", vec![string(&path), string("a")]);
+        call("fputs", vec![string("second\n"), file.clone()]);
+        call("fclose", vec![file]);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "first\nsecond\n");
+
+        // "a+" can read from the start but still writes at the end
+        let file = call("fopen", vec![string(&path), string("a+b")]);
+        assert_eq!(call("fgets", vec![file.clone()]), string("first\n"));
+        call("fputs", vec![string("third\n"), file.clone()]);
+        call("fclose", vec![file]);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "first\nsecond\nthird\n"
+        );
+    }
+
+    #[test]
+    fn test_fopen_read_write_modes() {
+        let path = temp_path("modes.txt");
+        std::fs::write(&path, "hello").unwrap();
+
+        let file = call("fopen", vec![string(&path), string("r+")]);
+        call("fputs", vec![string("J"), file.clone()]);
+        call("fclose", vec![file]);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "Jello");
+
+        let file = call("fopen", vec![string(&path), string("w+")]);
+        call("fputs", vec![string("new"), file.clone()]);
+        call("rewind", vec![file.clone()]);
+        assert_eq!(call("fgets", vec![file.clone()]), string("new"));
+        call("fclose", vec![file]);
+    }
+
+    #[test]
+    fn test_fopen_failure_sets_errno() {
+        let call = stdlib_caller();
+        assert_eq!(call("errno", vec![]), Object::Integer(0));
+        let missing = temp_path("no-such-dir/file.txt");
+        assert_eq!(
+            call("fopen", vec![string(&missing), string("r")]),
+            Object::Null
+        );
+        assert_eq!(call("errno", vec![]), call("ENOENT", vec![]));
+        let dir = std::env::temp_dir().to_string_lossy().into_owned();
+        assert_eq!(call("fopen", vec![string(&dir), string("w")]), Object::Null);
+        assert_eq!(call("errno", vec![]), Object::Integer(21));
+        // Success leaves errno as it was, as in C
+        let file = call("fopen", vec![string(&temp_path("errno.txt")), string("w")]);
+        assert!(matches!(file, Object::File(_)));
+        assert_eq!(call("errno", vec![]), Object::Integer(21));
+    }
+
+    #[test]
+    fn test_remove_and_rename_status() {
+        let call = stdlib_caller();
+        let (from, to) = (temp_path("rename-from.txt"), temp_path("rename-to.txt"));
+        std::fs::write(&from, "data").unwrap();
+
+        assert_eq!(
+            call("rename", vec![string(&from), string(&to)]),
+            Object::Integer(0)
+        );
+        assert_eq!(std::fs::read_to_string(&to).unwrap(), "data");
+        assert_eq!(
+            call("rename", vec![string(&from), string(&to)]),
+            Object::Integer(-1)
+        );
+        assert_eq!(call("errno", vec![]), Object::Integer(2));
+
+        assert_eq!(call("remove", vec![string(&to)]), Object::Integer(0));
+        assert_eq!(call("remove", vec![string(&to)]), Object::Integer(-1));
+        assert_eq!(call("errno", vec![]), Object::Integer(2));
+        // Bad arguments are still errors rather than a status
+        assert!(matches!(
+            call("remove", vec![Object::Integer(1)]),
+            Object::Error(_)
+        ));
+    }
+
+    #[test]
+    fn test_file_queries() {
+        let dir = std::env::temp_dir().join(format!("tinyc-queries-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("five.txt");
+        std::fs::write(&file, "12345").unwrap();
+        let missing = dir.join("missing.txt");
+        let [dir_s, file_s, missing_s] =
+            [&dir, &file, &missing].map(|p| string(&p.to_string_lossy()));
+
+        let call = stdlib_caller();
+        assert_eq!(
+            call("file_exists", vec![file_s.clone()]),
+            Object::Boolean(true)
+        );
+        assert_eq!(
+            call("file_exists", vec![dir_s.clone()]),
+            Object::Boolean(true)
+        );
+        assert_eq!(
+            call("file_exists", vec![missing_s.clone()]),
+            Object::Boolean(false)
+        );
+        assert_eq!(call("is_dir", vec![dir_s.clone()]), Object::Boolean(true));
+        assert_eq!(call("is_dir", vec![file_s.clone()]), Object::Boolean(false));
+        assert_eq!(
+            call("is_dir", vec![missing_s.clone()]),
+            Object::Boolean(false)
+        );
+        assert_eq!(call("file_size", vec![file_s]), Object::Integer(5));
+        assert_eq!(call("errno", vec![]), Object::Integer(0));
+        assert_eq!(call("file_size", vec![missing_s]), Object::Integer(-1));
+        assert_eq!(call("errno", vec![]), Object::Integer(2));
+        for name in ["file_exists", "file_size", "is_dir"] {
+            assert_eq!(
+                call(name, vec![Object::Integer(1)]),
+                Object::Error(format!("{}: argument 1 must be a string, got 1", name))
+            );
+        }
+
+        #[cfg(unix)]
+        {
+            let link = dir.join("dangling");
+            let _ = std::fs::remove_file(&link);
+            std::os::unix::fs::symlink(&missing, &link).unwrap();
+            let link = string(&link.to_string_lossy());
+            assert_eq!(
+                call("file_exists", vec![link.clone()]),
+                Object::Boolean(false)
+            );
+            assert_eq!(call("file_size", vec![link]), Object::Integer(-1));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_directories() {
+        let root = std::env::temp_dir().join(format!("tinyc-dirs-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let path = |rel: &str| string(&root.join(rel).to_string_lossy());
+        let call = stdlib_caller();
+
+        assert_eq!(call("mkdir", vec![path("")]), Object::Integer(0));
+        assert_eq!(call("mkdir", vec![path("")]), Object::Integer(-1));
+        assert_eq!(call("errno", vec![]), Object::Integer(17));
+        assert_eq!(call("mkdir", vec![path("a/b")]), Object::Integer(-1));
+        assert_eq!(call("errno", vec![]), Object::Integer(2));
+        let recursive = vec![path("a/b"), Object::Boolean(true)];
+        assert_eq!(call("mkdir", recursive.clone()), Object::Integer(0));
+        assert_eq!(call("mkdir", recursive), Object::Integer(0));
+        std::fs::write(root.join("z.txt"), "").unwrap();
+        std::fs::write(root.join(".hidden"), "").unwrap();
+
+        let names = |names: &[&str]| {
+            let names = names.iter().map(|name| string(name)).collect();
+            Object::Array(Rc::new(RefCell::new(names)))
+        };
+        assert_eq!(
+            call("listdir", vec![path("")]),
+            names(&[".hidden", "a", "z.txt"])
+        );
+        assert_eq!(call("listdir", vec![path("a/b")]), names(&[]));
+        assert_eq!(call("listdir", vec![path("missing")]), Object::Null);
+        assert_eq!(call("errno", vec![]), Object::Integer(2));
+
+        assert_eq!(call("rmdir", vec![path("a")]), Object::Integer(-1));
+        assert_eq!(call("errno", vec![]), Object::Integer(39));
+        assert_eq!(call("rmdir", vec![path("a/b")]), Object::Integer(0));
+        assert_eq!(call("rmdir", vec![path("a")]), Object::Integer(0));
+        assert_eq!(call("rmdir", vec![path("z.txt")]), Object::Integer(-1));
+        assert_eq!(call("errno", vec![]), Object::Integer(20));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_tmpfile() {
+        let temp_path = |file: &Object| match file {
+            Object::File(handle) => handle.borrow().temp_path().unwrap().to_path_buf(),
+            other => panic!("not a file: {:?}", other),
+        };
+
+        let file = call("tmpfile", vec![]);
+        let path = temp_path(&file);
+        assert!(path.starts_with(std::env::temp_dir()));
+        assert_ne!(path, temp_path(&call("tmpfile", vec![])));
+        call("fputs", vec![string("scratch\n"), file.clone()]);
+        call("rewind", vec![file.clone()]);
+        assert_eq!(call("fgets", vec![file.clone()]), string("scratch\n"));
+        assert!(path.exists());
+        assert_eq!(call("fclose", vec![file.clone()]), Object::Integer(0));
+        assert!(!path.exists());
+
+        // One that is never closed goes when the last reference does
+        let file = call("tmpfile", vec![]);
+        let path = temp_path(&file);
+        assert!(path.exists());
+        drop(file);
+        assert!(!path.exists());
+
+        assert_eq!(
+            call("tempdir", vec![]),
+            string(&std::env::temp_dir().to_string_lossy())
+        );
+    }
+
+    #[test]
+    fn test_fclose_forgets_tmpfile() {
+        let files = TempFiles::default();
+        let env = Rc::new(RefCell::new(Environment::new()));
+        register_stdlib(
+            Rc::clone(&env),
+            stdout_output(),
+            StdlibConfig::default(),
+            Rc::clone(&files),
+        );
+        let call = |name: &str, args| match env.borrow().get(name) {
+            Some(Object::Builtin(f)) => f.call(args),
+            _ => panic!("{} is not a builtin", name),
+        };
+        let temp_path = |file: &Object| match file {
+            Object::File(handle) => handle.borrow().temp_path().unwrap().to_path_buf(),
+            other => panic!("not a file: {:?}", other),
+        };
+
+        // Closing deletes the file right away, however many a loop makes
+        for _ in 0..3 {
+            let file = call("tmpfile", vec![]);
+            let path = temp_path(&file);
+            assert_eq!(*files.borrow(), vec![path.clone()]);
+            assert_eq!(call("fclose", vec![file]), Object::Integer(0));
+            assert!(!path.exists());
+            assert!(files.borrow().is_empty());
+        }
+
+        // Only files still open are left for the sweep at the end
+        let first = call("tmpfile", vec![]);
+        drop(call("tmpfile", vec![]));
+        let second = call("tmpfile", vec![]);
+        let open = vec![temp_path(&first), temp_path(&second)];
+        assert_eq!(*files.borrow(), open);
+        remove_temp_files(&files);
+        assert!(open.iter().all(|path| !path.exists()));
+    }
+
+    #[test]
+    fn test_strerror() {
+        assert_eq!(
+            call("strerror", vec![Object::Integer(2)]),
+            string("No such file or directory")
+        );
+        assert_eq!(
+            call("strerror", vec![Object::Integer(0)]),
+            string("Success")
+        );
+        assert_eq!(
+            call("strerror", vec![Object::Integer(1234)]),
+            string("Unknown error 1234")
+        );
+        let denied = std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+        assert_eq!(strerror(errno_code(&denied)), "Permission denied");
+        let other = std::io::Error::other("disk on fire");
+        assert_eq!(strerror(errno_code(&other)), "Input/output error");
+    }
+
+    #[test]
+    fn test_fopen_invalid_mode() {
+        assert_eq!(
+            call("fopen", vec![string("x.txt"), string("rw")]),
+            Object::Error("fopen: invalid mode \"rw\"".to_string())
+        );
+        for mode in ["wbb", "bw", "b", "r+bb", "rb+b", "bb+"] {
+            assert_eq!(
+                call("fopen", vec![string("x.txt"), string(mode)]),
+                Object::Error(format!("fopen: invalid mode {:?}", mode))
+            );
+        }
+
+        // A single "b" before or after the "+" is ignored
+        let path = temp_path("binary_modes.txt");
+        for mode in ["wb", "ab", "rb", "w+b", "a+b", "r+b", "wb+", "ab+", "rb+"] {
+            let file = call("fopen", vec![string(&path), string(mode)]);
+            assert!(matches!(file, Object::File(_)), "{}: {:?}", mode, file);
+            call("fclose", vec![file]);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
 }
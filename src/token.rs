@@ -1,47 +1,231 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::rc::Rc;
+
+// An interned name. Cloning one, as the parser and interpreter constantly do,
+// only bumps a reference count.
+pub type Symbol = Rc<str>;
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token {
     // Keywords
     Int,
+    Char,
+    Void,
     Return,
     If,
     Else,
     While,
+    Do,
+    Switch,
+    Case,
+    Default,
+    Break,
+    For,
+    Const,
+    True,
+    False,
+
+    // Directives
+    Include, // #include
+    Define,  // #define
+    Undef,   // #undef
 
     // Identifiers and Literals
-    Identifier(String),
+    Identifier(Symbol),
     Integer(i64),
+    Float(f64),
     String(String),
+    Character(char),
 
     // Operators
     Plus,
+    Bang,
     Minus,
+    Increment,
+    Decrement,
     Asterisk,
     Slash,
+    Percent,
     Assign,
+    PlusAssign,
+    MinusAssign,
+    AsteriskAssign,
+    SlashAssign,
+    PercentAssign,
     Equal,
     NotEqual,
     LessThan,
     GreaterThan,
+    LessEqual,
+    GreaterEqual,
+    And,
+    Or,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
+    ShiftLeft,
+    ShiftRight,
 
     // Delimiters
     LParen,
     RParen,
     LBrace,
     RBrace,
+    LBracket,
+    RBracket,
     Semicolon,
+    Colon,
+    Question,
     Comma,
 
     // End of File
-    EOF,
+    Eof,
 
-    // Invalid
+    // Invalid: text no token can be made of, as written, such as `@` or `0x`;
+    // and a literal or comment that is wrong in a way worth explaining, as the
+    // explanation, such as "unterminated string"
     Illegal(String),
+    Malformed(String),
+}
+
+impl Token {
+    // Whether the token is a reserved word, which can't be used as a name
+    pub fn is_keyword(&self) -> bool {
+        matches!(
+            self,
+            Token::Int
+                | Token::Char
+                | Token::Void
+                | Token::Return
+                | Token::If
+                | Token::Else
+                | Token::While
+                | Token::Do
+                | Token::Switch
+                | Token::Case
+                | Token::Default
+                | Token::Break
+                | Token::For
+                | Token::Const
+                | Token::True
+                | Token::False
+        )
+    }
+
+    // The error an Illegal or Malformed token is reported with: the text
+    // quoted as written, or the explanation as is
+    pub fn lex_error(&self) -> Option<String> {
+        match self {
+            Token::Illegal(_) => Some(format!("illegal token {}", self)),
+            Token::Malformed(problem) => Some(problem.clone()),
+            _ => None,
+        }
+    }
+
+    // Source spelling of the token
+    pub fn literal(&self) -> String {
+        match self {
+            Token::Int => "int".to_string(),
+            Token::Char => "char".to_string(),
+            Token::Void => "void".to_string(),
+            Token::Return => "return".to_string(),
+            Token::If => "if".to_string(),
+            Token::Else => "else".to_string(),
+            Token::While => "while".to_string(),
+            Token::Do => "do".to_string(),
+            Token::Switch => "switch".to_string(),
+            Token::Case => "case".to_string(),
+            Token::Default => "default".to_string(),
+            Token::Break => "break".to_string(),
+            Token::For => "for".to_string(),
+            Token::Const => "const".to_string(),
+            Token::True => "true".to_string(),
+            Token::False => "false".to_string(),
+            Token::Include => "#include".to_string(),
+            Token::Define => "#define".to_string(),
+            Token::Undef => "#undef".to_string(),
+            Token::Identifier(name) => name.to_string(),
+            Token::Integer(val) => val.to_string(),
+            Token::Float(val) => format!("{:?}", val),
+            Token::String(val) => format!("{:?}", val),
+            Token::Character(val) => format!("{:?}", val),
+            Token::Plus => "+".to_string(),
+            Token::Bang => "!".to_string(),
+            Token::Minus => "-".to_string(),
+            Token::Increment => "++".to_string(),
+            Token::Decrement => "--".to_string(),
+            Token::Asterisk => "*".to_string(),
+            Token::Slash => "/".to_string(),
+            Token::Percent => "%".to_string(),
+            Token::Assign => "=".to_string(),
+            Token::PlusAssign => "+=".to_string(),
+            Token::MinusAssign => "-=".to_string(),
+            Token::AsteriskAssign => "*=".to_string(),
+            Token::SlashAssign => "/=".to_string(),
+            Token::PercentAssign => "%=".to_string(),
+            Token::Equal => "==".to_string(),
+            Token::NotEqual => "!=".to_string(),
+            Token::LessThan => "<".to_string(),
+            Token::GreaterThan => ">".to_string(),
+            Token::LessEqual => "<=".to_string(),
+            Token::GreaterEqual => ">=".to_string(),
+            Token::And => "&&".to_string(),
+            Token::Or => "||".to_string(),
+            Token::Ampersand => "&".to_string(),
+            Token::Pipe => "|".to_string(),
+            Token::Caret => "^".to_string(),
+            Token::Tilde => "~".to_string(),
+            Token::ShiftLeft => "<<".to_string(),
+            Token::ShiftRight => ">>".to_string(),
+            Token::LParen => "(".to_string(),
+            Token::RParen => ")".to_string(),
+            Token::LBrace => "{".to_string(),
+            Token::RBrace => "}".to_string(),
+            Token::LBracket => "[".to_string(),
+            Token::RBracket => "]".to_string(),
+            Token::Semicolon => ";".to_string(),
+            Token::Colon => ":".to_string(),
+            Token::Question => "?".to_string(),
+            Token::Comma => ",".to_string(),
+            Token::Eof => "end of file".to_string(),
+            Token::Illegal(text) | Token::Malformed(text) => text.clone(),
+        }
+    }
+}
+
+// How a token is named in error messages: punctuation and keywords quoted as
+// written, identifiers and literals with their kind in front
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Identifier(name) => write!(f, "identifier '{}'", name),
+            Token::Integer(val) => write!(f, "integer '{}'", val),
+            Token::Float(val) => write!(f, "float '{:?}'", val),
+            Token::String(val) => write!(f, "string {:?}", val),
+            Token::Character(val) => write!(f, "character {:?}", val),
+            Token::Eof => write!(f, "end of file"),
+            Token::Malformed(problem) => write!(f, "{}", problem),
+            other => write!(f, "'{}'", other.literal()),
+        }
+    }
+}
+
+// Position of the first character of a token, both 1-based
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
 }
 
 pub struct Lexer<'a> {
     #[allow(dead_code)]
     input: &'a str,
     chars: std::iter::Peekable<std::str::Chars<'a>>,
+    line: usize,
+    column: usize,
+    symbols: HashSet<Symbol>, // Every identifier seen, so repeats share one allocation
 }
 
 impl<'a> Lexer<'a> {
@@ -49,17 +233,38 @@ impl<'a> Lexer<'a> {
         Lexer {
             input,
             chars: input.chars().peekable(),
+            line: 1,
+            column: 1,
+            symbols: HashSet::new(),
+        }
+    }
+
+    fn intern(&mut self, name: String) -> Symbol {
+        if let Some(symbol) = self.symbols.get(name.as_str()) {
+            return Rc::clone(symbol);
         }
+        let symbol: Symbol = name.into();
+        self.symbols.insert(Rc::clone(&symbol));
+        symbol
     }
 
     pub fn next_token(&mut self) -> Token {
+        self.next_token_spanned().0
+    }
+
+    pub fn next_token_spanned(&mut self) -> (Token, Span) {
         self.skip_whitespace();
 
-        match self.chars.next() {
+        let span = Span {
+            line: self.line,
+            column: self.column,
+        };
+
+        let token = match self.advance() {
             Some(c) => match c {
                 '=' => {
                     if let Some(&'=') = self.chars.peek() {
-                        self.chars.next();
+                        self.advance();
                         Token::Equal
                     } else {
                         Token::Assign
@@ -71,28 +276,28 @@ impl<'a> Lexer<'a> {
                         if next_c == '"' {
                             break;
                         }
-                        let c = self.chars.next().unwrap();
+                        let c = self.advance().unwrap();
                         if c == '\\' {
                             if let Some(&next_next) = self.chars.peek() {
                                 match next_next {
                                     'n' => {
-                                        self.chars.next();
+                                        self.advance();
                                         str_val.push('\n');
                                     }
                                     'r' => {
-                                        self.chars.next();
+                                        self.advance();
                                         str_val.push('\r');
                                     }
                                     't' => {
-                                        self.chars.next();
+                                        self.advance();
                                         str_val.push('\t');
                                     }
                                     '"' => {
-                                        self.chars.next();
+                                        self.advance();
                                         str_val.push('"');
                                     }
                                     '\\' => {
-                                        self.chars.next();
+                                        self.advance();
                                         str_val.push('\\');
                                     }
                                     _ => str_val.push('\\'), // Keep backslash if unknown escape
@@ -105,23 +310,48 @@ impl<'a> Lexer<'a> {
                         }
                     }
                     if let Some(&'"') = self.chars.peek() {
-                        self.chars.next(); // Consume closing quote
+                        self.advance(); // Consume closing quote
                         Token::String(str_val)
                     } else {
-                        Token::Illegal("Unterminated string".to_string())
+                        Token::Malformed("unterminated string".to_string())
                     }
                 }
+                '\'' => self.read_char_literal(),
                 '!' => {
                     if let Some(&'=') = self.chars.peek() {
-                        self.chars.next();
+                        self.advance();
                         Token::NotEqual
                     } else {
-                        Token::Illegal(c.to_string()) // For now we don't support just '!'
+                        Token::Bang
+                    }
+                }
+                '&' => {
+                    if let Some(&'&') = self.chars.peek() {
+                        self.advance();
+                        Token::And
+                    } else {
+                        Token::Ampersand
+                    }
+                }
+                '|' => {
+                    if let Some(&'|') = self.chars.peek() {
+                        self.advance();
+                        Token::Or
+                    } else {
+                        Token::Pipe
                     }
                 }
-                '+' => Token::Plus,
-                '-' => Token::Minus,
-                '*' => Token::Asterisk,
+                '+' if self.chars.peek() == Some(&'+') => {
+                    self.advance();
+                    Token::Increment
+                }
+                '-' if self.chars.peek() == Some(&'-') => {
+                    self.advance();
+                    Token::Decrement
+                }
+                '+' => self.with_assign(Token::Plus, Token::PlusAssign),
+                '-' => self.with_assign(Token::Minus, Token::MinusAssign),
+                '*' => self.with_assign(Token::Asterisk, Token::AsteriskAssign),
                 '/' => {
                     if let Some(&'/') = self.chars.peek() {
                         // It's a comment! Skip until newline
@@ -129,60 +359,224 @@ impl<'a> Lexer<'a> {
                             if c == '\n' {
                                 break;
                             }
-                            self.chars.next();
+                            self.advance();
+                        }
+                        return self.next_token_spanned(); // Recursively call next_token to get the actual next token
+                    } else if let Some(&'*') = self.chars.peek() {
+                        // Block comment, ended by the first `*/` (no nesting, as in C)
+                        self.advance();
+                        let mut prev = None;
+                        loop {
+                            match self.advance() {
+                                Some('/') if prev == Some('*') => break,
+                                Some(c) => prev = Some(c),
+                                None => {
+                                    return (
+                                        Token::Malformed("unterminated block comment".to_string()),
+                                        span,
+                                    );
+                                }
+                            }
                         }
-                        self.next_token() // Recursively call next_token to get the actual next token
+                        return self.next_token_spanned();
+                    } else {
+                        self.with_assign(Token::Slash, Token::SlashAssign)
+                    }
+                }
+                '%' => self.with_assign(Token::Percent, Token::PercentAssign),
+                '^' => Token::Caret,
+                '~' => Token::Tilde,
+                '<' if self.chars.peek() == Some(&'<') => {
+                    self.advance();
+                    Token::ShiftLeft
+                }
+                '>' if self.chars.peek() == Some(&'>') => {
+                    self.advance();
+                    Token::ShiftRight
+                }
+                '<' => {
+                    if let Some(&'=') = self.chars.peek() {
+                        self.advance();
+                        Token::LessEqual
+                    } else {
+                        Token::LessThan
+                    }
+                }
+                '>' => {
+                    if let Some(&'=') = self.chars.peek() {
+                        self.advance();
+                        Token::GreaterEqual
                     } else {
-                        Token::Slash
+                        Token::GreaterThan
                     }
                 }
-                '<' => Token::LessThan,
-                '>' => Token::GreaterThan,
                 '(' => Token::LParen,
                 ')' => Token::RParen,
                 '{' => Token::LBrace,
                 '}' => Token::RBrace,
+                '[' => Token::LBracket,
+                ']' => Token::RBracket,
                 ';' => Token::Semicolon,
+                ':' => Token::Colon,
+                '?' => Token::Question,
                 ',' => Token::Comma,
                 _ if c.is_ascii_digit() => {
+                    // Take the whole alphanumeric run so `0xG1` is one bad literal, not `0` and `xG1`
                     let mut num_str = c.to_string();
                     while let Some(&next_c) = self.chars.peek() {
-                        if next_c.is_ascii_digit() {
-                            num_str.push(self.chars.next().unwrap());
+                        let decimal = !is_prefixed_literal(&num_str);
+                        // A fraction needs a digit after the point, so `1.` stays an integer
+                        let fraction = next_c == '.'
+                            && decimal
+                            && !num_str.contains(['.', 'e', 'E'])
+                            && self
+                                .chars
+                                .clone()
+                                .nth(1)
+                                .is_some_and(|d| d.is_ascii_digit());
+                        let exponent_sign =
+                            matches!(next_c, '+' | '-') && decimal && num_str.ends_with(['e', 'E']);
+                        if next_c.is_ascii_alphanumeric()
+                            || next_c == '_'
+                            || fraction
+                            || exponent_sign
+                        {
+                            num_str.push(self.advance().unwrap());
                         } else {
                             break;
                         }
                     }
-                    Token::Integer(num_str.parse().unwrap())
+                    if !is_prefixed_literal(&num_str) && num_str.contains(['.', 'e', 'E']) {
+                        parse_float_literal(&num_str)
+                    } else {
+                        parse_integer_literal(&num_str)
+                    }
                 }
                 _ if c.is_ascii_alphabetic() || c == '_' => {
                     let mut ident = c.to_string();
                     while let Some(&next_c) = self.chars.peek() {
                         if next_c.is_ascii_alphanumeric() || next_c == '_' {
-                            ident.push(self.chars.next().unwrap());
+                            ident.push(self.advance().unwrap());
                         } else {
                             break;
                         }
                     }
                     match ident.as_str() {
                         "int" => Token::Int,
+                        "char" => Token::Char,
+                        "void" => Token::Void,
                         "return" => Token::Return,
                         "if" => Token::If,
                         "else" => Token::Else,
                         "while" => Token::While,
-                        _ => Token::Identifier(ident),
+                        "do" => Token::Do,
+                        "switch" => Token::Switch,
+                        "case" => Token::Case,
+                        "default" => Token::Default,
+                        "break" => Token::Break,
+                        "for" => Token::For,
+                        "const" => Token::Const,
+                        "true" => Token::True,
+                        "false" => Token::False,
+                        _ => Token::Identifier(self.intern(ident)),
+                    }
+                }
+                '#' => {
+                    let mut directive = c.to_string();
+                    while let Some(&next_c) = self.chars.peek() {
+                        if next_c.is_ascii_alphabetic() {
+                            directive.push(self.advance().unwrap());
+                        } else {
+                            break;
+                        }
+                    }
+                    match directive.as_str() {
+                        "#include" => Token::Include,
+                        "#define" => Token::Define,
+                        "#undef" => Token::Undef,
+                        _ => Token::Illegal(directive),
                     }
                 }
                 _ => Token::Illegal(c.to_string()),
             },
-            None => Token::EOF,
+            None => Token::Eof,
+        };
+
+        (token, span)
+    }
+
+    // Lexes an operator that becomes a compound assignment when followed by `=`
+    fn with_assign(&mut self, plain: Token, compound: Token) -> Token {
+        if let Some(&'=') = self.chars.peek() {
+            self.advance();
+            compound
+        } else {
+            plain
+        }
+    }
+
+    // Reads the rest of a char literal after its opening quote
+    fn read_char_literal(&mut self) -> Token {
+        let mut text = String::new(); // as written, for error messages
+        let mut chars = vec![];
+
+        loop {
+            match self.chars.peek() {
+                None | Some('\n') => {
+                    return Token::Malformed(format!("unterminated char literal '{}", text));
+                }
+                Some('\'') => {
+                    self.advance();
+                    break;
+                }
+                _ => {}
+            }
+
+            let c = self.advance().unwrap();
+            text.push(c);
+            if c != '\\' {
+                chars.push(c);
+                continue;
+            }
+
+            let escaped = match self.chars.peek() {
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some('t') => '\t',
+                Some('0') => '\0',
+                Some('\\') => '\\',
+                Some('\'') => '\'',
+                Some('"') => '"',
+                _ => {
+                    chars.push('\\'); // Keep backslash if unknown escape
+                    continue;
+                }
+            };
+            text.push(self.advance().unwrap());
+            chars.push(escaped);
+        }
+
+        match chars.as_slice() {
+            [c] => Token::Character(*c),
+            _ => Token::Malformed(format!("invalid char literal '{}'", text)),
         }
     }
 
+    fn advance(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
     fn skip_whitespace(&mut self) {
         while let Some(&c) = self.chars.peek() {
             if c.is_whitespace() {
-                self.chars.next();
+                self.advance();
             } else {
                 break;
             }
@@ -190,6 +584,57 @@ impl<'a> Lexer<'a> {
     }
 }
 
+// Whether a number starts with a `0x` or `0b` radix prefix
+fn is_prefixed_literal(text: &str) -> bool {
+    matches!(text.get(..2), Some("0x" | "0X" | "0b" | "0B"))
+}
+
+// Parses a decimal literal with a fraction and/or exponent, like `3.14` or
+// `1e-3`. Malformed or infinite values come back as Illegal with their text
+fn parse_float_literal(text: &str) -> Token {
+    let well_formed = !text.ends_with('_') && !text.contains("_.") && !text.contains("._");
+    match text.replace('_', "").parse::<f64>() {
+        Ok(n) if well_formed && n.is_finite() => Token::Float(n),
+        _ => Token::Illegal(text.to_string()),
+    }
+}
+
+// Parses a decimal, `0x` hex or `0b` binary literal, allowing `_` between digits.
+// Anything malformed comes back as Illegal with its text, and a literal too
+// large for an i64 or with a leading zero as Malformed with an explanation.
+fn parse_integer_literal(text: &str) -> Token {
+    let (digits, radix) = match text.get(..2) {
+        Some("0x" | "0X") => (&text[2..], 16),
+        Some("0b" | "0B") => (&text[2..], 2),
+        _ => (text, 10),
+    };
+
+    let digits = digits.replace('_', "");
+    let well_formed =
+        !digits.is_empty() && !text.ends_with('_') && digits.chars().all(|c| c.is_digit(radix));
+    if !well_formed {
+        return Token::Illegal(text.to_string());
+    }
+    // C would read `010` as octal 8, so rather than quietly meaning 10 it's an error
+    if radix == 10 && digits.len() > 1 && digits.starts_with('0') {
+        return Token::Malformed(format!("integer literal with a leading zero: {}", text));
+    }
+    match i64::from_str_radix(&digits, radix) {
+        Ok(n) => Token::Integer(n),
+        Err(_) => out_of_range(text),
+    }
+}
+
+fn out_of_range(text: &str) -> Token {
+    Token::Malformed(format!("integer literal out of range: {}", text))
+}
+
+// Whether `token` is the literal 9223372036854775808, out of range on its own
+// but i64::MIN straight after a minus sign
+pub fn is_i64_min_magnitude(token: &Token) -> bool {
+    *token == out_of_range(&i64::MIN.unsigned_abs().to_string())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -201,22 +646,314 @@ mod tests {
 
         let tests = vec![
             Token::Int,
-            Token::Identifier("x".to_string()),
+            Token::Identifier("x".into()),
             Token::Assign,
             Token::Integer(5),
             Token::Semicolon,
             Token::If,
             Token::LParen,
-            Token::Identifier("x".to_string()),
+            Token::Identifier("x".into()),
             Token::GreaterThan,
             Token::Integer(10),
             Token::RParen,
             Token::LBrace,
             Token::Return,
-            Token::Identifier("x".to_string()),
+            Token::Identifier("x".into()),
             Token::Semicolon,
             Token::RBrace,
-            Token::EOF,
+            Token::Eof,
+        ];
+
+        for expected in tests {
+            let tok = lexer.next_token();
+            assert_eq!(tok, expected);
+        }
+    }
+
+    #[test]
+    fn test_comparison_tokens() {
+        let input = "a <= b >= c < -1";
+        let mut lexer = Lexer::new(input);
+
+        let tests = vec![
+            Token::Identifier("a".into()),
+            Token::LessEqual,
+            Token::Identifier("b".into()),
+            Token::GreaterEqual,
+            Token::Identifier("c".into()),
+            Token::LessThan,
+            Token::Minus,
+            Token::Integer(1),
+            Token::Eof,
+        ];
+
+        for expected in tests {
+            let tok = lexer.next_token();
+            assert_eq!(tok, expected);
+        }
+    }
+
+    #[test]
+    fn test_token_spans() {
+        let input = "int x = 5;\n// comment\n  puts(x);";
+        let mut lexer = Lexer::new(input);
+
+        let tests = vec![
+            (Token::Int, 1, 1),
+            (Token::Identifier("x".into()), 1, 5),
+            (Token::Assign, 1, 7),
+            (Token::Integer(5), 1, 9),
+            (Token::Semicolon, 1, 10),
+            (Token::Identifier("puts".into()), 3, 3),
+            (Token::LParen, 3, 7),
+            (Token::Identifier("x".into()), 3, 8),
+            (Token::RParen, 3, 9),
+            (Token::Semicolon, 3, 10),
+            (Token::Eof, 3, 11),
+        ];
+
+        for (expected, line, column) in tests {
+            let (tok, span) = lexer.next_token_spanned();
+            assert_eq!(tok, expected);
+            assert_eq!(span, Span { line, column });
+        }
+    }
+
+    #[test]
+    fn test_char_literals() {
+        let input = r"'a' '\n' '\t' '\\' '\'' '\0' 'ab' '' 'x";
+        let mut lexer = Lexer::new(input);
+
+        let tests = vec![
+            Token::Character('a'),
+            Token::Character('\n'),
+            Token::Character('\t'),
+            Token::Character('\\'),
+            Token::Character('\''),
+            Token::Character('\0'),
+            Token::Malformed("invalid char literal 'ab'".to_string()),
+            Token::Malformed("invalid char literal ''".to_string()),
+            Token::Malformed("unterminated char literal 'x".to_string()),
+            Token::Eof,
+        ];
+
+        for expected in tests {
+            let tok = lexer.next_token();
+            assert_eq!(tok, expected);
+        }
+    }
+
+    #[test]
+    fn test_integer_literal_radixes() {
+        let input = "0xFF 0X1f 0b1010 1_000_000 0x_ff 0x 0xG1 0b102 12ab 99999999999999999999 9223372036854775807 9223372036854775808 0x8000000000000000 0 007 0_1 0x07";
+        let mut lexer = Lexer::new(input);
+
+        let tests = vec![
+            Token::Integer(255),
+            Token::Integer(31),
+            Token::Integer(10),
+            Token::Integer(1_000_000),
+            Token::Integer(255),
+            Token::Illegal("0x".to_string()),
+            Token::Illegal("0xG1".to_string()),
+            Token::Illegal("0b102".to_string()),
+            Token::Illegal("12ab".to_string()),
+            Token::Malformed("integer literal out of range: 99999999999999999999".to_string()),
+            Token::Integer(i64::MAX),
+            // i64::MIN's magnitude; the parser reads it after a minus sign
+            Token::Malformed("integer literal out of range: 9223372036854775808".to_string()),
+            Token::Malformed("integer literal out of range: 0x8000000000000000".to_string()),
+            Token::Integer(0),
+            Token::Malformed("integer literal with a leading zero: 007".to_string()),
+            Token::Malformed("integer literal with a leading zero: 0_1".to_string()),
+            Token::Integer(7),
+            Token::Eof,
+        ];
+
+        for expected in tests {
+            let tok = lexer.next_token();
+            assert_eq!(tok, expected);
+        }
+    }
+
+    #[test]
+    fn test_float_literals() {
+        let input = "2.75 1e-3 2.5E+2 1_000.5 0.5e3 7. 0x1e-2 1e 1.5x 1e999";
+        let mut lexer = Lexer::new(input);
+
+        let tests = vec![
+            Token::Float(2.75),
+            Token::Float(0.001),
+            Token::Float(250.0),
+            Token::Float(1000.5),
+            Token::Float(500.0),
+            // No digit after the point, so this is 7 followed by a stray `.`
+            Token::Integer(7),
+            Token::Illegal(".".to_string()),
+            // Hex literals have no exponent: this is 0x1e minus 2
+            Token::Integer(0x1e),
+            Token::Minus,
+            Token::Integer(2),
+            Token::Illegal("1e".to_string()),
+            Token::Illegal("1.5x".to_string()),
+            Token::Illegal("1e999".to_string()),
+            Token::Eof,
+        ];
+
+        for expected in tests {
+            let tok = lexer.next_token();
+            assert_eq!(tok, expected);
+        }
+    }
+
+    #[test]
+    fn test_block_comments() {
+        let input = "/* header\n comment */ x /**/ y /* a /* b */ z */";
+        let mut lexer = Lexer::new(input);
+
+        // Block comments don't nest: the first `*/` ends the comment, leaving `z */`
+        let tests = vec![
+            Token::Identifier("x".into()),
+            Token::Identifier("y".into()),
+            Token::Identifier("z".into()),
+            Token::Asterisk,
+            Token::Slash,
+            Token::Eof,
+        ];
+
+        for expected in tests {
+            let tok = lexer.next_token();
+            assert_eq!(tok, expected);
+        }
+    }
+
+    #[test]
+    fn test_unterminated_block_comment() {
+        let mut lexer = Lexer::new("x /* never closed");
+        assert_eq!(lexer.next_token(), Token::Identifier("x".into()));
+        assert_eq!(
+            lexer.next_token(),
+            Token::Malformed("unterminated block comment".to_string())
+        );
+    }
+
+    #[test]
+    fn test_compound_assignment_tokens() {
+        let input = "+= -= *= /= %= + /";
+        let mut lexer = Lexer::new(input);
+
+        let tests = vec![
+            Token::PlusAssign,
+            Token::MinusAssign,
+            Token::AsteriskAssign,
+            Token::SlashAssign,
+            Token::PercentAssign,
+            Token::Plus,
+            Token::Slash,
+            Token::Eof,
+        ];
+
+        for expected in tests {
+            let tok = lexer.next_token();
+            assert_eq!(tok, expected);
+        }
+    }
+
+    #[test]
+    fn test_boolean_literals() {
+        let mut lexer = Lexer::new("true false truth False");
+        for expected in [
+            Token::True,
+            Token::False,
+            Token::Identifier("truth".into()),
+            Token::Identifier("False".into()),
+            Token::Eof,
+        ] {
+            assert_eq!(lexer.next_token(), expected);
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        let tests = vec![
+            (Token::RParen, "')'"),
+            (Token::Equal, "'=='"),
+            (Token::Int, "'int'"),
+            (Token::Define, "'#define'"),
+            (Token::Identifier("foo".into()), "identifier 'foo'"),
+            (Token::Integer(42), "integer '42'"),
+            (Token::Float(1.5), "float '1.5'"),
+            (Token::String("a\"b".to_string()), "string \"a\\\"b\""),
+            (Token::Character('x'), "character 'x'"),
+            (Token::Eof, "end of file"),
+        ];
+
+        for (token, expected) in tests {
+            assert_eq!(token.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_increment_decrement_tokens() {
+        let input = "i++ --n a+-b";
+        let mut lexer = Lexer::new(input);
+
+        let tests = vec![
+            Token::Identifier("i".into()),
+            Token::Increment,
+            Token::Decrement,
+            Token::Identifier("n".into()),
+            Token::Identifier("a".into()),
+            Token::Plus,
+            Token::Minus,
+            Token::Identifier("b".into()),
+            Token::Eof,
+        ];
+
+        for expected in tests {
+            let tok = lexer.next_token();
+            assert_eq!(tok, expected);
+        }
+    }
+
+    #[test]
+    fn test_bitwise_tokens() {
+        let input = "& && | || ^ ~ << <= >> >=";
+        let mut lexer = Lexer::new(input);
+
+        let tests = vec![
+            Token::Ampersand,
+            Token::And,
+            Token::Pipe,
+            Token::Or,
+            Token::Caret,
+            Token::Tilde,
+            Token::ShiftLeft,
+            Token::LessEqual,
+            Token::ShiftRight,
+            Token::GreaterEqual,
+            Token::Eof,
+        ];
+
+        for expected in tests {
+            let tok = lexer.next_token();
+            assert_eq!(tok, expected);
+        }
+    }
+
+    #[test]
+    fn test_directive_tokens() {
+        let input = "#include \"lib.c\" #define #undef #if #";
+        let mut lexer = Lexer::new(input);
+
+        let tests = vec![
+            Token::Include,
+            Token::String("lib.c".to_string()),
+            Token::Define,
+            Token::Undef,
+            Token::Illegal("#if".to_string()),
+            Token::Illegal("#".to_string()),
+            Token::Eof,
         ];
 
         for expected in tests {
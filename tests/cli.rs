@@ -0,0 +1,118 @@
+// Runs the tcc binary to check what goes to stdout and stderr, and the exit
+// status, for programs that succeed and fail in different ways
+
+use std::process::Command;
+
+// The exit status, stdout and stderr of running `program` given with -e
+fn run(program: &str) -> (i32, String, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_tcc"))
+        .args(["-e", program])
+        .output()
+        .expect("failed to run tcc");
+    (
+        output.status.code().expect("tcc was killed by a signal"),
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+#[test]
+fn test_result_goes_to_stdout() {
+    assert_eq!(
+        run("puts(\"hi\"); 6 * 7;"),
+        (0, "hi\nInterpreter Result: 42\n".to_string(), String::new())
+    );
+}
+
+#[test]
+fn test_runtime_error_goes_to_stderr() {
+    let (code, stdout, stderr) = run("puts(\"before\");\nint x = 1 / 0;\nputs(\"after\");");
+    assert_eq!(code, 70);
+    assert_eq!(stdout, "before\n");
+    assert_eq!(stderr, "runtime error at line 2: division by zero\n");
+
+    // The same from inside main
+    let (code, stdout, stderr) = run("int main() {\n    return strlen(5);\n}");
+    assert_eq!(code, 70);
+    assert_eq!(stdout, "");
+    assert_eq!(
+        stderr,
+        "runtime error at line 2: strlen: argument 1 must be a string, got 5\n  in main (line 1)\n"
+    );
+}
+
+#[test]
+fn test_runtime_error_in_included_file() {
+    let dir = std::env::temp_dir().join(format!("tinyc-cli-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("lib.c"),
+        "int ratio(int n) {\n    return n / 0;\n}",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("main.c"),
+        "#include \"lib.c\"\nint main() {\n    return ratio(1);\n}",
+    )
+    .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_tcc"))
+        .arg(dir.join("main.c"))
+        .output()
+        .expect("failed to run tcc");
+    let lib = dir.join("lib.c").display().to_string();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(output.status.code(), Some(70));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!(
+            "runtime error at {}:2: division by zero\n  in ratio ({}:1)\n  in main (line 2)\n",
+            lib, lib
+        )
+    );
+}
+
+#[test]
+fn test_temp_files_removed_on_exit() {
+    // A reference cycle keeps the file open until the process ends
+    for program in [
+        "int a = [tmpfile()]; push(a, a);",
+        "int a = [tmpfile()]; push(a, a); exit(2);",
+        "int a = [tmpfile()]; push(a, a); 1 / 0;",
+    ] {
+        let child = Command::new(env!("CARGO_BIN_EXE_tcc"))
+            .args(["-e", program])
+            .stderr(std::process::Stdio::null())
+            .spawn()
+            .expect("failed to run tcc");
+        let prefix = format!("tinyc-tmp-{}-", child.id());
+        child.wait_with_output().unwrap();
+
+        let left: Vec<_> = std::fs::read_dir(std::env::temp_dir())
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+            .collect();
+        assert!(left.is_empty(), "{}: {:?}", program, left);
+    }
+}
+
+#[test]
+fn test_parse_errors_go_to_stderr() {
+    let (code, stdout, stderr) = run("puts(\"never\");\nint = 3;");
+    assert_eq!(code, 1);
+    assert_eq!(stdout, "");
+    assert_eq!(
+        stderr,
+        "<cmdline>:2:5: expected an identifier after int but found '='\n"
+    );
+}
+
+#[test]
+fn test_exit_status() {
+    assert_eq!(
+        run("puts(\"bye\"); exit(3);"),
+        (3, "bye\n".to_string(), String::new())
+    );
+    assert_eq!(run("int main() { return 256 + 4; }").0, 4);
+}
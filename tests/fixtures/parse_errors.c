@@ -0,0 +1,14 @@
+int main() {
+    int count = 0;
+    int = 5;
+    while (count < 3) {
+        count = count + 1;
+    }
+    if (count > 2 {
+        puts("big");
+        return 1;
+    }
+    int total = count * 2;
+    printf("%d" count);
+    return 0;
+}
@@ -0,0 +1,100 @@
+// Runs every program in tests/programs and compares what it gives with the
+// .out file next to it. The expected text is what the program prints, then,
+// as the command line would report them, any parse errors or runtime error
+// report, then `exit status N` when the status isn't 0.
+//
+// Each program gets an empty scratch directory as ARGV[1] for any files it
+// writes, removed again once it has run.
+
+use std::cell::RefCell;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+use tcc::{Interpreter, Object, RuntimeError, load_program};
+
+// A runtime error ends the program with EX_SOFTWARE, as in the binary
+const RUNTIME_ERROR_EXIT_CODE: i64 = 70;
+
+fn programs_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/programs")
+}
+
+// Mirrors how the binary runs a file: the top level, then main if it has one
+fn run(path: &Path, scratch: &Path) -> String {
+    let name = path.file_name().unwrap().to_string_lossy().to_string();
+    let input = fs::read_to_string(path).unwrap();
+    let (program, errors) = load_program(&input, Some(&name), path.parent().unwrap());
+
+    let buffer = Rc::new(RefCell::new(Vec::new()));
+    let mut report = String::new();
+    let code = if !errors.is_empty() {
+        for err in errors {
+            report.push_str(&format!("{}\n", err));
+        }
+        1
+    } else {
+        let mut interpreter = Interpreter::with_output(buffer.clone());
+        interpreter.set_args(vec![name, scratch.to_string_lossy().to_string()]);
+        let env = interpreter.global_environment();
+        let result = match interpreter.eval_program(&program, Rc::clone(&env)) {
+            Ok(Object::Integer(n)) if interpreter.program_returned() => Ok(n),
+            Ok(_) if interpreter.program_returned() => Ok(0),
+            Ok(_) => match interpreter.call_main(Rc::clone(&env)) {
+                Some(Ok(Object::Integer(n))) => Ok(n),
+                Some(Ok(_)) | None => Ok(0),
+                Some(Err(err)) => Err(err),
+            },
+            Err(err) => Err(err),
+        };
+        let code = match result {
+            Ok(n) | Err(RuntimeError::Exit(n)) => n,
+            Err(err) => {
+                report.push_str(&format!("{}\n", interpreter.error_report(&err)));
+                RUNTIME_ERROR_EXIT_CODE
+            }
+        };
+        // Open files are flushed and closed before the output is read
+        drop(env);
+        code.rem_euclid(256)
+    };
+
+    let mut actual = String::from_utf8(buffer.borrow().clone()).unwrap();
+    actual.push_str(&report);
+    if code != 0 {
+        actual.push_str(&format!("exit status {}\n", code));
+    }
+    actual
+}
+
+#[test]
+fn test_programs() {
+    let mut paths: Vec<PathBuf> = fs::read_dir(programs_dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "tc"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no programs in {:?}", programs_dir());
+
+    let mut failures = vec![];
+    for path in &paths {
+        let stem = path.file_stem().unwrap().to_string_lossy();
+        let scratch =
+            std::env::temp_dir().join(format!("tinyc-programs-{}-{}", process::id(), stem));
+        fs::create_dir_all(&scratch).unwrap();
+        let actual = run(path, &scratch);
+        fs::remove_dir_all(&scratch).unwrap();
+
+        let expected = fs::read_to_string(path.with_extension("out")).unwrap_or_default();
+        if actual != expected {
+            failures.push(format!(
+                "{}\n--- expected\n{}--- actual\n{}",
+                path.display(),
+                expected,
+                actual
+            ));
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}